@@ -1,12 +1,130 @@
-use std::io::{self, Write};
+//! GitHub's alerts (`> [!NOTE]`), rendered as Confluence's info, tip, note and warning macros and
+//! panels, and `> [!NOTE][expand] Title` as an expand.
+//!
+//! Spaces can map their own alert types to macros or panels, and restyle GitHub's, in
+//! marked-space.yml:
+//!
+//! ```yaml
+//! alerts:
+//!   decision:
+//!     emoji: thinking
+//!     color: "#eae6ff"
+//!   success:
+//!     panel: success
+//! ```
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use comrak::{
+    nodes::{AlertType, AstNode, NodeValue},
+    Arena,
+};
+use once_cell::sync::Lazy;
+use quick_xml::escape::escape;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    error::{ConfluenceError, Result},
+    helpers::collect_text,
+};
+
+static ALERT_MARKER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\[!([A-Za-z][A-Za-z0-9_-]*)\]\s*").unwrap());
+
+/// How an alert type is rendered, from `alerts` in marked-space.yml.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AlertSettings {
+    /// A Confluence macro with a body, ie info, tip, note, warning or expand, rather than a panel.
+    #[serde(rename = "macro")]
+    pub macro_name: Option<String>,
+    /// The type of panel: info, note, success, warning, error or custom, which is the default.
+    pub panel: Option<String>,
+    /// The shortcode of the emoji on a custom panel, ie thinking.
+    pub emoji: Option<String>,
+    /// The background colour of a custom panel, ie #eae6ff.
+    pub color: Option<String>,
+    /// The title of alerts that don't have their own, instead of the alert type.
+    pub title: Option<String>,
+}
+
+impl AlertSettings {
+    fn write_enter(&self, output: &mut dyn Write, title: &str) -> Result<()> {
+        match self.macro_name {
+            Some(ref macro_name) => {
+                write!(
+                    output,
+                    "<ac:structured-macro ac:name=\"{}\">",
+                    escape(macro_name)
+                )?;
+                if macro_name == "expand" {
+                    write!(
+                        output,
+                        "<ac:parameter ac:name=\"title\">{}</ac:parameter>",
+                        escape(title)
+                    )?;
+                }
+                output.write_all(b"<ac:rich-text-body>")?;
+            }
+            None => {
+                let panel_type = self.panel.as_deref().unwrap_or("custom");
+                output.write_all(b"<ac:adf-extension><ac:adf-node type=\"panel\">")?;
+                write_adf_attribute(output, "panel-type", panel_type)?;
+                if let Some(ref shortcode) = self.emoji {
+                    let emoji = emojis::get_by_shortcode(shortcode).ok_or_else(|| {
+                        ConfluenceError::generic_error(format!(
+                            "Unknown short code '{}' for the emoji of an alert",
+                            shortcode
+                        ))
+                    })?;
+                    let id: Vec<String> = emoji
+                        .as_str()
+                        .chars()
+                        .filter(|c| *c != '\u{fe0f}')
+                        .map(|c| format!("{:x}", c as u32))
+                        .collect();
+                    write_adf_attribute(output, "panel-icon", &format!(":{}:", shortcode))?;
+                    write_adf_attribute(output, "panel-icon-id", &id.join("-"))?;
+                    write_adf_attribute(output, "panel-icon-text", emoji.as_str())?;
+                }
+                if let Some(ref color) = self.color {
+                    write_adf_attribute(output, "panel-color", color)?;
+                }
+                output.write_all(b"<ac:adf-content>")?;
+            }
+        }
+        if self.macro_name.as_deref() != Some("expand") {
+            write!(output, "\n<p><strong>{}</strong></p>", escape(title))?;
+        }
+        Ok(())
+    }
 
-use comrak::nodes::AlertType;
+    fn write_exit(&self, output: &mut dyn Write) -> io::Result<()> {
+        match self.macro_name {
+            Some(_) => output.write_all(b"</ac:rich-text-body></ac:structured-macro>"),
+            None => output.write_all(b"</ac:adf-content></ac:adf-node></ac:adf-extension>"),
+        }
+    }
+}
+
+fn write_adf_attribute(output: &mut dyn Write, key: &str, value: &str) -> io::Result<()> {
+    write!(
+        output,
+        "<ac:adf-attribute key=\"{}\">{}</ac:adf-attribute>",
+        key,
+        escape(value)
+    )
+}
 
 pub(crate) fn render_expand(
     output: &mut impl Write,
     title: &str,
     entering: bool,
-) -> Result<(), io::Error> {
+) -> io::Result<()> {
     let actual_title = title.strip_prefix("[expand]").unwrap().trim();
     if entering {
         output.write_all(b"<ac:structured-macro ac:name=\"expand\">")?;
@@ -51,7 +169,7 @@ fn alert_to_panel_type(alert_type: &AlertType, entering: bool) -> String {
         }
     } else {
         match alert_type {
-            AlertType::Important => "</ac:adf-extension>".into(),
+            AlertType::Important => "</ac:adf-content></ac:adf-node></ac:adf-extension>".into(),
             _ => "</ac:rich-text-body></ac:structured-macro>".into(),
         }
     }
@@ -61,7 +179,7 @@ pub(crate) fn render_basic_alert(
     output: &mut impl Write,
     node_alert: &comrak::nodes::NodeAlert,
     entering: bool,
-) -> Result<(), io::Error> {
+) -> io::Result<()> {
     if entering {
         output.write_all(alert_to_panel_type(&node_alert.alert_type, entering).as_bytes())?;
         output.write_all(b"\n<p><strong>")?;
@@ -79,11 +197,120 @@ pub(crate) fn render_basic_alert(
     Ok(())
 }
 
+/// Renders the alerts with settings in marked-space.yml: GitHub's alerts of those types, and
+/// block quotes starting with `[!TYPE]`, which comrak doesn't know are alerts.
+pub(crate) fn render_alerts<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    root: &'a AstNode<'a>,
+    settings: &HashMap<String, AlertSettings>,
+) -> Result<()> {
+    let settings: HashMap<String, &AlertSettings> = settings
+        .iter()
+        .map(|(alert_type, settings)| (alert_type.to_lowercase(), settings))
+        .collect();
+    let alerts: Vec<(&'a AstNode<'a>, String, Option<String>)> = root
+        .descendants()
+        .filter_map(|node| match node.data.borrow().value {
+            NodeValue::Alert(ref node_alert) => match node_alert.title {
+                Some(ref title) if title.starts_with("[expand]") => None,
+                _ => Some((
+                    node,
+                    node_alert.alert_type.default_title().to_lowercase(),
+                    node_alert.title.clone(),
+                )),
+            },
+            NodeValue::BlockQuote => {
+                alert_marker(node).map(|alert_type| (node, alert_type.to_lowercase(), None))
+            }
+            _ => None,
+        })
+        .filter(|(_, alert_type, _)| settings.contains_key(alert_type))
+        .collect();
+
+    for (node, alert_type, title) in alerts {
+        let alert_settings = settings[&alert_type];
+        let title = match title {
+            Some(title) => title,
+            None => take_marker_title(node).unwrap_or_else(|| {
+                alert_settings.title.clone().unwrap_or_else(|| {
+                    let mut title = alert_type.clone();
+                    title[..1].make_ascii_uppercase();
+                    title
+                })
+            }),
+        };
+
+        let mut enter = Vec::default();
+        alert_settings.write_enter(&mut enter, &title)?;
+        let mut exit = Vec::default();
+        alert_settings.write_exit(&mut exit)?;
+
+        node.insert_before(arena.alloc(NodeValue::Raw(String::from_utf8(enter)?).into()));
+        let children: Vec<&'a AstNode<'a>> = node.children().collect();
+        for child in children {
+            node.insert_before(child);
+        }
+        node.insert_before(arena.alloc(NodeValue::Raw(String::from_utf8(exit)?).into()));
+        node.detach();
+    }
+    Ok(())
+}
+
+/// The type of the alert a block quote is, from the `[!TYPE]` it starts with.
+fn alert_marker<'a>(block_quote: &'a AstNode<'a>) -> Option<String> {
+    let text = block_quote.first_child()?.first_child()?;
+    match text.data.borrow().value {
+        NodeValue::Text(ref text) => ALERT_MARKER
+            .captures(text)
+            .map(|captures| String::from(&captures[1])),
+        _ => None,
+    }
+}
+
+/// Removes the `[!TYPE]` line from the block quote, returning the title after it, if any.
+fn take_marker_title<'a>(block_quote: &'a AstNode<'a>) -> Option<String> {
+    let paragraph = block_quote.first_child()?;
+    let first = paragraph.first_child()?;
+    if let NodeValue::Text(ref mut text) = first.data.borrow_mut().value {
+        let marker_len = ALERT_MARKER.find(text)?.end();
+        text.replace_range(..marker_len, "");
+    }
+
+    let mut title = Vec::default();
+    let mut next = Some(first);
+    while let Some(node) = next {
+        next = node.next_sibling();
+        let end_of_line = matches!(
+            node.data.borrow().value,
+            NodeValue::SoftBreak | NodeValue::LineBreak
+        );
+        collect_text(node, &mut title);
+        node.detach();
+        if end_of_line {
+            break;
+        }
+    }
+    if paragraph.first_child().is_none() {
+        paragraph.detach();
+    }
+
+    let title = String::from_utf8_lossy(&title).trim().to_owned();
+    (!title.is_empty()).then_some(title)
+}
+
 #[cfg(test)]
 mod test {
     use comrak::{nodes::AstNode, Arena};
 
-    use crate::{error::TestResult, link_generator::LinkGenerator, markdown_page::page_from_str};
+    use crate::{
+        error::TestResult,
+        link_generator::LinkGenerator,
+        markdown_page::page_from_str,
+        test_helpers::{markdown_page_from_str, test_render},
+        xhtml::check_storage,
+    };
+
+    use super::*;
 
     #[test]
     fn it_renders_note() -> TestResult {
@@ -186,4 +413,100 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn it_renders_nested_expands() -> TestResult {
+        let rendered = test_render(
+            "# Page\n> [!note][expand] Outer\n> one\n>\n> > [!tip][expand] Inner\n> > two\n",
+        )?;
+
+        assert_eq!(
+            rendered.content.trim(),
+            r#"<ac:structured-macro ac:name="expand"><ac:parameter ac:name="title">Outer</ac:parameter><ac:rich-text-body>
+<p>one</p>
+<ac:structured-macro ac:name="expand"><ac:parameter ac:name="title">Inner</ac:parameter><ac:rich-text-body>
+<p>two</p>
+</ac:rich-text-body></ac:structured-macro></ac:rich-text-body></ac:structured-macro>"#
+        );
+        Ok(())
+    }
+
+    fn settings() -> HashMap<String, AlertSettings> {
+        HashMap::from([
+            (
+                String::from("decision"),
+                AlertSettings {
+                    emoji: Some(String::from("thinking")),
+                    color: Some(String::from("#eae6ff")),
+                    ..AlertSettings::default()
+                },
+            ),
+            (
+                String::from("SUCCESS"),
+                AlertSettings {
+                    panel: Some(String::from("success")),
+                    title: Some(String::from("Done")),
+                    ..AlertSettings::default()
+                },
+            ),
+            (
+                String::from("note"),
+                AlertSettings {
+                    macro_name: Some(String::from("expand")),
+                    ..AlertSettings::default()
+                },
+            ),
+        ])
+    }
+
+    #[test]
+    fn it_renders_configured_alert_types() -> TestResult {
+        let arena = Arena::<AstNode>::new();
+        let mut page = markdown_page_from_str(
+            "page.md",
+            "# Page\n> [!DECISION] Use *Rust*\n> Because.\n\n> [!success]\n> Shipped\n\n> [!OTHER]\n> Quoted\n",
+            &arena,
+        )?;
+
+        page.render_alerts(&arena, &settings())?;
+        let rendered = page.render(&LinkGenerator::default_test())?;
+
+        assert_eq!(
+            rendered.content.trim(),
+            r#"<ac:adf-extension><ac:adf-node type="panel"><ac:adf-attribute key="panel-type">custom</ac:adf-attribute><ac:adf-attribute key="panel-icon">:thinking:</ac:adf-attribute><ac:adf-attribute key="panel-icon-id">1f914</ac:adf-attribute><ac:adf-attribute key="panel-icon-text">🤔</ac:adf-attribute><ac:adf-attribute key="panel-color">#eae6ff</ac:adf-attribute><ac:adf-content>
+<p><strong>Use Rust</strong></p>
+<p>Because.</p>
+</ac:adf-content></ac:adf-node></ac:adf-extension><ac:adf-extension><ac:adf-node type="panel"><ac:adf-attribute key="panel-type">success</ac:adf-attribute><ac:adf-content>
+<p><strong>Done</strong></p>
+<p>Shipped</p>
+</ac:adf-content></ac:adf-node></ac:adf-extension>
+<blockquote>
+<p>[!OTHER] Quoted</p>
+</blockquote>"#
+        );
+        assert!(check_storage(&rendered.content).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn it_restyles_github_alerts() -> TestResult {
+        let arena = Arena::<AstNode>::new();
+        let mut page = markdown_page_from_str(
+            "page.md",
+            "# Page\n> [!NOTE] Details\n> Hidden\n\n> [!TIP]\n> Unchanged\n",
+            &arena,
+        )?;
+
+        page.render_alerts(&arena, &settings())?;
+        let rendered = page.render(&LinkGenerator::default_test())?;
+
+        assert!(rendered.content.starts_with(
+            r#"<ac:structured-macro ac:name="expand"><ac:parameter ac:name="title">Details</ac:parameter><ac:rich-text-body>
+<p>Hidden</p>"#
+        ), "{}", rendered.content);
+        assert!(rendered
+            .content
+            .contains(r#"<ac:structured-macro ac:name="tip""#));
+        Ok(())
+    }
 }
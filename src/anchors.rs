@@ -0,0 +1,107 @@
+//! Anchors for links to headings, ie `other.md#setup-steps`.
+//!
+//! Confluence makes its own anchors for headings, from the page title and the heading without any
+//! spaces (`OtherPage-SetupSteps`), so an anchor written the GitHub way, or as the heading's
+//! text, is matched to a heading of the page and rewritten into Confluence's format.
+
+/// The anchor GitHub gives a heading: lowercase, with spaces as hyphens and punctuation removed.
+pub(crate) fn heading_slug(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The anchor Confluence gives a heading on a page.
+pub(crate) fn confluence_anchor(title: &str, heading: &str) -> String {
+    let without_spaces = |text: &str| text.split_whitespace().collect::<String>();
+    format!("{}-{}", without_spaces(title), without_spaces(heading))
+}
+
+/// The heading of the page the anchor is for, if there is one.
+pub(crate) fn find_heading<'h>(
+    title: &str,
+    headings: &'h [String],
+    anchor: &str,
+) -> Option<&'h str> {
+    let slug = heading_slug(anchor);
+    headings
+        .iter()
+        .find(|heading| {
+            heading_slug(heading) == slug
+                || heading.as_str() == anchor
+                || confluence_anchor(title, heading) == anchor
+        })
+        .map(|heading| heading.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::{nodes::AstNode, Arena};
+
+    use crate::{
+        error::TestResult,
+        link_generator::LinkGenerator,
+        test_helpers::{markdown_page_from_str, register_mark_and_conf_page},
+    };
+
+    use super::*;
+
+    #[test]
+    fn it_makes_confluence_anchors() {
+        assert_eq!(
+            confluence_anchor("Getting Started", "Setup  steps (optional)"),
+            "GettingStarted-Setupsteps(optional)"
+        );
+    }
+
+    #[test]
+    fn it_finds_headings_however_the_anchor_is_written() {
+        let headings = vec![String::from("Setup Steps"), String::from("What's new?")];
+        let find = |anchor| find_heading("Getting Started", &headings, anchor);
+
+        assert_eq!(find("setup-steps"), Some("Setup Steps"));
+        assert_eq!(find("Setup-Steps"), Some("Setup Steps"));
+        assert_eq!(find("GettingStarted-SetupSteps"), Some("Setup Steps"));
+        assert_eq!(find("whats-new"), Some("What's new?"));
+        assert_eq!(find("teardown"), None);
+    }
+
+    #[test]
+    fn it_links_to_the_confluence_anchor_of_the_heading() -> TestResult {
+        let arena = Arena::<AstNode>::new();
+        let mut link_generator = LinkGenerator::default_test();
+        register_mark_and_conf_page(
+            "1",
+            &mut link_generator,
+            markdown_page_from_str("other.md", "# Other Page\n## Setup Steps\n", &arena)?,
+        )?;
+        let page = register_mark_and_conf_page(
+            "2",
+            &mut link_generator,
+            markdown_page_from_str(
+                "page.md",
+                "# Page\n## Notes\n[setup](other.md#setup-steps), [[Other Page#Setup Steps]] and [notes](#notes)\n",
+                &arena,
+            )?,
+        )?;
+
+        let rendered = page.render(&link_generator)?;
+
+        assert!(rendered.content.contains(
+            r#"<a href="https://example.atlassian.net/wiki/spaces/TEST/pages/1#OtherPage-SetupSteps">setup</a>"#
+        ), "{}", rendered.content);
+        assert!(rendered
+            .content
+            .contains(r#"pages/1#OtherPage-SetupSteps">Other Page#Setup Steps</a>"#));
+        assert!(rendered
+            .content
+            .contains(r#"pages/2#Page-Notes">notes</a>"#));
+        Ok(())
+    }
+}
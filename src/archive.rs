@@ -1,13 +1,28 @@
 use std::path::Path;
 
+use clap::ValueEnum;
+use serde::Deserialize;
+
 use crate::{
     confluence_client::ConfluenceClient,
     confluence_page::{ConfluenceNode, ConfluenceNodeType},
     console::{print_status, Status},
     link_generator::LinkGenerator,
+    report,
     responses::ContentStatus,
 };
 
+/// What to do with pages whose markdown has been deleted.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OrphanPolicy {
+    /// Archive them, so that they can be restored along with the markdown.
+    #[default]
+    Archive,
+    /// Leave them where they are, with a warning.
+    Keep,
+}
+
 pub(crate) fn should_archive(node: &ConfluenceNode, link_generator: &LinkGenerator) -> bool {
     match &node.data {
         ConfluenceNodeType::Page(p) => {
@@ -30,22 +45,59 @@ pub(crate) fn should_unarchive(node: &ConfluenceNode, link_generator: &LinkGener
     }
 }
 
+/// Describes why an orphaned node is being archived, or None if it doesn't warrant a mention
+/// (ie, the source file still exists).
+pub(crate) fn archive_description(node: &ConfluenceNode, space_dir: &Path) -> Option<String> {
+    match &node.data {
+        ConfluenceNodeType::Page(p) => {
+            if let Some(path) = &p.path {
+                if !space_dir.join(path).exists() {
+                    Some(format!(
+                        "orphaned \"{}\" from {} (deleted)",
+                        node.title,
+                        space_dir.join(path).display()
+                    ))
+                } else {
+                    None
+                }
+            } else {
+                Some(format!(
+                    "orphaned page \"{}\" (probably created outside of markedspace)",
+                    node.title
+                ))
+            }
+        }
+        ConfluenceNodeType::Folder(_confluence_folder) => None,
+    }
+}
+
+pub(crate) fn unarchive_description(node: &ConfluenceNode) -> String {
+    let path = node
+        .page_data()
+        .and_then(|p| p.path.clone())
+        .unwrap_or_default();
+    format!("restored \"{}\" from {}", node.title, path.display())
+}
+
+/// Attributes anything reported from here on to the file the node was synced from, if known.
+pub(crate) fn start_node_report(node: &ConfluenceNode) {
+    if let Some(path) = node.page_data().and_then(|p| p.path.as_ref()) {
+        let source = path.display().to_string();
+        report::start_page(&source, &node.title);
+    }
+}
+
 pub(crate) fn unarchive(
     node: &ConfluenceNode,
     confluence_client: &ConfluenceClient,
 ) -> anyhow::Result<()> {
     match &node.data {
-        crate::confluence_page::ConfluenceNodeType::Page(p) => {
-            let path = p.path.clone();
-            print_status(
-                Status::Unarchived,
-                &format!(
-                    "restored \"{}\" from {}",
-                    node.title,
-                    path.unwrap_or_default().display()
-                ),
-            );
-            node.unarchive(confluence_client)
+        crate::confluence_page::ConfluenceNodeType::Page(_p) => {
+            start_node_report(node);
+            print_status(Status::Unarchived, &unarchive_description(node));
+            let result = node.unarchive(confluence_client);
+            report::end_page();
+            result
         }
         crate::confluence_page::ConfluenceNodeType::Folder(_confluence_folder) => todo!(),
     }
@@ -57,29 +109,14 @@ pub(crate) fn archive(
     confluence_client: &ConfluenceClient,
 ) -> anyhow::Result<()> {
     match &node.data {
-        crate::confluence_page::ConfluenceNodeType::Page(p) => {
-            if let Some(path) = &p.path {
-                if !space_dir.join(path).exists() {
-                    print_status(
-                        Status::Archived,
-                        &format!(
-                            "orphaned \"{}\" from {} (deleted)",
-                            node.title,
-                            space_dir.join(path).display()
-                        ),
-                    );
-                }
-            } else {
-                print_status(
-                    Status::Archived,
-                    &format!(
-                        "orphaned page \"{}\" (probably created outside of markedspace)",
-                        node.title
-                    ),
-                );
+        crate::confluence_page::ConfluenceNodeType::Page(_p) => {
+            start_node_report(node);
+            if let Some(description) = archive_description(node, space_dir) {
+                print_status(Status::Archived, &description);
             }
-
-            node.archive(confluence_client)
+            let result = node.archive(confluence_client);
+            report::end_page();
+            result
         }
         crate::confluence_page::ConfluenceNodeType::Folder(_confluence_folder) => todo!(),
     }
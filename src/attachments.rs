@@ -1,7 +1,7 @@
 use crate::{
     checksum::sha256_digest,
     confluence_paginator::ConfluencePaginator,
-    console::{print_error, Status},
+    console::{print_error, print_planned, Status},
     error::Result,
     local_link::LocalLink,
     responses,
@@ -20,10 +20,12 @@ use regex::Regex;
 use crate::{
     confluence_client::ConfluenceClient,
     confluence_storage_renderer::{escape_href, WriteWithLast},
-    link_generator::LinkGenerator,
     responses::MultiEntityResult,
 };
 
+/// The file ids of a page's attachments, by attachment name, for its cover.
+pub type AttachmentIds = HashMap<String, String>;
+
 #[derive(Debug, PartialEq)]
 pub enum AttachmentKind {
     File,
@@ -85,42 +87,104 @@ pub fn render_link_leave(_nl: &NodeLink, output: &mut WriteWithLast) -> io::Resu
     Ok(())
 }
 
-pub fn sync_page_attachments(
+/// The attachments already on a page, keyed by title.
+struct ExistingAttachments {
+    hashes: HashMap<String, String>,
+    ids: HashMap<String, String>,
+    file_ids: HashMap<String, String>,
+}
+
+impl ExistingAttachments {
+    fn get(confluence_client: &ConfluenceClient, page_id: &str) -> Result<Self> {
+        let existing_attachments: MultiEntityResult<responses::Attachment> = confluence_client
+            .get_attachments(page_id)?
+            .error_for_status()?
+            .json()?;
+
+        let mut hashes = HashMap::<String, String>::new();
+        let mut ids = HashMap::<String, String>::new();
+        let mut file_ids = HashMap::<String, String>::new();
+        for existing_attachment in existing_attachments.results.iter() {
+            if let Some(hash) = existing_attachment.comment.strip_prefix("hash:") {
+                hashes.insert(existing_attachment.title.clone(), hash.into());
+            }
+            ids.insert(
+                existing_attachment.title.clone(),
+                existing_attachment.id.clone(),
+            );
+            file_ids.insert(
+                existing_attachment.title.clone(),
+                existing_attachment.file_id.clone(),
+            );
+        }
+
+        Ok(Self {
+            hashes,
+            ids,
+            file_ids,
+        })
+    }
+
+    fn is_up_to_date(&self, attachment_name: &str, hashstring: &str) -> bool {
+        self.hashes
+            .get(attachment_name)
+            .is_some_and(|hash| hash == hashstring)
+    }
+}
+
+pub(crate) fn attachment_hash(attachment: &Attachment) -> Result<String> {
+    let input = File::open(&attachment.link.target).with_context(|| {
+        format!(
+            "Opening attachment for {}",
+            attachment.link.attachment_name()
+        )
+    })?;
+    sha256_digest(BufReader::new(input))
+}
+
+/// Reports the attachment changes sync_page_attachments would make, without making them.
+pub fn plan_page_attachments(
     confluence_client: &ConfluenceClient,
     page_id: &str,
-    page_source: &str,
     attachments: &[Attachment],
-    link_generator: &mut LinkGenerator,
-) -> Result<()> {
-    let existing_attachments: MultiEntityResult<responses::Attachment> = confluence_client
-        .get_attachments(page_id)?
-        .error_for_status()?
-        .json()?;
-
-    let mut hashes = HashMap::<String, String>::new();
-    let mut remove_titles_to_id = HashMap::<String, String>::new();
-    let mut title_to_fileid = HashMap::<String, String>::new();
-    for existing_attachment in existing_attachments.results.iter() {
-        if existing_attachment.comment.starts_with("hash:") {
-            hashes.insert(
-                existing_attachment.title.clone(),
-                existing_attachment
-                    .comment
-                    .strip_prefix("hash:")
-                    .unwrap()
-                    .into(),
-            );
+) -> Result<AttachmentIds> {
+    let existing = ExistingAttachments::get(confluence_client, page_id)?;
+    let mut remove_titles = existing.ids.clone();
+    let mut attachment_ids = AttachmentIds::default();
+
+    for attachment in attachments.iter() {
+        let attachment_name = attachment.link.attachment_name();
+        remove_titles.remove(&attachment_name);
+
+        let desc = format!("[{}] attachment", attachment.link.target.display());
+        let hashstring = attachment_hash(attachment)?;
+        if existing.is_up_to_date(&attachment_name, &hashstring) {
+            let id = existing.file_ids[&attachment_name].clone();
+            attachment_ids.insert(attachment_name, id);
+        } else {
+            // there's no id until it's uploaded, but covers still need something to refer to
+            attachment_ids.insert(attachment_name, String::default());
+            print_planned(Status::Updated, &desc);
         }
-        remove_titles_to_id.insert(
-            existing_attachment.title.clone(),
-            existing_attachment.id.clone(),
-        );
-        title_to_fileid.insert(
-            existing_attachment.title.clone(),
-            existing_attachment.file_id.clone(),
-        );
     }
 
+    for title in remove_titles.keys() {
+        print_planned(Status::Deleted, &format!("[{}] attachment", title));
+    }
+
+    Ok(attachment_ids)
+}
+
+/// Uploads the page's attachments, returning their ids.
+pub fn sync_page_attachments(
+    confluence_client: &ConfluenceClient,
+    page_id: &str,
+    attachments: &[Attachment],
+) -> Result<AttachmentIds> {
+    let existing = ExistingAttachments::get(confluence_client, page_id)?;
+    let mut remove_titles_to_id = existing.ids.clone();
+    let mut attachment_ids = AttachmentIds::default();
+
     for attachment in attachments.iter() {
         let attachment_name = attachment.link.attachment_name();
 
@@ -130,22 +194,15 @@ pub fn sync_page_attachments(
             format!("[{}] attachment", attachment.link.target.display()),
             true,
         );
-        let input = File::open(&attachment.link.target)
-            .with_context(|| format!("Opening attachment for {}", attachment_name))?;
-        let reader = BufReader::new(input);
-        let hashstring = sha256_digest(reader)?;
-        if hashes.contains_key(&attachment_name)
-            && hashstring == *hashes.get(&attachment_name).unwrap()
-        {
+        let hashstring = attachment_hash(attachment)?;
+        if existing.is_up_to_date(&attachment_name, &hashstring) {
             // still add the existing attachment to lookup for covers
-            let id = title_to_fileid[&attachment_name].clone();
-            link_generator.register_attachment_id(
-                page_source,
-                &attachment.link.attachment_name(),
-                &id,
+            attachment_ids.insert(
+                attachment_name.clone(),
+                existing.file_ids[&attachment_name].clone(),
             );
             op.end(Status::Skipped);
-            return Ok(());
+            continue;
         }
 
         let response = confluence_client.create_or_update_attachment(
@@ -174,11 +231,7 @@ pub fn sync_page_attachments(
             assert_eq!(results[0].title, attachment_name);
             let id = results[0].extensions["fileId"].as_str().unwrap();
             // add new attachment to lookup
-            link_generator.register_attachment_id(
-                page_source,
-                &attachment.link.attachment_name(),
-                id,
-            );
+            attachment_ids.insert(attachment_name, String::from(id));
         }
 
         op.end(Status::Updated);
@@ -198,7 +251,7 @@ pub fn sync_page_attachments(
         })
         .collect();
 
-    Ok(())
+    Ok(attachment_ids)
 }
 
 #[cfg(test)]
@@ -1,8 +1,26 @@
 use std::collections::HashMap;
 
-use crate::error::Result;
+use crate::{error::Result, xhtml::mark_storage};
+use quick_xml::escape::escape;
 use tera::Tera;
 
+/// A function whose output is trusted, as it's from marked-space rather than the page, so the
+/// storage markup in it is written as it is.
+pub(crate) struct StorageFunction<F>(pub F);
+
+impl<F: tera::Function> tera::Function for StorageFunction<F> {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        Ok(match self.0.call(args)? {
+            tera::Value::String(output) => tera::Value::String(mark_storage(&output)),
+            output => output,
+        })
+    }
+
+    fn is_safe(&self) -> bool {
+        self.0.is_safe()
+    }
+}
+
 fn hello_world(
     _args: &HashMap<String, serde_json::Value>,
 ) -> std::result::Result<serde_json::Value, tera::Error> {
@@ -71,8 +89,57 @@ format!(r#"<ac:structured-macro ac:name="contentbylabel" ac:schema-version="4" d
     )
 }
 
+/// A table of the Jira issues a JQL query finds, with the Jira issues macro.
+fn jql_table(
+    args: &HashMap<String, serde_json::Value>,
+) -> std::result::Result<serde_json::Value, tera::Error> {
+    let jql = args
+        .get("jql")
+        .and_then(|jql| jql.as_str())
+        .ok_or("Missing required argument 'jql'")?;
+
+    let columns = match args.get("columns") {
+        None => String::from("key,summary,type,status,assignee,updated"),
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(serde_json::Value::Array(a)) if !a.is_empty() => a
+            .iter()
+            .map(|column| column.as_str().ok_or("columns need to be strings"))
+            .collect::<std::result::Result<Vec<&str>, _>>()?
+            .join(","),
+        _ => Err(tera::Error::msg(
+            "columns needs to be a string or non-empty array",
+        ))?,
+    };
+    let maximum = match args.get("maximum") {
+        None => 20,
+        Some(maximum) => maximum.as_u64().ok_or("maximum needs to be a number")?,
+    };
+
+    let mut parameters = format!(
+        r#"<ac:parameter ac:name="jqlQuery">{}</ac:parameter><ac:parameter ac:name="columns">{}</ac:parameter><ac:parameter ac:name="maximumIssues">{}</ac:parameter>"#,
+        escape(jql),
+        escape(&columns),
+        maximum
+    );
+    for (arg, parameter) in [("server", "server"), ("server_id", "serverId")] {
+        if let Some(value) = args.get(arg).and_then(|value| value.as_str()) {
+            parameters.push_str(&format!(
+                r#"<ac:parameter ac:name="{}">{}</ac:parameter>"#,
+                parameter,
+                escape(value)
+            ));
+        }
+    }
+    Ok(serde_json::to_value(format!(
+        r#"<ac:structured-macro ac:name="jira" ac:schema-version="1">{}</ac:structured-macro>"#,
+        parameters
+    ))
+    .unwrap())
+}
+
 const PROPERTIES_TABLE: &str = r###"{% macro properties(metadata) -%}
 <ac:structured-macro ac:name="details" ac:schema-version="1" data-layout="default" ac:local-id="779bc5f9-b8c3-41df-bccc-1840efc20a80" ac:macro-id="4008e080-6218-49a8-82f8-1387005d53d2"><ac:rich-text-body >
+{#- on one line, so the table isn't an HTML block that splits the paragraph the macro is in -#}
 <table><tbody>
 {%- for metadata_key in metadata -%}
 <tr><th>{{ metadata_key|title }}</th><td>{{ metadata(path=metadata_key) }}</td></tr>
@@ -90,12 +157,15 @@ const PROPERTIES_TABLE: &str = r###"{% macro properties(metadata) -%}
 {%- endmacro %}
 "###;
 
+/// Builtins that depend on the page being rendered (ie, `snippet`) are registered by
+/// TemplateRenderer::render_template_str instead.
 pub(crate) fn add_builtins(tera: &mut Tera) -> Result<()> {
     tera.register_function("hello_world", hello_world);
-    tera.register_function("toc", toc);
-    tera.register_function("children", children);
-    tera.register_function("labellist", labellist);
-    tera.add_raw_template("_tera/builtins", PROPERTIES_TABLE)?;
+    tera.register_function("toc", StorageFunction(toc));
+    tera.register_function("children", StorageFunction(children));
+    tera.register_function("labellist", StorageFunction(labellist));
+    tera.register_function("jql_table", StorageFunction(jql_table));
+    tera.add_raw_template("_tera/builtins", &mark_storage(PROPERTIES_TABLE))?;
 
     Ok(())
 }
@@ -108,8 +178,11 @@ mod test {
     use scraper::{Html, Selector};
 
     use crate::{
-        builtins::labellist, error::TestResult, link_generator::LinkGenerator,
-        markdown_page::page_from_str, test_helpers::test_render,
+        builtins::{jql_table, labellist},
+        error::TestResult,
+        link_generator::LinkGenerator,
+        markdown_page::page_from_str,
+        test_helpers::test_render,
     };
 
     fn extract_properties_table(parsed_html: Html) -> Vec<(String, String)> {
@@ -180,6 +253,25 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn it_renders_jql_tables() -> TestResult {
+        let arena = Arena::<AstNode>::new();
+        let markdown_content = r#"# compulsory title
+{{ jql_table(jql='project = ABC AND status != "Done"', columns=["key", "summary"], maximum=5) }}"#;
+
+        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let rendered_page = page.render(&LinkGenerator::default_test())?;
+
+        assert_eq!(
+            rendered_page.content.trim(),
+            r#"<p><ac:structured-macro ac:name="jira" ac:schema-version="1"><ac:parameter ac:name="jqlQuery">project = ABC AND status != "Done"</ac:parameter><ac:parameter ac:name="columns">key,summary</ac:parameter><ac:parameter ac:name="maximumIssues">5</ac:parameter></ac:structured-macro></p>"#
+        );
+
+        let args = HashMap::from([("columns".to_string(), serde_json::Value::from("key"))]);
+        assert!(jql_table(&args).is_err());
+        Ok(())
+    }
+
     #[test]
     fn properties_report_defaults_to_current_space() -> TestResult {
         let rendered_page =
@@ -0,0 +1,361 @@
+//! The info string of a fenced code block, ie ```` ```rust title="main.rs" linenumbers ````, and
+//! how it maps to the parameters of Confluence's code macro.
+
+use std::{
+    fmt::{self, Display},
+    io::{self, Write},
+};
+
+use quick_xml::escape::escape;
+
+/// The languages Confluence's code macro can highlight.
+const CONFLUENCE_LANGUAGES: [&str; 77] = [
+    "abap",
+    "actionscript",
+    "ada",
+    "applescript",
+    "arduino",
+    "autoit",
+    "bash",
+    "c",
+    "c#",
+    "clojure",
+    "coffeescript",
+    "coldfusion",
+    "cpp",
+    "css",
+    "cuda",
+    "d",
+    "dart",
+    "diff",
+    "elixir",
+    "erlang",
+    "fortran",
+    "foxpro",
+    "go",
+    "graphql",
+    "groovy",
+    "haskell",
+    "haxe",
+    "html",
+    "java",
+    "javafx",
+    "javascript",
+    "json",
+    "jsx",
+    "julia",
+    "kotlin",
+    "livescript",
+    "lua",
+    "mathematica",
+    "matlab",
+    "objective-c",
+    "objective-j",
+    "ocaml",
+    "octave",
+    "pascal",
+    "perl",
+    "php",
+    "plaintext",
+    "powershell",
+    "prolog",
+    "puppet",
+    "python",
+    "qml",
+    "r",
+    "racket",
+    "restructuredtext",
+    "ruby",
+    "rust",
+    "sass",
+    "scala",
+    "scheme",
+    "shell",
+    "smalltalk",
+    "splunk-spl",
+    "sql",
+    "standardml",
+    "swift",
+    "tcl",
+    "tex",
+    "typescript",
+    "vala",
+    "vbnet",
+    "verilog",
+    "vhdl",
+    "visualbasic",
+    "xml",
+    "xquery",
+    "yaml",
+];
+
+/// Common names for languages that Confluence knows by another name.
+const LANGUAGE_ALIASES: [(&str, &str); 26] = [
+    ("rs", "rust"),
+    ("sh", "bash"),
+    ("zsh", "bash"),
+    ("console", "bash"),
+    ("js", "javascript"),
+    ("mjs", "javascript"),
+    ("ts", "typescript"),
+    ("tsx", "typescript"),
+    ("py", "python"),
+    ("rb", "ruby"),
+    ("yml", "yaml"),
+    ("kt", "kotlin"),
+    ("cs", "c#"),
+    ("csharp", "c#"),
+    ("c++", "cpp"),
+    ("golang", "go"),
+    ("ps1", "powershell"),
+    ("pwsh", "powershell"),
+    ("objc", "objective-c"),
+    ("text", "plaintext"),
+    ("txt", "plaintext"),
+    ("plain", "plaintext"),
+    ("htm", "html"),
+    ("xhtml", "xml"),
+    ("svg", "xml"),
+    ("patch", "diff"),
+];
+
+/// Languages that marked-space handles itself (ie, diagrams), so aren't worth a warning.
+const MARKED_SPACE_LANGUAGES: [&str; 4] = ["mermaid", "plantuml", "puml", "math"];
+
+/// The themes of Confluence's code macro.
+const THEMES: [&str; 8] = [
+    "Confluence",
+    "Default",
+    "Django",
+    "Eclipse",
+    "Emacs",
+    "FadeToGrey",
+    "Midnight",
+    "RDark",
+];
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct CodeBlockInfo {
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub line_numbers: bool,
+    pub first_line: Option<u32>,
+    pub theme: Option<String>,
+    pub collapse: bool,
+    /// Lines to highlight, ie `{1,3-5}` or `highlight="1,3-5"`.
+    pub highlight: Option<String>,
+    /// Anything in the info string that couldn't be used.
+    pub warnings: Vec<String>,
+}
+
+fn normalise_language(language: &str) -> Option<String> {
+    let language = language.to_lowercase();
+    LANGUAGE_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == language)
+        .map(|(_, canonical)| String::from(*canonical))
+        .or_else(|| {
+            CONFLUENCE_LANGUAGES
+                .contains(&language.as_str())
+                .then_some(language)
+        })
+}
+
+fn is_true(value: &str) -> bool {
+    matches!(value, "" | "true" | "yes" | "1")
+}
+
+impl CodeBlockInfo {
+    pub(crate) fn parse(info: &str) -> Self {
+        let mut result = Self::default();
+        let tokens = shlex::split(info).unwrap_or_else(|| {
+            result
+                .warnings
+                .push(format!("Unbalanced quotes in code block info: {}", info));
+            info.split_whitespace().map(String::from).collect()
+        });
+
+        let mut tokens = tokens.into_iter().peekable();
+        if let Some(language) = tokens.next_if(|t| !t.contains('=') && !t.starts_with('{')) {
+            result.language = match normalise_language(&language) {
+                Some(language) => Some(language),
+                None => {
+                    if !MARKED_SPACE_LANGUAGES.contains(&language.as_str()) {
+                        result.warnings.push(format!(
+                            "Code block language \"{}\" isn't supported by Confluence, so won't be highlighted",
+                            language
+                        ));
+                    }
+                    Some(language)
+                }
+            };
+        }
+
+        for token in tokens {
+            if let Some(lines) = token.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+                result.highlight = Some(String::from(lines));
+                continue;
+            }
+            let (key, value) = token.split_once('=').unwrap_or((&token, ""));
+            match key.to_lowercase().as_str() {
+                "title" => result.title = Some(String::from(value)),
+                "linenumbers" | "linenos" => result.line_numbers = is_true(value),
+                "firstline" => match value.parse::<u32>() {
+                    Ok(first_line) => {
+                        result.first_line = Some(first_line);
+                        result.line_numbers = true;
+                    }
+                    Err(_) => result
+                        .warnings
+                        .push(format!("Invalid firstline for code block: {}", value)),
+                },
+                "theme" => match THEMES.iter().find(|t| t.eq_ignore_ascii_case(value)) {
+                    Some(theme) => result.theme = Some(String::from(*theme)),
+                    None => result.warnings.push(format!(
+                        "Unknown code block theme \"{}\", expected one of {}",
+                        value,
+                        THEMES.join(", ")
+                    )),
+                },
+                "collapse" => result.collapse = is_true(value),
+                "highlight" | "hl_lines" => result.highlight = Some(String::from(value)),
+                _ => result
+                    .warnings
+                    .push(format!("Unknown code block attribute: {}", token)),
+            }
+        }
+
+        if result.highlight.is_some() {
+            result.warnings.push(String::from(
+                "Confluence's code macro can't highlight lines, so the highlighted lines are ignored",
+            ));
+        }
+        result
+    }
+
+    /// Writes the code macro's parameters.
+    pub(crate) fn write_parameters(&self, output: &mut impl Write) -> io::Result<()> {
+        let mut parameter = |name: &str, value: &str| {
+            write!(
+                output,
+                "<ac:parameter ac:name=\"{}\">{}</ac:parameter>",
+                name,
+                escape(value)
+            )
+        };
+        parameter("language", self.language.as_deref().unwrap_or_default())?;
+        if let Some(ref title) = self.title {
+            parameter("title", title)?;
+        }
+        if self.line_numbers {
+            parameter("linenumbers", "true")?;
+        }
+        if let Some(first_line) = self.first_line {
+            parameter("firstline", &first_line.to_string())?;
+        }
+        if let Some(ref theme) = self.theme {
+            parameter("theme", theme)?;
+        }
+        if self.collapse {
+            parameter("collapse", "true")?;
+        }
+        Ok(())
+    }
+}
+
+/// The info string, ie for pulled pages.
+impl Display for CodeBlockInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::<String>::default();
+        if let Some(ref language) = self.language {
+            parts.push(language.clone());
+        }
+        if let Some(ref title) = self.title {
+            parts.push(format!(
+                "title={}",
+                shlex::try_quote(title).map_err(|_| fmt::Error)?
+            ));
+        }
+        if let Some(first_line) = self.first_line {
+            parts.push(format!("firstline={}", first_line));
+        } else if self.line_numbers {
+            parts.push(String::from("linenumbers"));
+        }
+        if let Some(ref theme) = self.theme {
+            parts.push(format!("theme={}", theme));
+        }
+        if self.collapse {
+            parts.push(String::from("collapse"));
+        }
+        f.write_str(&parts.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::TestResult, test_helpers::test_render};
+
+    use super::*;
+
+    #[test]
+    fn it_parses_the_language_and_attributes() {
+        let info =
+            CodeBlockInfo::parse(r#"rs title="src/main.rs" firstline=10 theme=midnight collapse"#);
+
+        assert_eq!(
+            info,
+            CodeBlockInfo {
+                language: Some(String::from("rust")),
+                title: Some(String::from("src/main.rs")),
+                line_numbers: true,
+                first_line: Some(10),
+                theme: Some(String::from("Midnight")),
+                collapse: true,
+                highlight: None,
+                warnings: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn it_warns_about_what_it_cant_use() {
+        let info = CodeBlockInfo::parse("brainfuck {1,3-5} colour=red");
+
+        assert_eq!(info.language.as_deref(), Some("brainfuck"));
+        assert_eq!(info.highlight.as_deref(), Some("1,3-5"));
+        assert_eq!(info.warnings.len(), 3);
+        assert!(info.warnings[0].contains("\"brainfuck\" isn't supported"));
+        assert!(info.warnings[1].contains("colour=red"));
+        assert!(info.warnings[2].contains("can't highlight lines"));
+    }
+
+    #[test]
+    fn it_has_no_language_for_bare_attributes() {
+        let info = CodeBlockInfo::parse("title=notes.txt");
+
+        assert_eq!(info.language, None);
+        assert_eq!(info.title.as_deref(), Some("notes.txt"));
+        assert!(info.warnings.is_empty());
+    }
+
+    #[test]
+    fn it_renders_the_code_macro_parameters() -> TestResult {
+        let rendered =
+            test_render("# Page\n```sh title=\"Install & run\" linenumbers\nmake\n```\n")?;
+
+        assert!(rendered.content.contains(
+            "<ac:parameter ac:name=\"language\">bash</ac:parameter><ac:parameter ac:name=\"title\">Install &amp; run</ac:parameter><ac:parameter ac:name=\"linenumbers\">true</ac:parameter><ac:plain-text-body>"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_the_info_string() {
+        let info = CodeBlockInfo::parse(r#"rust title="main file.rs" linenumbers collapse"#);
+
+        assert_eq!(
+            info.to_string(),
+            "rust title='main file.rs' linenumbers collapse"
+        );
+    }
+}
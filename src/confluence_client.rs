@@ -7,11 +7,12 @@ use reqwest::Method;
 use serde_json::{json, Value};
 use std::env;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::console::{print_error, print_warning};
-use crate::retry::{classify_error, classify_status, retry_after, RetryConfig};
+use crate::retry::{classify_error, classify_status, retry_after, RetryConfig, RetryReason};
 
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
@@ -24,6 +25,9 @@ pub struct ConfluenceClient {
     pub hostname: String,
     insecure: bool,
     retry: RetryConfig,
+    /// When Confluence rate limits one request, it's rate limiting all of them, so every clone of
+    /// the client (ie, one per --jobs worker) waits until this has passed before sending.
+    rate_limited_until: Arc<Mutex<Option<Instant>>>,
 }
 
 pub type Result = anyhow::Result<reqwest::blocking::Response>;
@@ -46,6 +50,7 @@ impl ConfluenceClient {
             hostname: String::from(hostname),
             insecure: false,
             retry: RetryConfig::from_env(),
+            rate_limited_until: Arc::default(),
         }
     }
 
@@ -63,6 +68,7 @@ impl ConfluenceClient {
                 initial_backoff: Duration::from_millis(1),
                 max_backoff: Duration::from_millis(5),
             },
+            rate_limited_until: Arc::default(),
         }
     }
 
@@ -131,6 +137,7 @@ impl ConfluenceClient {
     {
         let mut attempt: u32 = 0;
         loop {
+            self.wait_for_rate_limit();
             let request = build_request()?.build()?;
             let method = request.method().clone();
             let path = String::from(request.url().path());
@@ -173,7 +180,41 @@ impl ConfluenceClient {
                 self.retry.max_retries
             ));
 
-            sleep(delay);
+            if let RetryReason::RateLimited = reason {
+                self.rate_limited_for(delay);
+            } else {
+                sleep(delay);
+            }
+        }
+    }
+
+    fn rate_limited_for(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut rate_limited_until = self
+            .rate_limited_until
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if rate_limited_until.is_none_or(|current| current < until) {
+            *rate_limited_until = Some(until);
+        }
+    }
+
+    /// How long until requests can be sent again, after being rate limited.
+    fn rate_limit_remaining(&self) -> Duration {
+        self.rate_limited_until
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .unwrap_or_default()
+    }
+
+    fn wait_for_rate_limit(&self) {
+        loop {
+            let remaining = self.rate_limit_remaining();
+            if remaining.is_zero() {
+                break;
+            }
+            sleep(remaining);
         }
     }
 
@@ -209,6 +250,14 @@ impl ConfluenceClient {
         ))
     }
 
+    /// Current pages in the space matching the filter, ie `("id", page_id)` or `("title", title)`.
+    pub(crate) fn find_pages(&self, space_id: &str, filter: (&str, &str)) -> Result {
+        self.send(
+            self.request(Method::GET, self.rest_api_v2("pages"))
+                .query(&[("space-id", space_id), ("status", "current"), filter]),
+        )
+    }
+
     pub fn get_all_pages_from_homepage(&self, homepage_id: &str) -> Result {
         self.send(
             self.request(
@@ -239,6 +288,33 @@ impl ConfluenceClient {
         )
     }
 
+    pub(crate) fn get_page_with_body(&self, page_id: &str) -> Result {
+        self.send(
+            self.request(Method::GET, self.rest_api_v2(&format!("pages/{}", page_id)))
+                .query(&[("body-format", "storage")]),
+        )
+    }
+
+    pub(crate) fn get_page_version_with_body(&self, page_id: &str, version: i32) -> Result {
+        self.send(
+            self.request(Method::GET, self.rest_api_v2(&format!("pages/{}", page_id)))
+                .query(&[
+                    ("body-format", "storage"),
+                    ("version", &version.to_string()),
+                ]),
+        )
+    }
+
+    pub(crate) fn get_page_versions(&self, page_id: &str) -> Result {
+        self.send(
+            self.request(
+                Method::GET,
+                self.rest_api_v2(&format!("pages/{}/versions", page_id)),
+            )
+            .query(&[("sort", "-modified-date")]),
+        )
+    }
+
     pub fn update_page(&self, page_id: &String, payload: Value) -> Result {
         self.send(
             self.request(Method::PUT, self.rest_api_v2(&format!("pages/{}", page_id)))
@@ -279,6 +355,20 @@ impl ConfluenceClient {
         ))
     }
 
+    /// Download an attachment's content, using the download link from get_attachments (which is
+    /// relative to the wiki).
+    pub(crate) fn download_attachment(&self, download_link: &str) -> Result {
+        self.send(self.request(
+            Method::GET,
+            format!(
+                "{}://{}/wiki{}",
+                self.scheme(),
+                self.hostname,
+                download_link
+            ),
+        ))
+    }
+
     pub(crate) fn remove_attachment(&self, id: &str) -> Result {
         self.send(self.request(
             Method::DELETE,
@@ -517,6 +607,16 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn it_shares_rate_limiting_between_clones() {
+        let client = ConfluenceClient::new_insecure("localhost");
+        let worker = client.clone();
+
+        worker.rate_limited_for(Duration::from_secs(30));
+
+        assert!(client.rate_limit_remaining() > Duration::from_secs(20));
+    }
+
     #[test]
     fn it_gives_up_after_the_configured_number_of_retries() -> TestResult {
         let mut server = mockito::Server::new();
@@ -0,0 +1,184 @@
+//! Links to pages in other Confluence spaces, ie `<confluence://OPS/On Call Runbook#Paging>`, which
+//! are rendered as Confluence's own page links so they keep working if the page moves.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    io::{self, Write},
+};
+
+use percent_encoding::percent_decode_str;
+use quick_xml::escape::escape;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    error::{ConfluenceError, Result},
+    markdown_page::PageInfo,
+};
+
+const SCHEME: &str = "confluence://";
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ConfluenceLink {
+    pub space_key: String,
+    pub title: String,
+    pub anchor: Option<String>,
+}
+
+impl ConfluenceLink {
+    pub fn is_confluence_link(url: &str) -> bool {
+        url.starts_with(SCHEME)
+    }
+
+    pub fn from_url(url: &str) -> Result<Self> {
+        let invalid = || {
+            ConfluenceError::generic_error(format!(
+                "Invalid Confluence link {}, expected confluence://SPACE/Page Title",
+                url
+            ))
+        };
+        let link = url.strip_prefix(SCHEME).ok_or_else(invalid)?;
+        let link = percent_decode_str(link)
+            .decode_utf8()
+            .map_err(|_| invalid())?;
+        let (space_key, page) = link.split_once('/').ok_or_else(invalid)?;
+        let (title, anchor) = match page.split_once('#') {
+            Some((title, anchor)) => (title, Some(String::from(anchor))),
+            None => (page, None),
+        };
+        if space_key.is_empty() || title.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            space_key: String::from(space_key),
+            title: String::from(title),
+            anchor,
+        })
+    }
+
+    /// Opens the link, with the title as its text if the markdown link doesn't have any.
+    pub fn write_enter(&self, output: &mut dyn Write, no_children: bool) -> io::Result<()> {
+        output.write_all(b"<ac:link")?;
+        if let Some(ref anchor) = self.anchor {
+            write!(output, " ac:anchor=\"{}\"", escape(anchor))?;
+        }
+        write!(
+            output,
+            "><ri:page ri:space-key=\"{}\" ri:content-title=\"{}\"/><ac:link-body>",
+            escape(&self.space_key),
+            escape(&self.title)
+        )?;
+        if no_children {
+            output.write_all(escape(&self.title).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn write_exit(output: &mut dyn Write) -> io::Result<()> {
+        output.write_all(b"</ac:link-body></ac:link>")
+    }
+}
+
+impl Display for ConfluenceLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}/{}", SCHEME, self.space_key, self.title)
+    }
+}
+
+/// Checks that the pages in other spaces that the pages link to exist, for --check.
+pub(crate) fn check_confluence_links(
+    pages: &[&PageInfo],
+    confluence_client: &ConfluenceClient,
+) -> Result<()> {
+    let mut space_ids = HashMap::<String, Option<String>>::default();
+    let mut missing = Vec::<String>::default();
+    for page in pages {
+        for link in page.confluence_links.iter() {
+            if !space_ids.contains_key(&link.space_key) {
+                let json = confluence_client
+                    .get_space_by_key(&link.space_key)?
+                    .error_for_status()?
+                    .json::<serde_json::Value>()?;
+                let id = json["results"][0]["id"].as_str().map(String::from);
+                space_ids.insert(link.space_key.clone(), id);
+            }
+            let exists = match space_ids[&link.space_key] {
+                Some(ref space_id) => {
+                    let json = confluence_client
+                        .find_pages(space_id, ("title", &link.title))?
+                        .error_for_status()?
+                        .json::<serde_json::Value>()?;
+                    json["results"]
+                        .as_array()
+                        .is_some_and(|results| !results.is_empty())
+                }
+                None => false,
+            };
+            if !exists {
+                missing.push(format!("{} (in {})", link, page.source));
+            }
+        }
+    }
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfluenceError::generic_error(format!(
+            "Links to missing Confluence pages: {}",
+            missing.join(", ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::TestResult, test_helpers::test_render};
+
+    use super::*;
+
+    #[test]
+    fn it_parses_links_to_other_spaces() -> TestResult {
+        assert_eq!(
+            ConfluenceLink::from_url("confluence://OPS/On%20Call%20Runbook#Paging")?,
+            ConfluenceLink {
+                space_key: String::from("OPS"),
+                title: String::from("On Call Runbook"),
+                anchor: Some(String::from("Paging")),
+            }
+        );
+        assert!(ConfluenceLink::from_url("confluence://OPS").is_err());
+        assert!(ConfluenceLink::from_url("confluence:///Title").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_renders_links_to_other_spaces() -> TestResult {
+        let rendered = test_render(
+            "# Page\nSee [the runbook](<confluence://OPS/On Call & Runbook>) or <confluence://OPS/Home>.\n",
+        )?;
+
+        assert!(
+            rendered.content.contains(
+                r#"See <ac:link><ri:page ri:space-key="OPS" ri:content-title="On Call &amp; Runbook"/><ac:link-body>the runbook</ac:link-body></ac:link>"#
+            ),
+            "{}",
+            rendered.content
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_uses_link_aliases_from_the_front_matter() -> TestResult {
+        let rendered = test_render(
+            "---\nlinks:\n  runbook: confluence://OPS/Runbook#Paging\n---\n# Page\nSee [the runbook](runbook).\n",
+        )?;
+
+        assert!(
+            rendered.content.contains(
+                r#"<ac:link ac:anchor="Paging"><ri:page ri:space-key="OPS" ri:content-title="Runbook"/><ac:link-body>the runbook</ac:link-body></ac:link>"#
+            ),
+            "{}",
+            rendered.content
+        );
+        Ok(())
+    }
+}
@@ -1,23 +1,33 @@
+use std::collections::HashMap;
 use std::path::Path;
 
 use anyhow::Result;
 use serde_json::json;
 
-use crate::archive::{archive, should_archive, should_unarchive, unarchive};
+use crate::archive::{archive, archive_description, should_archive, should_unarchive, unarchive};
 use crate::confluence_client::ConfluenceClient;
 use crate::confluence_page::{ConfluenceNode, ConfluenceNodeType, ConfluencePageData};
-use crate::console::{print_status, Status};
+use crate::console::{print_status, print_warning, Status};
 use crate::error::{self, ConfluenceError};
 use crate::link_generator::LinkGenerator;
 
 use crate::page_statuses::ContentStates;
-use crate::responses::{self, ContentStatus, PageSingleWithoutBody, Version};
+use crate::report;
+use crate::responses::{
+    self, ContentStatus, MultiEntityResult, PageBulkWithoutBody, PageSingleWithoutBody, Version,
+};
 use crate::sync_operation::SyncOperation;
 
 #[derive(Debug)]
 pub struct ConfluenceSpace {
     pub id: String,
+    /// The page index.md is synced to: the space homepage, or with --parent a page beneath the
+    /// given one. Empty with --parent and --plan if that page hasn't been created yet.
     pub homepage_id: String,
+    /// The page given by --parent, which index.md's page is kept under.
+    pub homepage_parent_id: Option<String>,
+    /// True if only the pages under homepage_id are managed, rather than the whole space.
+    subtree_only: bool,
     nodes: Vec<ConfluenceNode>,
     pub content_states: ContentStates,
 }
@@ -50,13 +60,102 @@ impl ConfluenceSpace {
         Ok(ConfluenceSpace {
             id: parsed_space.id,
             homepage_id: parsed_space.homepage_id,
+            homepage_parent_id: None,
+            subtree_only: false,
             nodes: Vec::default(),
             content_states,
         })
     }
 
+    /// Syncs under an existing page, given by ID or title, instead of the space homepage: index.md
+    /// is synced to a page of its own beneath it, titled `home_title`, so that the parent page
+    /// itself is left alone, and only the pages under index.md's page are archived or restored.
+    ///
+    /// index.md's page is created if it doesn't exist yet, unless `create` is false (ie for
+    /// --plan), in which case homepage_id is left empty.
+    pub fn use_parent_page(
+        &mut self,
+        confluence_client: &ConfluenceClient,
+        parent: &str,
+        home_title: &str,
+        create: bool,
+    ) -> Result<()> {
+        let find = |filter| -> Result<Vec<PageBulkWithoutBody>> {
+            Ok(confluence_client
+                .find_pages(&self.id, filter)?
+                .error_for_status()?
+                .json::<MultiEntityResult<PageBulkWithoutBody>>()?
+                .results)
+        };
+        let mut pages = if parent.chars().all(|c| c.is_ascii_digit()) {
+            find(("id", parent))?
+        } else {
+            Vec::default()
+        };
+        if pages.is_empty() {
+            pages = find(("title", parent))?;
+        }
+        let Some(page) = pages.into_iter().next() else {
+            return Err(ConfluenceError::generic_error(format!(
+                "No page with the ID or title \"{}\" in the space",
+                parent
+            )));
+        };
+
+        // Titles are unique in a space, so a page with index.md's title is either already its
+        // page, or one that sync takes over like any other page with a matching title.
+        let home = find(("title", home_title))?.into_iter().next();
+        self.homepage_id = match home {
+            Some(home) if home.id == page.id => {
+                return Err(ConfluenceError::generic_error(format!(
+                    "index.md has the same title as the parent page \"{}\", which it would \
+                     overwrite. Give index.md a different title.",
+                    page.title
+                )))
+            }
+            Some(home) => home.id,
+            None if create => self.create_home_page(confluence_client, home_title, &page.id)?,
+            None => String::default(),
+        };
+        self.homepage_parent_id = Some(page.id);
+        self.subtree_only = true;
+        Ok(())
+    }
+
+    fn create_home_page(
+        &self,
+        confluence_client: &ConfluenceClient,
+        title: &str,
+        parent_id: &str,
+    ) -> Result<String> {
+        let op = SyncOperation::start(format!("Creating new page \"{}\"", title), true);
+        let resp = confluence_client.create_page(json!({
+            "spaceId": self.id,
+            "status": "current",
+            "title": title,
+            "parentId": parent_id,
+        }))?;
+        if !resp.status().is_success() {
+            op.end(Status::Error);
+            return Err(ConfluenceError::failed_request(resp));
+        }
+        let page: PageSingleWithoutBody = resp.json()?;
+        op.end(Status::Created);
+        Ok(page.id)
+    }
+
     pub fn read_all_pages(&mut self, confluence_client: &ConfluenceClient) -> Result<()> {
-        self.nodes = ConfluenceNode::get_all(confluence_client, self)?;
+        if self.homepage_id.is_empty() {
+            // index.md's page hasn't been created under the parent yet, so nothing is synced
+            self.nodes = Vec::default();
+            return Ok(());
+        }
+        let nodes = ConfluenceNode::get_all(confluence_client, self)?;
+        self.nodes = if self.subtree_only {
+            subtree(nodes, &self.homepage_id)
+        } else {
+            nodes
+        };
         Ok(())
     }
 
@@ -66,18 +165,35 @@ impl ConfluenceSpace {
         });
     }
 
+    /// Archived pages that have a matching markdown file again.
+    pub(crate) fn pages_to_restore<'a>(
+        &'a self,
+        link_generator: &'a LinkGenerator,
+    ) -> impl Iterator<Item = &'a ConfluenceNode> {
+        self.nodes
+            .iter()
+            .filter(|p| should_unarchive(p, link_generator))
+    }
+
+    /// Managed pages that no longer have a matching markdown file.
+    pub(crate) fn orphans<'a>(
+        &'a self,
+        link_generator: &'a LinkGenerator,
+    ) -> impl Iterator<Item = &'a ConfluenceNode> {
+        self.nodes
+            .iter()
+            .filter(|p| should_archive(p, link_generator))
+    }
+
     pub(crate) fn restore_archived_pages(
         &self,
         link_generator: &LinkGenerator,
         confluence_client: &ConfluenceClient,
     ) -> anyhow::Result<()> {
         let _errors = self
-            .nodes
-            .iter()
-            .filter(|p| should_unarchive(p, link_generator))
+            .pages_to_restore(link_generator)
             .filter_map(|p| unarchive(p, confluence_client).err())
             .collect::<Vec<anyhow::Error>>();
-
         Ok(())
     }
 
@@ -87,17 +203,24 @@ impl ConfluenceSpace {
         space_dir: &Path,
         confluence_client: &ConfluenceClient,
     ) -> error::Result<()> {
-        // let orphaned_pages = self.get_orphans(link_generator);
         let _errors = self
-            .nodes
-            .iter()
-            .filter(|p| should_archive(p, link_generator))
+            .orphans(link_generator)
             .filter_map(|p| archive(p, space_dir, confluence_client).err())
             .collect::<Vec<anyhow::Error>>();
-
         Ok(())
     }
 
+    /// Warns about orphans instead of archiving them, for --orphans=keep.
+    pub(crate) fn keep_orphans(&self, link_generator: &LinkGenerator, space_dir: &Path) {
+        for node in self.orphans(link_generator) {
+            print_warning(&format!(
+                "not archiving {}",
+                archive_description(node, space_dir)
+                    .unwrap_or_else(|| format!("orphaned \"{}\"", node.title))
+            ));
+        }
+    }
+
     pub fn get_existing_node(&self, node_id: &str) -> Option<ConfluenceNode> {
         self.nodes.iter().find(|node| node.id == node_id).cloned()
     }
@@ -112,11 +235,15 @@ impl ConfluenceSpace {
         confluence_client: &ConfluenceClient,
     ) -> Result<()> {
         for title in link_generator.get_nodes_to_create() {
+            if let Some(file) = link_generator.get_title_file(&title) {
+                report::start_page(&file, &title);
+            }
             if link_generator.is_folder(&title) {
                 self.create_folder(title, confluence_client, link_generator)?;
             } else {
                 self.create_page(title, confluence_client, link_generator)?;
             }
+            report::end_page();
         }
         Ok(())
     }
@@ -176,3 +303,174 @@ impl ConfluenceSpace {
         Ok(())
     }
 }
+
+/// The node with the root ID and all of its descendants.
+fn subtree(nodes: Vec<ConfluenceNode>, root_id: &str) -> Vec<ConfluenceNode> {
+    let parents: HashMap<&str, Option<&str>> = nodes
+        .iter()
+        .map(|node| (node.id.as_str(), node.parent_id.as_deref()))
+        .collect();
+    let in_subtree = |node: &ConfluenceNode| {
+        let mut id = Some(node.id.as_str());
+        // bounded, in case the parents somehow form a loop
+        for _ in 0..=parents.len() {
+            match id {
+                Some(current) if current == root_id => return true,
+                Some(current) => id = parents.get(current).copied().flatten(),
+                None => return false,
+            }
+        }
+        false
+    };
+    let keep: Vec<bool> = nodes.iter().map(in_subtree).collect();
+    nodes
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(node, keep)| keep.then_some(node))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    fn space() -> ConfluenceSpace {
+        ConfluenceSpace {
+            id: String::from("1"),
+            homepage_id: String::from("10"),
+            homepage_parent_id: None,
+            subtree_only: false,
+            nodes: Vec::default(),
+            content_states: ContentStates::new(&[]),
+        }
+    }
+
+    /// Mocks finding pages by title, with the given pages as the results.
+    fn mock_find(server: &mut mockito::Server, title: &str, pages: &str) -> mockito::Mock {
+        server
+            .mock("GET", "/wiki/api/v2/pages")
+            .match_query(Matcher::UrlEncoded(
+                String::from("title"),
+                String::from(title),
+            ))
+            .with_body(format!(r#"{{"results": [{}], "_links": {{}}}}"#, pages))
+            .create()
+    }
+
+    const PARENT: &str = r#"{"id": "100", "parentId": "10", "title": "Platform Team", "status": "current", "version": {"number": 7, "message": ""}}"#;
+
+    #[test]
+    fn it_creates_a_page_for_index_md_under_an_existing_parent() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let _parent = mock_find(&mut server, "Platform Team", PARENT);
+        let _home = mock_find(&mut server, "Team Docs", "");
+        let created = server
+            .mock("POST", "/wiki/api/v2/pages")
+            .match_body(Matcher::PartialJsonString(String::from(
+                r#"{"title": "Team Docs", "parentId": "100"}"#,
+            )))
+            .with_body(
+                r#"{"id": "200", "title": "Team Docs", "version": {"number": 1, "message": ""}}"#,
+            )
+            .create();
+        let mut space = space();
+
+        space.use_parent_page(&client, "Platform Team", "Team Docs", true)?;
+
+        created.assert();
+        assert_eq!(space.homepage_id, "200");
+        assert_eq!(space.homepage_parent_id.as_deref(), Some("100"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_uses_the_existing_page_for_index_md_under_the_parent() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let _parent = mock_find(&mut server, "Platform Team", PARENT);
+        let _home = mock_find(
+            &mut server,
+            "Team Docs",
+            r#"{"id": "200", "parentId": "100", "title": "Team Docs", "status": "current", "version": {"number": 3, "message": ""}}"#,
+        );
+        let created = server.mock("POST", "/wiki/api/v2/pages").expect(0).create();
+        let mut space = space();
+
+        space.use_parent_page(&client, "Platform Team", "Team Docs", true)?;
+
+        created.assert();
+        assert_eq!(space.homepage_id, "200");
+        assert_eq!(space.homepage_parent_id.as_deref(), Some("100"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_plans_without_creating_the_page_for_index_md() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let _parent = mock_find(&mut server, "Platform Team", PARENT);
+        let _home = mock_find(&mut server, "Team Docs", "");
+        let created = server.mock("POST", "/wiki/api/v2/pages").expect(0).create();
+        let mut space = space();
+
+        space.use_parent_page(&client, "Platform Team", "Team Docs", false)?;
+        space.read_all_pages(&client)?;
+
+        created.assert();
+        assert!(space.homepage_id.is_empty());
+        assert!(space.nodes.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn it_refuses_to_sync_index_md_onto_the_parent() {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let _parent = mock_find(&mut server, "Platform Team", PARENT);
+        let mut space = space();
+
+        let err = space
+            .use_parent_page(&client, "Platform Team", "Platform Team", true)
+            .unwrap_err();
+
+        assert!(err.to_string().contains("same title as the parent page"));
+    }
+
+    fn node(id: &str, parent_id: Option<&str>) -> ConfluenceNode {
+        ConfluenceNode {
+            id: String::from(id),
+            title: format!("Page {}", id),
+            parent_id: parent_id.map(String::from),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    number: 1,
+                    message: String::default(),
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        }
+    }
+
+    #[test]
+    fn it_keeps_only_the_pages_under_the_parent() {
+        let nodes = vec![
+            node("1", None),
+            node("2", Some("1")),
+            node("3", Some("2")),
+            node("4", Some("3")),
+            node("5", Some("1")),
+            node("6", Some("5")),
+            node("7", None),
+        ];
+
+        let ids: Vec<String> = subtree(nodes, "2").into_iter().map(|n| n.id).collect();
+
+        assert_eq!(ids, vec!["2", "3", "4"]);
+    }
+}
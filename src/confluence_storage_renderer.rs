@@ -13,7 +13,10 @@ use once_cell::sync::Lazy;
 
 use crate::alerts::{render_basic_alert, render_expand};
 use crate::attachments::{render_link_enter, render_link_leave};
+use crate::code_blocks::CodeBlockInfo;
 use crate::link_generator::LinkGenerator;
+use crate::math::{write_math_macro, MathSettings};
+use crate::xhtml::{cdata, escape_text, fix_html};
 
 #[rustfmt::skip]
 const CMARK_CTYPE_CLASS: [u8; 256] = [
@@ -52,11 +55,25 @@ pub fn render_confluence_storage<'a>(
     let mut f = ConfluenceStorageRenderer::new(options, &mut writer, link_generator, source);
     f.format(root, false)?;
     if f.footnote_ix > 0 {
-        f.output.write_all(b"</ol>\n</section>\n")?;
+        f.output.write_all(b"</ol>\n")?;
     }
     Ok(())
 }
 
+/// Writes a code block as Confluence's code macro.
+pub(crate) fn render_code_macro(
+    output: &mut impl Write,
+    info: &CodeBlockInfo,
+    literal: &str,
+) -> io::Result<()> {
+    output.write_all(br#"<ac:structured-macro ac:name="code" ac:schema-version="1" ac:macro-id="d248891e-ba87-4ba9-becf-edfb21175463">"#)?;
+    info.write_parameters(output)?;
+    output.write_all(b"<ac:plain-text-body><![CDATA[")?;
+    output.write_all(cdata(literal.trim_end()).as_bytes())?;
+    output.write_all(b"]]></ac:plain-text-body></ac:structured-macro>")?;
+    Ok(())
+}
+
 pub struct WriteWithLast<'w> {
     output: &'w mut dyn Write,
     last_was_lf: Cell<bool>,
@@ -326,10 +343,6 @@ impl<'o> ConfluenceStorageRenderer<'o> {
         escape(&mut self.output, buffer)
     }
 
-    fn escape_href(&mut self, buffer: &[u8]) -> io::Result<()> {
-        escape_href(&mut self.output, buffer)
-    }
-
     fn format<'a>(&mut self, node: &'a AstNode<'a>, plain: bool) -> io::Result<()> {
         // Traverse the AST iteratively using a work stack, with pre- and
         // post-child-traversal phases. During pre-order traversal render the
@@ -474,26 +487,20 @@ impl<'o> ConfluenceStorageRenderer<'o> {
             NodeValue::CodeBlock(ref ncb) => {
                 if entering {
                     self.cr()?;
-
-                    self.output.write_all(br#"<ac:structured-macro ac:name="code" ac:schema-version="1" ac:macro-id="d248891e-ba87-4ba9-becf-edfb21175463">"#)?;
-
-                    self.output
-                        .write_all(br#"<ac:parameter ac:name="language">"#)?;
-                    self.output.write_all(ncb.info.as_bytes())?;
-                    self.output.write_all(b"</ac:parameter>")?;
-                    self.output.write_all(b"<ac:plain-text-body><![CDATA[")?;
-
-                    let literal = &ncb.literal.trim_end().as_bytes();
-                    self.output.write_all(literal)?;
-                    self.output
-                        .write_all(b"]]></ac:plain-text-body></ac:structured-macro>")?;
+                    render_code_macro(self.output, &CodeBlockInfo::parse(&ncb.info), &ncb.literal)?;
                 }
             }
             NodeValue::HtmlBlock(ref nhb) => {
-                // No sourcepos.
+                // No sourcepos, except in a comment for finding where invalid HTML came from.
                 if entering {
                     self.cr()?;
-                    let literal = nhb.literal.as_bytes();
+                    if self.options.render.sourcepos {
+                        self.output.write_all(b"<!--")?;
+                        self.render_sourcepos(node)?;
+                        self.output.write_all(b" -->")?;
+                    }
+                    let fixed = fix_html(&nhb.literal);
+                    let literal = fixed.as_bytes();
                     if self.options.render.escape {
                         self.escape(literal)?;
                     } else if !self.options.render.unsafe_ {
@@ -551,8 +558,7 @@ impl<'o> ConfluenceStorageRenderer<'o> {
             }
             NodeValue::Text(ref literal) => {
                 if entering {
-                    // self.escape(literal.as_bytes())?;
-                    self.output.write_all(literal.as_bytes())?; // need to avoid escaping template stuff :/
+                    escape_text(self.output, literal)?;
                 }
             }
             NodeValue::LineBreak => {
@@ -586,7 +592,8 @@ impl<'o> ConfluenceStorageRenderer<'o> {
             NodeValue::HtmlInline(ref literal) => {
                 // No sourcepos.
                 if entering {
-                    let literal = literal.as_bytes();
+                    let fixed = fix_html(literal);
+                    let literal = fixed.as_bytes();
                     if self.options.render.escape {
                         self.escape(literal)?;
                     } else if !self.options.render.unsafe_ {
@@ -755,18 +762,16 @@ impl<'o> ConfluenceStorageRenderer<'o> {
             }
             NodeValue::FootnoteDefinition(ref nfd) => {
                 if entering {
+                    // Confluence drops <section> and id attributes, so the footnotes are a plain
+                    // list with anchor macros for the references and backrefs to link to
                     if self.footnote_ix == 0 {
-                        self.output.write_all(b"<section")?;
-                        self.render_sourcepos(node)?;
-                        self.output
-                            .write_all(b" class=\"footnotes\" data-footnotes>\n<ol>\n")?;
+                        self.output.write_all(b"<hr />\n<ol>\n")?;
                     }
                     self.footnote_ix += 1;
                     self.output.write_all(b"<li")?;
                     self.render_sourcepos(node)?;
-                    self.output.write_all(b" id=\"fn-")?;
-                    self.escape_href(nfd.name.as_bytes())?;
-                    self.output.write_all(b"\">")?;
+                    self.output.write_all(b">")?;
+                    self.put_anchor(&format!("fn-{}", nfd.name))?;
                 } else {
                     if self.put_footnote_backref(nfd)? {
                         self.output.write_all(b"\n")?;
@@ -780,17 +785,15 @@ impl<'o> ConfluenceStorageRenderer<'o> {
 
                     self.output.write_all(b"<sup")?;
                     self.render_sourcepos(node)?;
+                    self.output.write_all(b">")?;
 
                     if nfr.ref_num > 1 {
                         ref_id = format!("{}-{}", ref_id, nfr.ref_num);
                     }
 
-                    self.output
-                        .write_all(b" class=\"footnote-ref\"><a href=\"#fn-")?;
-                    self.escape_href(nfr.name.as_bytes())?;
-                    self.output.write_all(b"\" id=\"")?;
-                    self.escape_href(ref_id.as_bytes())?;
-                    write!(self.output, "\" data-footnote-ref>{}</a></sup>", nfr.ix)?;
+                    self.put_anchor(&ref_id)?;
+                    self.put_anchor_link(&format!("fn-{}", nfr.name), &nfr.ix.to_string())?;
+                    self.output.write_all(b"</sup>")?;
                 }
             }
             NodeValue::TaskItem(symbol) => {
@@ -811,11 +814,27 @@ impl<'o> ConfluenceStorageRenderer<'o> {
                     self.output.write_all(b"</ac:task-body></ac:task>\n")?;
                 }
             }
-            NodeValue::Raw(_) => (),
-            NodeValue::Math(ref _node_math) => (),
+            NodeValue::Raw(ref literal) => {
+                // No sourcepos.
+                if entering {
+                    self.output.write_all(literal.as_bytes())?;
+                }
+            }
+            NodeValue::Math(ref node_math) => {
+                if entering {
+                    write_math_macro(self.output, &MathSettings::default(), node_math)?;
+                }
+            }
             NodeValue::MultilineBlockQuote(_node_multiline_block_quote) => (),
             NodeValue::Escaped => (),
-            NodeValue::WikiLink(ref _node_wiki_link) => (),
+            NodeValue::WikiLink(ref nwl) => {
+                let link_generator = self.link_generator;
+                if entering {
+                    link_generator.enter_wiki_link(nwl, self)?;
+                } else {
+                    self.output.write_all(b"</a>")?;
+                }
+            }
             NodeValue::Underline => (),
             NodeValue::Subscript => (),
             NodeValue::SpoileredText => (),
@@ -855,26 +874,39 @@ impl<'o> ConfluenceStorageRenderer<'o> {
 
         self.written_footnote_ix = self.footnote_ix;
 
-        let mut ref_suffix = String::new();
-        let mut superscript = String::new();
-
         for ref_num in 1..=nfd.total_references {
             if ref_num > 1 {
-                ref_suffix = format!("-{}", ref_num);
-                superscript = format!("<sup class=\"footnote-ref\">{}</sup>", ref_num);
                 write!(self.output, " ")?;
+                self.put_anchor_link(
+                    &format!("fnref-{}-{}", nfd.name, ref_num),
+                    &format!("↩{}", ref_num),
+                )?;
+            } else {
+                self.put_anchor_link(&format!("fnref-{}", nfd.name), "↩")?;
             }
-
-            self.output.write_all(b"<a href=\"#fnref-")?;
-            self.escape_href(nfd.name.as_bytes())?;
-            write!(
-                self.output,
-                "{}\" class=\"footnote-backref\" data-footnote-backref data-footnote-backref-idx=\"{}{}\" aria-label=\"Back to reference {}{}\">↩{}</a>",
-                ref_suffix, self.footnote_ix, ref_suffix, self.footnote_ix, ref_suffix, superscript
-            )?;
         }
         Ok(true)
     }
+
+    /// An anchor macro, which can be linked to with `put_anchor_link`.
+    fn put_anchor(&mut self, name: &str) -> io::Result<()> {
+        self.output
+            .write_all(b"<ac:structured-macro ac:name=\"anchor\"><ac:parameter ac:name=\"\">")?;
+        self.escape(name.as_bytes())?;
+        self.output
+            .write_all(b"</ac:parameter></ac:structured-macro>")
+    }
+
+    /// A link to an anchor on the same page.
+    fn put_anchor_link(&mut self, anchor: &str, text: &str) -> io::Result<()> {
+        self.output.write_all(b"<ac:link ac:anchor=\"")?;
+        self.escape(anchor.as_bytes())?;
+        self.output
+            .write_all(b"\"><ac:plain-text-link-body><![CDATA[")?;
+        self.output.write_all(cdata(text).as_bytes())?;
+        self.output
+            .write_all(b"]]></ac:plain-text-link-body></ac:link>")
+    }
 }
 
 fn has_task_children<'a>(
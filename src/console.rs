@@ -1,9 +1,15 @@
 use owo_colors::{OwoColorize, Stream::Stdout, Style};
+use serde::Serialize;
+
+use crate::report::{record_error, record_status, record_warning};
 
 const PADDING: usize = 9;
 
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
 pub enum Status {
     Updated,
+    #[default]
     Skipped,
     Created,
     Error,
@@ -13,7 +19,24 @@ pub enum Status {
     Reordered,
 }
 
+impl Status {
+    /// How much a status matters when summarising several of them, ie for a page that was
+    /// both created and updated.
+    pub fn significance(&self) -> u8 {
+        match self {
+            Status::Skipped => 0,
+            Status::Reordered => 1,
+            Status::Updated => 2,
+            Status::Deleted => 3,
+            Status::Archived | Status::Unarchived => 4,
+            Status::Created => 5,
+            Status::Error => 6,
+        }
+    }
+}
+
 pub fn print_warning(warning_str: &str) {
+    record_warning(warning_str);
     println!(
         "{}: {}",
         "warning".if_supports_color(Stdout, |s| s.bright_yellow()),
@@ -26,6 +49,7 @@ pub fn print_info(info_str: &str) {
 }
 
 pub fn print_error(info_str: &str) {
+    record_error(info_str);
     println!(
         "{}: {}",
         "  error".if_supports_color(Stdout, |s| s.bright_red()),
@@ -34,6 +58,7 @@ pub fn print_error(info_str: &str) {
 }
 
 pub fn print_status(status: Status, status_str: &str) {
+    record_status(status, status_str, false);
     let (label, style) = match status {
         Status::Updated => ("updated", Style::new().cyan()),
         Status::Skipped => ("skipped", Style::new().dimmed()),
@@ -50,3 +75,41 @@ pub fn print_status(status: Status, status_str: &str) {
         status_str
     );
 }
+
+/// Like print_status, but for changes that would be made by a sync (ie, with --plan).
+pub fn print_planned(status: Status, status_str: &str) {
+    record_status(status, status_str, true);
+    let (label, style) = match status {
+        Status::Updated => ("would update", Style::new().cyan()),
+        Status::Skipped => ("unchanged", Style::new().dimmed()),
+        Status::Created => ("would create", Style::new().green()),
+        Status::Error => ("  error", Style::new().red()),
+        Status::Deleted => ("would delete", Style::new()),
+        Status::Archived => ("would archive", Style::new().blue()),
+        Status::Unarchived => ("would unarchive", Style::new().blue()),
+        Status::Reordered => ("would reorder", Style::new().cyan()),
+    };
+    println!(
+        "{}: {}",
+        label.if_supports_color(Stdout, |s| s.style(style)),
+        status_str
+    );
+}
+
+/// Prints a unified diff, coloured by whether lines were added or removed.
+pub fn print_diff(diff: &str) {
+    for line in diff.lines() {
+        let style = if line.starts_with("+++") || line.starts_with("---") {
+            Style::new().bold()
+        } else if line.starts_with('+') {
+            Style::new().green()
+        } else if line.starts_with('-') {
+            Style::new().red()
+        } else if line.starts_with("@@") {
+            Style::new().cyan()
+        } else {
+            Style::new()
+        };
+        println!("{}", line.if_supports_color(Stdout, |s| s.style(style)));
+    }
+}
@@ -0,0 +1,398 @@
+//! Containers fenced with colons, for markdown that's rendered inside Confluence's markup:
+//!
+//! ```markdown
+//! :::columns
+//! :::column
+//! The left column, in **markdown**.
+//! :::
+//! :::column
+//! The right column.
+//! :::
+//! :::
+//! ```
+//!
+//! `:::macro jira key=ABC-1` is any Confluence macro, with the markdown in it as its body.
+//!
+//! Before the page is parsed, each fence is turned into a comment, which comrak makes a block of
+//! its own, so the markdown between the fences is parsed as usual (and its links and images are
+//! collected). The comments are then paired up and replaced with the container's markup.
+
+use std::collections::HashMap;
+
+use comrak::nodes::{AstNode, NodeValue};
+use once_cell::sync::Lazy;
+use quick_xml::escape::escape;
+use regex::Regex;
+
+static CONTAINER_FENCE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^( {0,3}):{3,}\s*([A-Za-z][\w-]*)?\s*(.*?)\s*$").unwrap());
+
+static MACRO_NAME: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Za-z0-9][\w.-]*$").unwrap());
+
+static CONTAINER_MARKER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^<!-- ::: ?([\w-]*) ?(.*?) -->$").unwrap());
+
+/// Turns the container fences outside of code blocks into comments.
+pub(crate) fn mark_containers(content: &str) -> String {
+    let mut marked = String::with_capacity(content.len());
+    let mut code_fence: Option<(char, usize)> = None;
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim_start_matches(' ');
+        if line.len() - trimmed.len() < 4 {
+            if let Some(fence_char @ ('`' | '~')) = trimmed.chars().next() {
+                let length = trimmed.chars().take_while(|c| *c == fence_char).count();
+                match code_fence {
+                    None if length >= 3 => code_fence = Some((fence_char, length)),
+                    Some((open_char, open_length))
+                        if open_char == fence_char
+                            && length >= open_length
+                            && trimmed[length..].trim().is_empty() =>
+                    {
+                        code_fence = None
+                    }
+                    _ => (),
+                }
+            }
+        }
+        match CONTAINER_FENCE.captures(line.trim_end_matches(['\r', '\n'])) {
+            Some(captures) if code_fence.is_none() => {
+                marked.push_str(&format!(
+                    "{}<!-- ::: {} {} -->",
+                    &captures[1],
+                    captures.get(2).map_or("", |name| name.as_str()),
+                    &captures[3]
+                ));
+                marked.push_str(&line[line.trim_end_matches(['\r', '\n']).len()..]);
+            }
+            _ => marked.push_str(line),
+        }
+    }
+    marked
+}
+
+struct Container<'a> {
+    name: String,
+    args: String,
+    open: &'a AstNode<'a>,
+    close: &'a AstNode<'a>,
+}
+
+impl<'a> Container<'a> {
+    /// The nodes between the fences.
+    fn content(&self) -> impl Iterator<Item = &'a AstNode<'a>> + '_ {
+        std::iter::successors(self.open.next_sibling(), |node| node.next_sibling())
+            .take_while(|node| !node.same_node(self.close))
+    }
+}
+
+fn marker<'a>(node: &'a AstNode<'a>) -> Option<(String, String)> {
+    match node.data.borrow().value {
+        NodeValue::HtmlBlock(ref html_block) => CONTAINER_MARKER
+            .captures(html_block.literal.trim())
+            .map(|captures| (String::from(&captures[1]), String::from(&captures[2]))),
+        _ => None,
+    }
+}
+
+fn set_raw<'a>(node: &'a AstNode<'a>, html: String) {
+    node.data.borrow_mut().value = NodeValue::Raw(html);
+}
+
+/// Replaces the containers in the page with their markup, returning any errors in them.
+pub(crate) fn render_containers<'a>(root: &'a AstNode<'a>) -> Vec<String> {
+    let mut errors = Vec::<String>::default();
+    let mut containers = Vec::<Container<'a>>::default();
+    let mut open = Vec::<(&'a AstNode<'a>, String, String)>::default();
+    for node in root.descendants() {
+        match marker(node) {
+            Some((name, args)) if !name.is_empty() => open.push((node, name, args)),
+            Some(_) => match open.pop() {
+                Some((open, name, args))
+                    if open.parent().is_some_and(|parent| {
+                        node.parent()
+                            .is_some_and(|node_parent| node_parent.same_node(parent))
+                    }) =>
+                {
+                    containers.push(Container {
+                        name,
+                        args,
+                        open,
+                        close: node,
+                    })
+                }
+                Some((_, name, _)) => {
+                    errors.push(format!(":::{} is closed inside something else", name))
+                }
+                None => errors.push(String::from("::: closes a container that isn't open")),
+            },
+            None => (),
+        }
+    }
+    for (_, name, _) in open {
+        errors.push(format!(":::{} is never closed", name));
+    }
+    if !errors.is_empty() {
+        return errors;
+    }
+
+    let by_open: HashMap<*const AstNode<'a>, usize> = containers
+        .iter()
+        .enumerate()
+        .map(|(i, container)| (container.open as *const AstNode<'a>, i))
+        .collect();
+    for container in containers.iter() {
+        let result = match container.name.as_str() {
+            "columns" => render_columns(container, root, &containers, &by_open),
+            "column" => Ok(()),
+            "macro" => render_macro(container),
+            name => Err(format!("Unknown container :::{}", name)),
+        };
+        if let Err(err) = result {
+            errors.push(err);
+        }
+    }
+    if errors.is_empty()
+        && containers
+            .iter()
+            .any(|container| container.name == "column" && marker(container.open).is_some())
+    {
+        errors.push(String::from(":::column can only be inside :::columns"));
+    }
+    errors
+}
+
+/// Any Confluence macro, with its name and then its parameters after `:::macro`, ie
+/// `:::macro jira key=ABC-1`, and the markdown in the container as its body. The name can also be
+/// given as `name=jira` first, so that any later `name=` is a parameter.
+fn render_macro(container: &Container) -> Result<(), String> {
+    let tokens = shlex::split(&container.args)
+        .ok_or_else(|| format!("Unbalanced quotes in :::macro {}", container.args))?;
+    let mut tokens = tokens.into_iter();
+    let name = tokens
+        .next()
+        .map(|name| match name.strip_prefix("name=") {
+            Some(name) => String::from(name),
+            None => name,
+        })
+        .filter(|name| !name.contains('='))
+        .ok_or(":::macro needs the macro's name first, ie :::macro jira key=ABC-1")?;
+    if !MACRO_NAME.is_match(&name) {
+        return Err(format!(":::macro has an invalid macro name '{}'", name));
+    }
+    let mut parameters = String::default();
+    for token in tokens {
+        let (key, value) = token.split_once('=').ok_or_else(|| {
+            format!(
+                ":::macro parameters should be key=value, rather than '{}'",
+                token
+            )
+        })?;
+        parameters.push_str(&format!(
+            "<ac:parameter ac:name=\"{}\">{}</ac:parameter>",
+            escape(key),
+            escape(value)
+        ));
+    }
+
+    let has_body = container.content().next().is_some();
+    set_raw(
+        container.open,
+        format!(
+            "<ac:structured-macro ac:name=\"{}\">{}{}",
+            escape(&name),
+            parameters,
+            if has_body { "<ac:rich-text-body>" } else { "" }
+        ),
+    );
+    set_raw(
+        container.close,
+        format!(
+            "{}</ac:structured-macro>",
+            if has_body { "</ac:rich-text-body>" } else { "" }
+        ),
+    );
+    Ok(())
+}
+
+/// Confluence's layout for the argument to `:::columns` and the number of columns.
+fn layout_type(args: &str, columns: usize) -> Result<&'static str, String> {
+    let (layout_type, expected) = match args {
+        "" | "equal" => match columns {
+            1 => ("single", 1),
+            2 => ("two_equal", 2),
+            3 => ("three_equal", 3),
+            _ => ("", 0),
+        },
+        "left-sidebar" => ("two_left_sidebar", 2),
+        "right-sidebar" => ("two_right_sidebar", 2),
+        "sidebars" => ("three_with_sidebars", 3),
+        args => return Err(format!(":::columns has an unknown layout '{}'", args)),
+    };
+    if columns == expected {
+        Ok(layout_type)
+    } else if expected == 0 {
+        Err(format!(":::columns has {} columns, not 1, 2 or 3", columns))
+    } else {
+        Err(format!(
+            ":::columns {} has {} columns, not {}",
+            args, columns, expected
+        ))
+    }
+}
+
+fn render_columns<'a>(
+    columns: &Container<'a>,
+    root: &'a AstNode<'a>,
+    containers: &[Container<'a>],
+    by_open: &HashMap<*const AstNode<'a>, usize>,
+) -> Result<(), String> {
+    if !columns
+        .open
+        .parent()
+        .is_some_and(|parent| parent.same_node(root))
+    {
+        return Err(String::from(
+            ":::columns can only be at the top level of the page",
+        ));
+    }
+
+    let mut cells = Vec::<&Container<'a>>::default();
+    let mut content = columns.content();
+    while let Some(node) = content.next() {
+        match by_open.get(&(node as *const AstNode<'a>)) {
+            Some(i) if containers[*i].name == "column" => {
+                let cell = &containers[*i];
+                for node in content.by_ref() {
+                    if node.same_node(cell.close) {
+                        break;
+                    }
+                }
+                cells.push(cell);
+            }
+            _ => {
+                return Err(String::from(
+                    ":::columns should only have :::column containers in it",
+                ))
+            }
+        }
+    }
+    let layout_type = layout_type(&columns.args, cells.len())?;
+
+    set_raw(
+        columns.open,
+        format!("<ac:layout><ac:layout-section ac:type=\"{}\">", layout_type),
+    );
+    set_raw(
+        columns.close,
+        String::from("</ac:layout-section></ac:layout>"),
+    );
+    for cell in cells {
+        set_raw(cell.open, String::from("<ac:layout-cell>"));
+        set_raw(cell.close, String::from("</ac:layout-cell>"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::{nodes::AstNode, Arena};
+
+    use crate::{
+        error::TestResult,
+        test_helpers::{markdown_page_from_str, test_render},
+        xhtml::check_storage,
+    };
+
+    use super::*;
+
+    #[test]
+    fn it_marks_fences_outside_of_code() {
+        assert_eq!(
+            mark_containers(":::columns\n```\n:::\n```\n:::\r\n"),
+            "<!-- ::: columns  -->\n```\n:::\n```\n<!-- :::   -->\r\n"
+        );
+    }
+
+    #[test]
+    fn it_renders_columns_as_layouts() -> TestResult {
+        let rendered = test_render(
+            "# Page\nBefore.\n:::columns left-sidebar\n:::column\nSee [the site](https://example.com).\n:::\n:::column\n## Details\n- one\n- two\n:::\n:::\nAfter.\n",
+        )?;
+
+        assert_eq!(
+            rendered.content.trim(),
+            r#"<p>Before.</p>
+<ac:layout><ac:layout-section ac:type="two_left_sidebar"><ac:layout-cell>
+<p>See <a href="https://example.com">the site</a>.</p>
+</ac:layout-cell><ac:layout-cell>
+<h2>Details</h2>
+<ul>
+<li>one</li>
+<li>two</li>
+</ul>
+</ac:layout-cell></ac:layout-section></ac:layout>
+<p>After.</p>"#
+        );
+        assert!(check_storage(&rendered.content).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn it_collects_links_in_columns() -> TestResult {
+        let arena = Arena::<AstNode>::new();
+        let page = markdown_page_from_str(
+            "page.md",
+            "# Page\n:::columns\n:::column\n[Other](other.md)\n:::\n:::column\n![](image.png)\n:::\n:::\n",
+            &arena,
+        )?;
+
+        assert_eq!(page.local_links.len(), 1);
+        assert_eq!(page.attachments.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn it_renders_macros() -> TestResult {
+        let rendered = test_render(
+            "# Page\n:::macro name=jira key=ABC-1\n:::\n\n:::macro panel title=\"Tom & Jerry\" =default\nSee [the site](https://example.com):\n\n:::macro status colour=Green title=DONE\n:::\n\n:::macro include-page name=Other\n:::\n:::\n",
+        )?;
+
+        assert_eq!(
+            rendered.content.trim(),
+            r#"<ac:structured-macro ac:name="jira"><ac:parameter ac:name="key">ABC-1</ac:parameter></ac:structured-macro><ac:structured-macro ac:name="panel"><ac:parameter ac:name="title">Tom &amp; Jerry</ac:parameter><ac:parameter ac:name="">default</ac:parameter><ac:rich-text-body>
+<p>See <a href="https://example.com">the site</a>:</p>
+<ac:structured-macro ac:name="status"><ac:parameter ac:name="colour">Green</ac:parameter><ac:parameter ac:name="title">DONE</ac:parameter></ac:structured-macro><ac:structured-macro ac:name="include-page"><ac:parameter ac:name="name">Other</ac:parameter></ac:structured-macro></ac:rich-text-body></ac:structured-macro>"#
+        );
+        assert!(check_storage(&rendered.content).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_bad_containers() {
+        let error = |markdown: &str| format!("{:#}", test_render(markdown).err().unwrap());
+
+        assert!(
+            error("# Page\n:::columns\n:::column\nA\n:::\n").contains(":::columns is never closed")
+        );
+        assert!(error("# Page\n:::columns\nA\n:::\n")
+            .contains(":::columns should only have :::column containers in it"));
+        assert!(
+            error("# Page\n:::columns sidebars\n:::column\nA\n:::\n:::\n")
+                .contains(":::columns sidebars has 1 columns, not 3")
+        );
+        assert!(
+            error("# Page\n- item\n\n  :::columns\n  :::column\n  A\n  :::\n  :::\n")
+                .contains("only be at the top level")
+        );
+        assert!(error("# Page\n:::tabs\n:::\n").contains("Unknown container :::tabs"));
+        assert!(error("# Page\n:::macro key=ABC-1\n:::\n")
+            .contains(":::macro needs the macro's name first"));
+        assert!(error("# Page\n:::macro\n:::\n").contains(":::macro needs the macro's name first"));
+        assert!(error("# Page\n:::macro \"\"\n:::\n").contains("invalid macro name ''"));
+        assert!(error("# Page\n:::macro name=\n:::\n").contains("invalid macro name ''"));
+        assert!(error("# Page\n:::macro \"a b\"\n:::\n").contains("invalid macro name 'a b'"));
+        assert!(error("# Page\n:::macro jira ABC-1\n:::\n")
+            .contains("should be key=value, rather than 'ABC-1'"));
+        assert!(error("# Page\n:::column\nA\n:::\n").contains("can only be inside :::columns"));
+    }
+}
@@ -0,0 +1,355 @@
+//! Renders mermaid and plantuml code blocks into images with a local command (ie, mmdc), so they
+//! show up in Confluence as diagrams rather than as their source.
+//!
+//! The images are attached to the page under a name made from a hash of the diagram, so they're
+//! only uploaded again when the diagram changes, and are cached between syncs.
+
+use std::{
+    fs::{self, create_dir_all},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::Context;
+use comrak::nodes::{AstNode, NodeValue};
+use serde::Deserialize;
+
+use crate::{
+    alerts::render_expand,
+    attachments::Attachment,
+    checksum::sha256_digest,
+    code_blocks::CodeBlockInfo,
+    confluence_storage_renderer::render_code_macro,
+    error::{ConfluenceError, Result},
+    local_link::LocalLink,
+};
+
+/// Where rendered diagrams (and math) are cached, relative to the space directory.
+pub const DIAGRAM_DIR: &str = ".marked-space/diagrams";
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiagramFormat {
+    #[default]
+    Svg,
+    Png,
+}
+
+impl DiagramFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            DiagramFormat::Svg => "svg",
+            DiagramFormat::Png => "png",
+        }
+    }
+}
+
+/// How to render diagrams, from `diagrams` in marked-space.yml.
+///
+/// In the commands `{input}` and `{output}` are replaced with the paths of the diagram's source
+/// and the image to write, and `{format}` with svg or png. Without `{input}` the source is piped
+/// to the command, and without `{output}` the image is read from what it prints.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DiagramSettings {
+    pub mermaid: String,
+    pub plantuml: String,
+    pub format: DiagramFormat,
+    /// Keep the source of each diagram in an expand beneath it.
+    pub keep_source: bool,
+}
+
+impl Default for DiagramSettings {
+    fn default() -> Self {
+        Self {
+            mermaid: String::from("mmdc --input {input} --output {output}"),
+            plantuml: String::from("plantuml -pipe -t{format}"),
+            format: DiagramFormat::default(),
+            keep_source: false,
+        }
+    }
+}
+
+impl DiagramSettings {
+    /// The command for the language of a code block, and the extension its source is written
+    /// with, if it's a diagram.
+    fn command(&self, language: &str) -> Option<(&str, &'static str)> {
+        match language {
+            "mermaid" => Some((&self.mermaid, "mmd")),
+            "plantuml" | "puml" => Some((&self.plantuml, "puml")),
+            _ => None,
+        }
+    }
+}
+
+/// Replaces every diagram in the page with an image of it, returning the images to attach.
+pub(crate) fn render_diagrams<'a>(
+    root: &'a AstNode<'a>,
+    page_path: &Path,
+    settings: &DiagramSettings,
+    cache_dir: &Path,
+) -> Result<Vec<Attachment>> {
+    let mut attachments = Vec::<Attachment>::default();
+    for node in root.descendants() {
+        let (language, literal) = match node.data.borrow().value {
+            NodeValue::CodeBlock(ref ncb) => (
+                ncb.info.split_whitespace().next().unwrap_or("").to_owned(),
+                ncb.literal.clone(),
+            ),
+            _ => continue,
+        };
+        let Some((command, source_extension)) = settings.command(&language) else {
+            continue;
+        };
+
+        let attachment = render_image(
+            command,
+            &literal,
+            source_extension,
+            settings.format,
+            &cache_dir.join(&language),
+            page_path,
+        )
+        .with_context(|| format!("Rendering {} diagram", language))?;
+
+        let mut storage = Vec::default();
+        write_diagram(
+            &mut storage,
+            &attachment.link.attachment_name(),
+            &language,
+            &literal,
+            settings.keep_source,
+        )?;
+        node.data.borrow_mut().value = NodeValue::Raw(String::from_utf8(storage)?);
+
+        add_attachment(&mut attachments, attachment);
+    }
+    Ok(attachments)
+}
+
+/// Adds an image to the page's attachments, unless the same image is already there.
+pub(crate) fn add_attachment(attachments: &mut Vec<Attachment>, attachment: Attachment) {
+    if !attachments
+        .iter()
+        .any(|a| a.link.text == attachment.link.text)
+    {
+        attachments.push(attachment);
+    }
+}
+
+/// Renders the source into an image with the command, returning it as an attachment of the page.
+///
+/// The image is named after the cache directory and a hash of the source, and is only rendered
+/// again if it's not already in the cache.
+pub(crate) fn render_image(
+    command: &str,
+    source: &str,
+    source_extension: &str,
+    format: DiagramFormat,
+    cache_dir: &Path,
+    page_path: &Path,
+) -> Result<Attachment> {
+    let image = render_to_cache(command, source, source_extension, format, cache_dir)?;
+    let name = match (
+        cache_dir.file_name().and_then(|name| name.to_str()),
+        image.file_name().and_then(|name| name.to_str()),
+    ) {
+        (Some(prefix), Some(name)) => format!("{}-{}", prefix, name),
+        _ => return Err(ConfluenceError::generic_error("Invalid image file name")),
+    };
+    Ok(Attachment::image(LocalLink {
+        page_path: page_path.to_owned(),
+        text: name,
+        target: image,
+        anchor: None,
+    }))
+}
+
+fn render_to_cache(
+    command: &str,
+    source: &str,
+    source_extension: &str,
+    format: DiagramFormat,
+    cache_dir: &Path,
+) -> Result<PathBuf> {
+    // the command is part of the hash, so changing the renderer renders everything again
+    let hash = sha256_digest(format!("{}\n{}", command, source).as_bytes())?;
+    let stem = hash[..16].to_lowercase();
+    let output = cache_dir.join(format!("{}.{}", stem, format.extension()));
+    if output.exists() {
+        return Ok(output);
+    }
+    create_dir_all(cache_dir)?;
+    let input = cache_dir.join(format!("{}.{}", stem, source_extension));
+    fs::write(&input, source)?;
+
+    let args: Vec<String> = shlex::split(&command.replace("{format}", format.extension()))
+        .filter(|args| !args.is_empty())
+        .ok_or_else(|| {
+            ConfluenceError::generic_error(format!("Invalid diagram command: {}", command))
+        })?
+        .into_iter()
+        .map(|arg| {
+            arg.replace("{input}", &input.to_string_lossy())
+                .replace("{output}", &output.to_string_lossy())
+        })
+        .collect();
+    let pipe_source = !command.contains("{input}");
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(if pipe_source {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            ConfluenceError::generic_error(format!("Failed to run {}: {}", args[0], err))
+        })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // a command that exits without reading everything is judged by its exit status below
+        if let Err(err) = stdin.write_all(source.as_bytes()) {
+            if err.kind() != ErrorKind::BrokenPipe {
+                return Err(err.into());
+            }
+        }
+    }
+    let result = child.wait_with_output()?;
+    if !result.status.success() {
+        return Err(ConfluenceError::generic_error(format!(
+            "{} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&result.stderr).trim()
+        )));
+    }
+    if !command.contains("{output}") {
+        fs::write(&output, &result.stdout)?;
+    }
+    if !output.exists() {
+        return Err(ConfluenceError::generic_error(format!(
+            "{} didn't write {}",
+            args[0],
+            output.display()
+        )));
+    }
+    Ok(output)
+}
+
+fn write_diagram(
+    output: &mut impl Write,
+    name: &str,
+    language: &str,
+    source: &str,
+    keep_source: bool,
+) -> Result<()> {
+    output.write_all(br#"<ac:image ac:align="center"><ri:attachment ri:filename=""#)?;
+    output.write_all(name.as_bytes())?;
+    output.write_all(b"\"/></ac:image>\n")?;
+    if keep_source {
+        render_expand(output, "[expand] Diagram source", true)?;
+        render_code_macro(output, &CodeBlockInfo::parse(language), source)?;
+        render_expand(output, "[expand] Diagram source", false)?;
+        output.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::{nodes::AstNode, Arena};
+
+    use crate::{
+        error::TestResult, link_generator::LinkGenerator, test_helpers::markdown_page_from_str,
+    };
+
+    use super::*;
+
+    fn echo_settings(keep_source: bool) -> DiagramSettings {
+        DiagramSettings {
+            mermaid: String::from("cat {input}"),
+            plantuml: String::from("cat"),
+            format: DiagramFormat::Svg,
+            keep_source,
+        }
+    }
+
+    #[test]
+    fn it_replaces_diagrams_with_attached_images() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let arena = Arena::<AstNode>::new();
+        let mut page = markdown_page_from_str(
+            "page.md",
+            "# Page\n```mermaid\ngraph TD; A-->B\n```\n\n```plantuml\n@startuml\nA -> B\n@enduml\n```\n\n```rust\nfn main() {}\n```\n",
+            &arena,
+        )?;
+
+        page.render_diagrams(
+            &PathBuf::from("page.md"),
+            &echo_settings(false),
+            temp.path(),
+        )?;
+        let rendered = page.render(&LinkGenerator::default_test())?;
+
+        assert_eq!(page.attachments.len(), 2);
+        let mermaid = &page.attachments[0];
+        assert!(mermaid.link.attachment_name().starts_with("mermaid-"));
+        assert!(mermaid.link.attachment_name().ends_with(".svg"));
+        assert_eq!(
+            fs::read_to_string(&mermaid.link.target)?,
+            "graph TD; A-->B\n"
+        );
+        assert!(rendered.content.contains(&format!(
+            "<ri:attachment ri:filename=\"{}\"/>",
+            mermaid.link.attachment_name()
+        )));
+        assert!(fs::read_to_string(&page.attachments[1].link.target)?.contains("A -> B"));
+        assert!(rendered.content.contains("fn main() {}"));
+        assert!(!rendered.content.contains("graph TD"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_the_source_in_an_expand() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let arena = Arena::<AstNode>::new();
+        let mut page = markdown_page_from_str(
+            "page.md",
+            "# Page\n```mermaid\ngraph TD; A-->B\n```\n",
+            &arena,
+        )?;
+
+        page.render_diagrams(&PathBuf::from("page.md"), &echo_settings(true), temp.path())?;
+        let rendered = page.render(&LinkGenerator::default_test())?;
+
+        assert!(rendered
+            .content
+            .contains("<ac:parameter ac:name=\"title\">Diagram source</ac:parameter>"));
+        assert!(rendered.content.contains("<![CDATA[graph TD; A-->B]]>"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_a_failed_command() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let arena = Arena::<AstNode>::new();
+        let mut page = markdown_page_from_str(
+            "page.md",
+            "# Page\n```mermaid\ngraph TD; A-->B\n```\n",
+            &arena,
+        )?;
+        let settings = DiagramSettings {
+            mermaid: String::from("false"),
+            ..DiagramSettings::default()
+        };
+
+        let err = page
+            .render_diagrams(&PathBuf::from("page.md"), &settings, temp.path())
+            .unwrap_err();
+
+        assert!(format!("{:#}", err).contains("Rendering mermaid diagram: false failed"));
+        Ok(())
+    }
+}
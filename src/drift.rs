@@ -0,0 +1,361 @@
+//! Detects pages that were edited in Confluence since marked-space last synced them.
+//!
+//! The sync state file records the version of each page that marked-space last wrote, and
+//! failing that every version marked-space writes has a message starting with
+//! `ConfluencePageData::version_message_prefix()`. A later version than that means someone else
+//! has changed the page since, whatever its message. That change only matters if it touched the
+//! body, so the body of the latest version is compared with the body of the last version
+//! marked-space wrote.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use clap::ValueEnum;
+use similar::TextDiff;
+
+use crate::{
+    checksum::sha256_digest,
+    confluence_client::ConfluenceClient,
+    confluence_page::{ConfluenceNode, ConfluencePageData},
+    confluence_paginator::ConfluencePaginator,
+    confluence_space::ConfluenceSpace,
+    console::{print_diff, print_warning},
+    error::{ConfluenceError, Result},
+    link_generator::LinkGenerator,
+    markdown_page::MarkdownPage,
+    responses::{self, BodySingle, PageSingleWithBody},
+    sync_state::SyncState,
+    Args,
+};
+
+/// What to do with a page that was edited in Confluence since it was last synced.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DriftPolicy {
+    /// Warn about the edit, then overwrite it.
+    #[default]
+    Warn,
+    /// Warn about the edit and leave the page's content as it is.
+    Skip,
+    /// Overwrite the edit without checking for it.
+    Overwrite,
+    /// Stop before changing anything.
+    Fail,
+}
+
+#[derive(Debug)]
+pub struct Drift {
+    pub page_id: String,
+    pub source: String,
+    pub title: String,
+    /// The last version written by marked-space.
+    pub synced_version: i32,
+    pub current_version: i32,
+    synced_body: String,
+    current_body: String,
+}
+
+/// Storage format is usually a single line, so break it up between tags to get a readable diff.
+fn diffable(storage: &str) -> String {
+    storage.replace("><", ">\n<")
+}
+
+impl Drift {
+    pub fn diff(&self) -> String {
+        TextDiff::from_lines(&diffable(&self.synced_body), &diffable(&self.current_body))
+            .unified_diff()
+            .context_radius(2)
+            .header(
+                &format!("{} (version {})", self.source, self.synced_version),
+                &format!("Confluence (version {})", self.current_version),
+            )
+            .to_string()
+    }
+}
+
+fn page_body(page: PageSingleWithBody) -> String {
+    match page.body {
+        BodySingle::Storage(body) => body.value,
+        _ => String::new(),
+    }
+}
+
+/// The page's drift since `recorded_version`, the version the sync state file says marked-space
+/// last wrote, or since the last version with marked-space's message if there's no state.
+fn page_drift(
+    client: &ConfluenceClient,
+    node: &ConfluenceNode,
+    source: &str,
+    recorded_version: Option<i32>,
+) -> Result<Option<Drift>> {
+    let Some(page_data) = node.page_data() else {
+        return Ok(None);
+    };
+    let synced_version = match recorded_version {
+        Some(recorded_version) if recorded_version >= page_data.version.number => return Ok(None),
+        Some(recorded_version) => recorded_version,
+        None if page_data.is_managed() || page_data.version.number <= 1 => return Ok(None),
+        None => {
+            let response = client.get_page_versions(&node.id)?.error_for_status()?;
+            let versions = ConfluencePaginator::<responses::Version>::new(client)
+                .start(response)?
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            let synced_version = versions
+                .iter()
+                .filter(|v| {
+                    v.message
+                        .starts_with(ConfluencePageData::version_message_prefix())
+                })
+                .map(|v| v.number)
+                .max();
+            match synced_version {
+                Some(synced_version) => synced_version,
+                // Never synced by marked-space, ie an existing page being taken over by title.
+                None => return Ok(None),
+            }
+        }
+    };
+
+    let current_body = page_body(
+        client
+            .get_page_with_body(&node.id)?
+            .error_for_status()?
+            .json()?,
+    );
+    let synced_body = page_body(
+        client
+            .get_page_version_with_body(&node.id, synced_version)?
+            .error_for_status()?
+            .json()?,
+    );
+    if sha256_digest(current_body.as_bytes())? == sha256_digest(synced_body.as_bytes())? {
+        // Only the title or location changed, which sync will put back anyway.
+        return Ok(None);
+    }
+
+    Ok(Some(Drift {
+        page_id: node.id.clone(),
+        source: String::from(source),
+        title: node.title.clone(),
+        synced_version,
+        current_version: page_data.version.number,
+        synced_body,
+        current_body,
+    }))
+}
+
+/// Finds the markdown pages whose Confluence page was edited since it was last synced.
+pub(crate) fn find_drift(
+    markdown_pages: &[MarkdownPage],
+    space: &ConfluenceSpace,
+    link_generator: &LinkGenerator,
+    client: &ConfluenceClient,
+    sync_state: &SyncState,
+) -> Result<Vec<Drift>> {
+    let mut drifted = Vec::new();
+    for markdown_page in markdown_pages.iter().filter(|p| !p.is_folder()) {
+        let node = link_generator
+            .get_file_id(&PathBuf::from(&markdown_page.source))
+            .and_then(|id| space.get_existing_node(&id));
+        if let Some(node) = node {
+            let recorded_version = sync_state.synced_version(&node.id);
+            if let Some(drift) = page_drift(client, &node, &markdown_page.source, recorded_version)?
+            {
+                drifted.push(drift);
+            }
+        }
+    }
+    Ok(drifted)
+}
+
+/// Applies the --on-drift policy, returning the IDs of pages whose content shouldn't be updated.
+pub(crate) fn check_drift(
+    markdown_pages: &[MarkdownPage],
+    space: &ConfluenceSpace,
+    link_generator: &LinkGenerator,
+    client: &ConfluenceClient,
+    sync_state: &SyncState,
+    args: &Args,
+) -> Result<HashSet<String>> {
+    if args.on_drift == DriftPolicy::Overwrite {
+        return Ok(HashSet::new());
+    }
+
+    let drifted = find_drift(markdown_pages, space, link_generator, client, sync_state)?;
+    for drift in drifted.iter() {
+        let action = match args.on_drift {
+            DriftPolicy::Skip => "its content will not be updated",
+            DriftPolicy::Fail => "refusing to overwrite it",
+            _ => "overwriting it (use --on-drift=skip to keep the changes)",
+        };
+        print_warning(&format!(
+            "[{}] \"{}\" was edited in Confluence after it was last synced (version {}, now version {}): {}",
+            drift.source, drift.title, drift.synced_version, drift.current_version, action
+        ));
+        if args.drift_diff {
+            print_diff(&drift.diff());
+        }
+    }
+
+    match args.on_drift {
+        DriftPolicy::Fail if !drifted.is_empty() => Err(ConfluenceError::generic_error(format!(
+            "{} page(s) were edited in Confluence since they were last synced. Bring the changes \
+             into the markdown, or use --on-drift=overwrite to discard them.",
+            drifted.len()
+        ))),
+        DriftPolicy::Skip => Ok(drifted.into_iter().map(|d| d.page_id).collect()),
+        _ => Ok(HashSet::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+    use serde_json::json;
+
+    use crate::{
+        confluence_page::ConfluenceNodeType,
+        error::TestResult,
+        responses::{ContentStatus, Version},
+    };
+
+    use super::*;
+
+    fn page_node(message: &str, number: i32) -> ConfluenceNode {
+        ConfluenceNode {
+            id: String::from("42"),
+            title: String::from("Drifting"),
+            parent_id: None,
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: String::from(message),
+                    number,
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        }
+    }
+
+    fn mock_versions(server: &mut mockito::Server) -> mockito::Mock {
+        server
+            .mock("GET", "/wiki/api/v2/pages/42/versions")
+            .match_query(Matcher::Any)
+            .with_body(
+                json!({
+                    "results": [
+                        {"number": 3, "message": ""},
+                        {"number": 2, "message": "updated by markedspace: source=drift.md; checksum=ABC"},
+                        {"number": 1, "message": ""},
+                    ],
+                    "_links": {}
+                })
+                .to_string(),
+            )
+            .create()
+    }
+
+    fn mock_body(server: &mut mockito::Server, version: Option<&str>, body: &str) -> mockito::Mock {
+        let query = match version {
+            Some(version) => Matcher::UrlEncoded(String::from("version"), String::from(version)),
+            None => Matcher::Exact(String::from("body-format=storage")),
+        };
+        server
+            .mock("GET", "/wiki/api/v2/pages/42")
+            .match_query(query)
+            .with_body(
+                json!({
+                    "id": "42",
+                    "title": "Drifting",
+                    "version": {"number": 3, "message": ""},
+                    "body": {"storage": {"representation": "storage", "value": body}}
+                })
+                .to_string(),
+            )
+            .create()
+    }
+
+    #[test]
+    fn it_ignores_pages_last_written_by_marked_space() -> TestResult {
+        let client = ConfluenceClient::new_insecure("localhost");
+        let node = page_node("updated by markedspace: source=drift.md; checksum=ABC", 5);
+
+        assert!(page_drift(&client, &node, "drift.md", None)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn it_detects_pages_edited_in_confluence() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let versions = mock_versions(&mut server);
+        let current = mock_body(&mut server, None, "<p>edited</p>");
+        let synced = mock_body(&mut server, Some("2"), "<p>original</p>");
+
+        let drift =
+            page_drift(&client, &page_node("", 3), "drift.md", None)?.expect("should have drifted");
+
+        versions.assert();
+        current.assert();
+        synced.assert();
+        assert_eq!(drift.synced_version, 2);
+        assert_eq!(drift.current_version, 3);
+        assert!(drift.diff().contains("-<p>original</p>"));
+        assert!(drift.diff().contains("+<p>edited</p>"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_ignores_edits_that_leave_the_body_unchanged() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let _versions = mock_versions(&mut server);
+        let _current = mock_body(&mut server, None, "<p>same</p>");
+        let _synced = mock_body(&mut server, Some("2"), "<p>same</p>");
+
+        assert!(page_drift(&client, &page_node("", 3), "drift.md", None)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn it_detects_edits_after_the_recorded_version_whatever_their_message() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let versions = mock_versions(&mut server).expect(0);
+        let _current = mock_body(&mut server, None, "<p>edited</p>");
+        let _synced = mock_body(&mut server, Some("2"), "<p>original</p>");
+        let node = page_node("updated by markedspace: source=drift.md; checksum=ABC", 3);
+
+        let drift = page_drift(&client, &node, "drift.md", Some(2))?.expect("should have drifted");
+
+        versions.assert();
+        assert_eq!(drift.synced_version, 2);
+        assert!(page_drift(&client, &node, "drift.md", Some(3))?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_if_the_versions_cannot_be_read() {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let _versions = server
+            .mock("GET", "/wiki/api/v2/pages/42/versions")
+            .match_query(Matcher::Any)
+            .with_body(
+                json!({
+                    "results": [{"number": 3, "message": ""}],
+                    "_links": {"next": "/wiki/api/v2/pages/42/versions?cursor=next"}
+                })
+                .to_string(),
+            )
+            .create();
+        let _next = server
+            .mock("GET", "/wiki/api/v2/pages/42/versions")
+            .match_query(Matcher::UrlEncoded(
+                String::from("cursor"),
+                String::from("next"),
+            ))
+            .with_status(500)
+            .create();
+
+        assert!(page_drift(&client, &page_node("", 3), "drift.md", None).is_err());
+    }
+}
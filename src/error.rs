@@ -24,6 +24,12 @@ pub enum ConfluenceError {
         local_links: String,
     },
 
+    #[error("Missing heading for link in [{source_file}] to [{anchors}]")]
+    MissingAnchor {
+        source_file: String,
+        anchors: String,
+    },
+
     #[error("Missing file for attachment link in [{source_file}] to [{attachment_paths}]")]
     MissingAttachmentLink {
         source_file: String,
@@ -6,12 +6,12 @@ use crate::{
     console::{print_status, Status::Updated},
     error::Result,
     link_generator::LinkGenerator,
-    markdown_page::MarkdownPage,
+    markdown_page::PageInfo,
     parent::get_parent_file,
 };
 
 pub fn sync_folder(
-    markdown_page: &MarkdownPage,
+    markdown_page: &PageInfo,
     link_generator: &LinkGenerator,
     space: &ConfluenceSpace,
     confluence_client: &ConfluenceClient,
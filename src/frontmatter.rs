@@ -2,8 +2,13 @@ use crate::page_covers::string_or_struct;
 use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 
-use crate::{page_covers::Cover, page_statuses::PageStatus, sort::Sort, Result};
-use std::io::{self, BufRead};
+use crate::{
+    page_covers::Cover, page_statuses::PageStatus, sort::Sort, tables::TableAttributes, Result,
+};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead},
+};
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
 #[serde(default)]
@@ -17,8 +22,14 @@ pub struct FrontMatter {
     pub unknown_keys: Vec<String>,
     pub imports: Vec<String>,
     pub folder: bool,
-    pub sort: Sort,
+    /// None if the page doesn't say, so that the space's default applies.
+    pub sort: Option<Sort>,
     pub status: Option<PageStatus>,
+    /// Names for links used in the page, ie `runbook: confluence://OPS/Runbook` for
+    /// `[the runbook](runbook)`.
+    pub links: HashMap<String, String>,
+    /// Attributes for every table in the page, which also lets them merge cells.
+    pub table: Option<TableAttributes>,
 }
 
 enum FrontMatterParseState {
@@ -36,9 +47,11 @@ impl Default for FrontMatter {
             unknown_keys: Vec::default(),
             imports: Vec::default(),
             folder: false,
-            sort: Sort::Unsorted,
+            sort: None,
             cover: Cover::default(),
             status: None,
+            links: HashMap::default(),
+            table: None,
         }
     }
 }
@@ -1,3 +1,5 @@
+use std::path::{Component, Path, PathBuf};
+
 use comrak::nodes::{AstNode, NodeCode, NodeValue};
 
 pub fn collect_text<'a>(node: &'a AstNode<'a>, output: &mut Vec<u8>) {
@@ -13,3 +15,48 @@ pub fn collect_text<'a>(node: &'a AstNode<'a>, output: &mut Vec<u8>) {
         }
     }
 }
+
+/// The path to `target` from the directory `from`, where both are relative to the same place,
+/// with `/` between its parts as in a markdown link.
+pub fn relative_path(from: &Path, target: &Path) -> String {
+    let from: Vec<Component> = from.components().collect();
+    let target: Vec<Component> = target.components().collect();
+    let common = from
+        .iter()
+        .zip(target.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut result = PathBuf::new();
+    for _ in common..from.len() {
+        result.push("..");
+    }
+    for component in &target[common..] {
+        result.push(component);
+    }
+    result.to_string_lossy().replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_makes_paths_relative() {
+        assert_eq!(
+            relative_path(Path::new("space/guides"), Path::new("space/tools.md")),
+            "../tools.md"
+        );
+        assert_eq!(
+            relative_path(Path::new("space"), Path::new("space/_includes/a.png")),
+            "_includes/a.png"
+        );
+        assert_eq!(
+            relative_path(Path::new("a/b"), Path::new("a/c/other.md")),
+            "../c/other.md"
+        );
+        assert_eq!(
+            relative_path(Path::new(""), Path::new("a/other.md")),
+            "a/other.md"
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Includes the markdown of another file in a page, ie a shared "prerequisites" section, with a
+//! comment on a line of its own:
+//!
+//! ```markdown
+//! <!-- include: _includes/prerequisites.md -->
+//! ```
+//!
+//! The included markdown is spliced into the page before its links and attachments are
+//! collected, with its links made relative to the page so that they still point at the same
+//! files. Files in `_includes` aren't pages themselves.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use comrak::{
+    nodes::{AstNode, NodeValue},
+    parse_document, Arena, Options,
+};
+use once_cell::sync::Lazy;
+use path_clean::PathClean;
+use regex::Regex;
+
+use crate::{containers::mark_containers, helpers::relative_path, local_link::LocalLink};
+
+/// The directory (in the space) for files that are only included, rather than being pages.
+pub const INCLUDES_DIR: &str = "_includes";
+
+static INCLUDE_DIRECTIVE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^<!--\s*include:\s*(.+?)\s*-->$").unwrap());
+
+fn include_path<'a>(node: &'a AstNode<'a>) -> Option<String> {
+    match node.data.borrow().value {
+        NodeValue::HtmlBlock(ref html_block) => INCLUDE_DIRECTIVE
+            .captures(html_block.literal.trim())
+            .map(|captures| String::from(&captures[1])),
+        _ => None,
+    }
+}
+
+pub(crate) struct Includer<'a, 'o> {
+    arena: &'a Arena<AstNode<'a>>,
+    options: &'o Options<'o>,
+    /// Files can't be included from outside of here.
+    repository_root: PathBuf,
+    /// The files being included, outermost first, to catch cycles.
+    stack: Vec<PathBuf>,
+    /// Every file included.
+    pub included: Vec<PathBuf>,
+    pub errors: Vec<String>,
+}
+
+impl<'a, 'o> Includer<'a, 'o> {
+    pub fn new(
+        arena: &'a Arena<AstNode<'a>>,
+        options: &'o Options<'o>,
+        repository_root: PathBuf,
+    ) -> Self {
+        Self {
+            arena,
+            options,
+            repository_root,
+            stack: Vec::default(),
+            included: Vec::default(),
+            errors: Vec::default(),
+        }
+    }
+
+    /// Replaces every include in the markdown of `file` with the markdown it includes.
+    pub fn expand(&mut self, root: &'a AstNode<'a>, file: &Path) {
+        self.stack
+            .push(fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file)));
+        let directives: Vec<(&'a AstNode<'a>, String)> = root
+            .descendants()
+            .filter_map(|node| include_path(node).map(|path| (node, path)))
+            .collect();
+        for (directive, path) in directives {
+            let included = file
+                .parent()
+                .map(|dir| dir.join(&path))
+                .unwrap_or_else(|| PathBuf::from(&path));
+            match self.include(&included, file) {
+                Ok(included_root) => {
+                    let children: Vec<&'a AstNode<'a>> = included_root.children().collect();
+                    for child in children {
+                        if !matches!(child.data.borrow().value, NodeValue::FrontMatter(_)) {
+                            directive.insert_before(child);
+                        }
+                    }
+                    directive.detach();
+                }
+                Err(err) => self.errors.push(format!("Including {}: {}", path, err)),
+            }
+        }
+        self.stack.pop();
+    }
+
+    fn include(&mut self, included: &Path, includer: &Path) -> Result<&'a AstNode<'a>, String> {
+        let canonical = fs::canonicalize(included).map_err(|err| err.to_string())?;
+        if !canonical.starts_with(&self.repository_root) {
+            return Err(String::from("file is outside of the repository"));
+        }
+        if let Some(start) = self.stack.iter().position(|file| *file == canonical) {
+            let cycle: Vec<String> = self.stack[start..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|file| file.display().to_string())
+                .collect();
+            return Err(format!("include cycle {}", cycle.join(" -> ")));
+        }
+
+        let content = fs::read_to_string(included).map_err(|err| err.to_string())?;
+        let included_root = parse_document(self.arena, &mark_containers(&content), self.options);
+        self.expand(included_root, included);
+        relink(included_root, included, includer);
+        self.included.push(canonical);
+        Ok(included_root)
+    }
+}
+
+/// Makes the local links in markdown from `included` relative to `includer`, where it's going.
+fn relink<'a>(root: &'a AstNode<'a>, included: &Path, includer: &Path) {
+    let included_dir = included.parent().unwrap_or(Path::new("")).clean();
+    let includer_dir = includer.parent().unwrap_or(Path::new("")).clean();
+    if included_dir == includer_dir {
+        return;
+    }
+    for node in root.descendants() {
+        if let NodeValue::Link(ref mut link) | NodeValue::Image(ref mut link) =
+            node.data.borrow_mut().value
+        {
+            if !LocalLink::is_local_link(&link.url) || link.url.starts_with('#') {
+                continue;
+            }
+            let (path, anchor) = match link.url.find('#') {
+                Some(hash) => link.url.split_at(hash),
+                None => (link.url.as_str(), ""),
+            };
+            let target = included_dir.join(path).clean();
+            link.url = format!("{}{}", relative_path(&includer_dir, &target), anchor);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::{
+        error::TestResult, markdown_page::MarkdownPage, template_renderer::TemplateRenderer,
+    };
+
+    use super::*;
+
+    #[test]
+    fn it_splices_included_markdown_into_the_page() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let space = temp.child("TEAM");
+        space
+            .child("guides/setup.md")
+            .write_str("# Setup\nBefore you start:\n\n<!-- include: ../_includes/prerequisites.md -->\n\nThen go.\n")?;
+        space.child("_includes/prerequisites.md").write_str(
+            "---\nignored: true\n---\n## Prerequisites\nSee [tools](../tools.md#install) and ![diagram](images/tools.png).\n",
+        )?;
+        space.child("_includes/images/tools.png").write_str("")?;
+        space.child("tools.md").write_str("# Tools")?;
+        let arena = Arena::<AstNode>::new();
+
+        let page = MarkdownPage::from_file(
+            space.path(),
+            space.child("guides/setup.md").path(),
+            &arena,
+            &mut TemplateRenderer::default()?,
+        )?;
+
+        assert_eq!(page.local_links.len(), 1);
+        assert_eq!(page.local_links[0].target, space.path().join("tools.md"));
+        assert_eq!(page.attachments.len(), 1);
+        assert_eq!(
+            page.attachments[0].link.target,
+            space.path().join("_includes/images/tools.png")
+        );
+        assert_eq!(page.dependencies.len(), 1);
+        let rendered = page.render(&crate::link_generator::LinkGenerator::default_test())?;
+        assert!(rendered.content.contains("<h2>Prerequisites</h2>"));
+        assert!(!rendered.content.contains("ignored"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_include_cycles() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let space = temp.child("TEAM");
+        space
+            .child("page.md")
+            .write_str("# Page\n<!-- include: _includes/a.md -->\n")?;
+        space
+            .child("_includes/a.md")
+            .write_str("A\n\n<!-- include: b.md -->\n")?;
+        space
+            .child("_includes/b.md")
+            .write_str("B\n\n<!-- include: a.md -->\n")?;
+        let arena = Arena::<AstNode>::new();
+
+        let err = MarkdownPage::from_file(
+            space.path(),
+            space.child("page.md").path(),
+            &arena,
+            &mut TemplateRenderer::default()?,
+        )
+        .err()
+        .unwrap();
+
+        let message = format!("{:#}", err);
+        assert!(message.contains("include cycle"), "{}", message);
+        assert!(message.contains("a.md -> "), "{}", message);
+        Ok(())
+    }
+}
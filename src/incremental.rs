@@ -0,0 +1,376 @@
+//! Limits a sync to the files that changed (--since and --only), and the pages that depend on
+//! them.
+//!
+//! Every page is still parsed and registered with the link generator, so that links resolve and
+//! orphans are archived as usual; only the pages to render and push are cut down.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    console::print_info,
+    error::{ConfluenceError, Result},
+    link_generator::LinkGenerator,
+    markdown_page::{MarkdownPage, PageInfo},
+    parent::get_parent_file,
+    sort::Sort,
+    Args,
+};
+
+/// The files (relative to the space directory) that --since and --only say have changed, or None
+/// if every page should be synced.
+pub(crate) fn changed_files(space_dir: &Path, args: &Args) -> Result<Option<Vec<PathBuf>>> {
+    if args.since.is_none() && args.only.is_empty() {
+        return Ok(None);
+    }
+
+    let space = fs::canonicalize(space_dir).unwrap_or_else(|_| PathBuf::from(space_dir));
+    let mut changed: Vec<PathBuf> = args
+        .only
+        .iter()
+        .map(|p| {
+            let p = PathBuf::from(p);
+            let p = p.strip_prefix(space_dir).map(PathBuf::from).unwrap_or(p);
+            // files outside the space (ie shared templates) are listed with their full path, as
+            // with --since
+            match fs::canonicalize(space_dir.join(&p)) {
+                Ok(full) if !full.starts_with(&space) => full,
+                _ => p,
+            }
+        })
+        .collect();
+    if let Some(ref since) = args.since {
+        changed.extend(git_changed_files(space_dir, since)?);
+    }
+    Ok(Some(changed))
+}
+
+fn git(space_dir: &Path, git_args: &[&str]) -> Result<Vec<PathBuf>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(space_dir)
+        .args(git_args)
+        .output()
+        .map_err(|err| ConfluenceError::generic_error(format!("Failed to run git: {}", err)))?;
+    if !output.status.success() {
+        return Err(ConfluenceError::generic_error(format!(
+            "git {} failed: {}",
+            git_args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Files in the space that differ from the revision, including uncommitted and untracked files.
+/// Renames are reported as a deletion and an addition, so both paths are included.
+///
+/// Changed files elsewhere in the repository, which pages can include as snippets, are listed
+/// with their full path.
+fn git_changed_files(space_dir: &Path, since: &str) -> Result<Vec<PathBuf>> {
+    let mut changed = git(
+        space_dir,
+        &[
+            "diff",
+            "--name-only",
+            "--relative",
+            "--no-renames",
+            since,
+            "--",
+        ],
+    )?;
+    changed.extend(git(
+        space_dir,
+        &["ls-files", "--others", "--exclude-standard"],
+    )?);
+
+    let space = fs::canonicalize(space_dir)?;
+    if let Some(top) = git(space_dir, &["rev-parse", "--show-toplevel"])?.pop() {
+        let top = fs::canonicalize(top)?;
+        changed.extend(
+            git(
+                space_dir,
+                &["diff", "--name-only", "--no-renames", since, "--"],
+            )?
+            .into_iter()
+            .map(|path| top.join(path))
+            .filter(|path| !path.starts_with(&space)),
+        );
+    }
+    Ok(changed)
+}
+
+fn is_changed(changed: &[PathBuf], path: &Path) -> bool {
+    changed.iter().any(|c| path.starts_with(c))
+}
+
+fn space_relative(space_dir: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(space_dir)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(path))
+}
+
+/// True if the page must be rendered again because of a change to something it depends on: a
+/// page it links to (whose title may have changed), including with a wiki link, an attachment, an
+/// imported template, or an included file.
+fn depends_on_changes(
+    page: &PageInfo,
+    changed: &[PathBuf],
+    space_dir: &Path,
+    link_generator: &LinkGenerator,
+) -> bool {
+    let space = fs::canonicalize(space_dir).unwrap_or_else(|_| PathBuf::from(space_dir));
+    // a wiki link that doesn't resolve may be to a page that was retitled
+    page.wiki_links
+        .iter()
+        .any(|url| match link_generator.get_wiki_link_file(url) {
+            Some(file) => is_changed(changed, Path::new(&file)),
+            None => true,
+        })
+        || page
+            .local_links
+            .iter()
+            .map(|link| &link.target)
+            .chain(page.attachments.iter().map(|a| &a.link.target))
+            .any(|target| is_changed(changed, &space_relative(space_dir, target)))
+        || page
+            .front_matter
+            .imports
+            .iter()
+            .any(|import| is_changed(changed, &Path::new("_tera").join(import)))
+        || page.dependencies.iter().any(|dependency| {
+            is_changed(changed, dependency)
+                || is_changed(changed, &space_relative(&space, dependency))
+        })
+}
+
+/// The sources of the pages to sync, given the files that changed.
+pub(crate) fn pages_to_sync(
+    markdown_pages: &[MarkdownPage],
+    changed: &[PathBuf],
+    space_dir: &Path,
+    link_generator: &LinkGenerator,
+) -> HashSet<String> {
+    let mut selected: HashSet<String> = markdown_pages
+        .iter()
+        .filter(|page| {
+            let source = PathBuf::from(&page.source);
+            is_changed(changed, &source)
+                // pages that don't exist in Confluence yet need their content either way
+                || link_generator.get_file_id(&source).is_none()
+                || depends_on_changes(page, changed, space_dir, link_generator)
+        })
+        .map(|page| page.source.clone())
+        .collect();
+
+    // A new or retitled page can change the order of its siblings.
+    let parents: Vec<String> = selected
+        .iter()
+        .map(|source| source.replace('\\', "/"))
+        .filter(|source| source != "index.md")
+        .map(|source| get_parent_file(Path::new(&source)).unwrap_or(PathBuf::from("index.md")))
+        .filter_map(|parent| parent.to_str().map(|s| s.replace('\\', "/")))
+        .collect();
+    for page in markdown_pages {
+        if page.front_matter.sort == Some(Sort::Incrementing)
+            && parents.contains(&page.source.replace('\\', "/"))
+        {
+            selected.insert(page.source.clone());
+        }
+    }
+
+    selected
+}
+
+/// Removes the pages that don't need to be synced, if --since or --only were given.
+pub(crate) fn retain_changed_pages(
+    markdown_pages: &mut Vec<MarkdownPage>,
+    changed: &Option<Vec<PathBuf>>,
+    space_dir: &Path,
+    link_generator: &LinkGenerator,
+) {
+    let Some(changed) = changed else {
+        return;
+    };
+    let selected = pages_to_sync(markdown_pages, changed, space_dir, link_generator);
+    let total = markdown_pages.len();
+    markdown_pages.retain(|page| selected.contains(&page.source));
+    print_info(&format!(
+        "Limited to {} of {} pages, for the files that changed and the pages that depend on them",
+        markdown_pages.len(),
+        total
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+    use comrak::{nodes::AstNode, Arena};
+
+    use crate::{
+        confluence_client::ConfluenceClient,
+        error::TestResult,
+        markdown_space::MarkdownSpace,
+        template_renderer::TemplateRenderer,
+        test_helpers::{markdown_page_from_str, register_mark_and_conf_page},
+    };
+
+    use super::*;
+
+    fn sorted(selected: HashSet<String>) -> Vec<String> {
+        let mut selected: Vec<String> = selected.into_iter().collect();
+        selected.sort();
+        selected
+    }
+
+    #[test]
+    fn it_selects_changed_pages_and_their_dependents() -> TestResult {
+        let arena = Arena::<AstNode>::new();
+        let mut link_generator = LinkGenerator::default_test();
+        let mut pages = [
+            ("index.md", "---\nsort: inc\n---\n# Home"),
+            ("changed.md", "# Changed"),
+            ("linking.md", "# Linking\n[link](changed.md)"),
+            ("wiki_title.md", "# Wiki Title\n[[Changed]]"),
+            ("wiki_file.md", "# Wiki File\n[[changed|the changed page]]"),
+            ("wiki_other.md", "# Wiki Other\n[[Unrelated]]"),
+            ("image.md", "# Image\n![image](image.png)"),
+            ("macros.md", "# Macros"),
+            ("snippet.md", "# Snippet"),
+            ("unrelated.md", "# Unrelated"),
+            ("sub/index.md", "# Sub"),
+            ("sub/child.md", "# Child"),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(id, (source, content))| {
+            register_mark_and_conf_page(
+                &id.to_string(),
+                &mut link_generator,
+                markdown_page_from_str(source, content, &arena)?,
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+        // set directly, as rendering with the import needs the template to exist
+        pages[7].front_matter.imports = vec![String::from("adr.md")];
+        pages[8].dependencies = vec![PathBuf::from("/repo/src/main.rs")];
+
+        let changed = vec![
+            PathBuf::from("changed.md"),
+            PathBuf::from("image.png"),
+            PathBuf::from("_tera/adr.md"),
+            PathBuf::from("/repo/src/main.rs"),
+        ];
+        let selected = pages_to_sync(&pages, &changed, Path::new(""), &link_generator);
+
+        assert_eq!(
+            sorted(selected),
+            vec![
+                "changed.md",
+                "image.md",
+                "index.md",
+                "linking.md",
+                "macros.md",
+                "snippet.md",
+                "wiki_file.md",
+                "wiki_title.md"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_selects_pages_importing_a_changed_shared_template() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("shared/adr.md")
+            .write_str("{% macro status() %}Accepted{% endmacro status %}")?;
+        temp.child("TEAM/marked-space.yml")
+            .write_str("templates: [../shared]\n")?;
+        temp.child("TEAM/index.md")
+            .write_str("---\nimports: [adr.md]\n---\n# Home\n{{ adr::status() }}\n")?;
+        temp.child("TEAM/other.md").write_str("# Other\n")?;
+        let mut space = MarkdownSpace::from_directory(temp.child("TEAM").path())?;
+        let mut template_renderer =
+            TemplateRenderer::new(&space, &ConfluenceClient::new_insecure("localhost"))?;
+        let space_dir = space.dir.clone();
+        let mut link_generator = LinkGenerator::default_test();
+        let pages = space
+            .parse(&mut template_renderer)?
+            .into_iter()
+            .enumerate()
+            .map(|(id, page)| {
+                register_mark_and_conf_page(&id.to_string(), &mut link_generator, page)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let changed = vec![fs::canonicalize(temp.child("shared/adr.md").path())?];
+        let selected = pages_to_sync(&pages, &changed, &space_dir, &link_generator);
+
+        assert_eq!(sorted(selected), vec!["index.md"]);
+        Ok(())
+    }
+
+    #[test]
+    fn it_selects_pages_not_yet_in_confluence() -> TestResult {
+        let arena = Arena::<AstNode>::new();
+        let pages = vec![markdown_page_from_str("new.md", "# New", &arena)?];
+        let mut link_generator = LinkGenerator::default_test();
+        link_generator.register_markdown_page(&pages[0])?;
+
+        let selected = pages_to_sync(&pages, &[], Path::new(""), &link_generator);
+
+        assert_eq!(sorted(selected), vec!["new.md"]);
+        Ok(())
+    }
+
+    #[test]
+    fn it_lists_files_changed_since_a_git_revision() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let space_dir = temp.child("TEAM");
+        space_dir.child("index.md").write_str("# Home")?;
+        space_dir.child("moved.md").write_str("# Moved")?;
+        space_dir.child("unchanged.md").write_str("# Unchanged")?;
+        temp.child("src/main.rs").write_str("fn main() {}")?;
+        let run = |args: &[&str]| -> Result<Vec<PathBuf>> { git(temp.path(), args) };
+        run(&["init", "--quiet"])?;
+        run(&["add", "."])?;
+        run(&[
+            "-c",
+            "user.name=test",
+            "-c",
+            "user.email=test@example.com",
+            "commit",
+            "--quiet",
+            "-m",
+            "initial",
+        ])?;
+        space_dir.child("index.md").write_str("# Changed Home")?;
+        run(&["mv", "TEAM/moved.md", "TEAM/renamed.md"])?;
+        space_dir.child("new.md").write_str("# New")?;
+        temp.child("src/main.rs")
+            .write_str("fn main() { todo!() }")?;
+
+        let mut changed = git_changed_files(space_dir.path(), "HEAD")?;
+        changed.sort();
+
+        assert_eq!(
+            changed,
+            vec![
+                fs::canonicalize(temp.child("src/main.rs").path())?,
+                PathBuf::from("index.md"),
+                PathBuf::from("moved.md"),
+                PathBuf::from("new.md"),
+                PathBuf::from("renamed.md"),
+            ]
+        );
+        Ok(())
+    }
+}
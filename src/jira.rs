@@ -0,0 +1,225 @@
+//! Jira issue keys in the text of a page, ie ABC-123, rendered as Jira issue macros, for the
+//! projects listed under `jira` in marked-space.yml.
+//!
+//! Only the macro is written, so it's Confluence that looks the issues up, and --check doesn't
+//! need to reach Jira.
+
+use comrak::{
+    nodes::{AstNode, NodeValue},
+    Arena,
+};
+use once_cell::sync::Lazy;
+use quick_xml::escape::escape;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    error::{ConfluenceError, Result},
+    xhtml::inside_storage,
+};
+
+static PROJECT_KEY: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Z][A-Z0-9_]+$").unwrap());
+
+/// Links to Jira, from `jira` in marked-space.yml.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct JiraSettings {
+    /// The keys of the projects whose issues are linked, ie ABC for ABC-123.
+    pub projects: Vec<String>,
+    /// The name of the Jira server, if Confluence is linked to more than one.
+    pub server: Option<String>,
+    pub server_id: Option<String>,
+}
+
+impl JiraSettings {
+    /// Checks that the projects are Jira project keys, as anything else would make a pattern
+    /// that matches more than their issues.
+    pub fn validate(&self) -> Result<()> {
+        match self.projects.iter().find(|p| !PROJECT_KEY.is_match(p)) {
+            Some(project) => Err(ConfluenceError::generic_error(format!(
+                "'{}' in jira projects is not a Jira project key, which is an upper case letter \
+                 followed by upper case letters, digits or underscores",
+                project
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// The linker for the issues of the projects, or None if there are no projects.
+    pub(crate) fn linker(&self) -> Option<JiraLinker<'_>> {
+        if self.projects.is_empty() {
+            return None;
+        }
+        let projects: Vec<String> = self
+            .projects
+            .iter()
+            .map(|project| regex::escape(project))
+            .collect();
+        let issue_key = Regex::new(&format!(r"\b(?:{})-[0-9]+\b", projects.join("|"))).unwrap();
+        Some(JiraLinker {
+            settings: self,
+            issue_key,
+        })
+    }
+
+    fn issue_macro(&self, key: &str) -> String {
+        let mut storage = format!(
+            "<ac:structured-macro ac:name=\"jira\" ac:schema-version=\"1\"><ac:parameter ac:name=\"key\">{}</ac:parameter>",
+            escape(key)
+        );
+        if let Some(ref server) = self.server {
+            storage.push_str(&format!(
+                "<ac:parameter ac:name=\"server\">{}</ac:parameter>",
+                escape(server)
+            ));
+        }
+        if let Some(ref server_id) = self.server_id {
+            storage.push_str(&format!(
+                "<ac:parameter ac:name=\"serverId\">{}</ac:parameter>",
+                escape(server_id)
+            ));
+        }
+        storage.push_str("</ac:structured-macro>");
+        storage
+    }
+}
+
+/// Links the issues of the configured projects, with the pattern for their keys built once for
+/// the whole space.
+pub(crate) struct JiraLinker<'s> {
+    settings: &'s JiraSettings,
+    issue_key: Regex,
+}
+
+/// Replaces the issue keys in the text of the page with Jira issue macros, except in links,
+/// headings and code.
+pub(crate) fn link_jira_issues<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    root: &'a AstNode<'a>,
+    linker: &JiraLinker,
+) {
+    let texts: Vec<(&'a AstNode<'a>, String)> = root
+        .descendants()
+        .filter_map(|node| match node.data.borrow().value {
+            // text from templates can be in storage markup, ie a macro's parameters
+            NodeValue::Text(ref text) if !inside_storage(node) => Some((node, text.clone())),
+            _ => None,
+        })
+        .filter(|(node, _)| {
+            !node.ancestors().any(|ancestor| {
+                matches!(
+                    ancestor.data.borrow().value,
+                    NodeValue::Link(_)
+                        | NodeValue::Image(_)
+                        | NodeValue::WikiLink(_)
+                        | NodeValue::Heading(_)
+                )
+            })
+        })
+        .collect();
+    for (node, text) in texts {
+        let mut last = 0;
+        for issue in linker.issue_key.find_iter(&text) {
+            if issue.start() > last {
+                node.insert_before(
+                    arena.alloc(NodeValue::Text(String::from(&text[last..issue.start()])).into()),
+                );
+            }
+            node.insert_before(
+                arena.alloc(NodeValue::Raw(linker.settings.issue_macro(issue.as_str())).into()),
+            );
+            last = issue.end();
+        }
+        if last == text.len() {
+            node.detach();
+        } else if last > 0 {
+            node.data.borrow_mut().value = NodeValue::Text(String::from(&text[last..]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::{nodes::AstNode, Arena};
+
+    use crate::{
+        error::TestResult, link_generator::LinkGenerator, test_helpers::markdown_page_from_str,
+    };
+
+    use super::*;
+
+    #[test]
+    fn it_links_issues_in_the_listed_projects() -> TestResult {
+        let arena = Arena::<AstNode>::new();
+        let mut page = markdown_page_from_str(
+            "page.md",
+            "# Page\n## Fixing ABC-1\nABC-12 and *XYZ-3* but not DEF-4, XABC-5, `ABC-6` or [ABC-7](https://example.com).\n",
+            &arena,
+        )?;
+        let settings = JiraSettings {
+            projects: vec![String::from("ABC"), String::from("XYZ")],
+            server_id: Some(String::from("1234")),
+            ..JiraSettings::default()
+        };
+
+        page.link_jira_issues(&arena, &settings.linker().unwrap());
+        let rendered = page.render(&LinkGenerator::default_test())?;
+
+        assert_eq!(
+            rendered.content.trim(),
+            r#"<h2>Fixing ABC-1</h2>
+<p><ac:structured-macro ac:name="jira" ac:schema-version="1"><ac:parameter ac:name="key">ABC-12</ac:parameter><ac:parameter ac:name="serverId">1234</ac:parameter></ac:structured-macro> and <em><ac:structured-macro ac:name="jira" ac:schema-version="1"><ac:parameter ac:name="key">XYZ-3</ac:parameter><ac:parameter ac:name="serverId">1234</ac:parameter></ac:structured-macro></em> but not DEF-4, XABC-5, <code>ABC-6</code> or <a href="https://example.com">ABC-7</a>.</p>"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_leaves_macro_parameters_from_templates_alone() -> TestResult {
+        let arena = Arena::<AstNode>::new();
+        let mut page = markdown_page_from_str(
+            "page.md",
+            "# Page\n{{ jql_table(jql='key = ABC-1') }} and ABC-2\n",
+            &arena,
+        )?;
+        let settings = JiraSettings {
+            projects: vec![String::from("ABC")],
+            ..JiraSettings::default()
+        };
+
+        page.link_jira_issues(&arena, &settings.linker().unwrap());
+        let rendered = page.render(&LinkGenerator::default_test())?;
+
+        assert!(rendered
+            .content
+            .contains("<ac:parameter ac:name=\"jqlQuery\">key = ABC-1</ac:parameter>"));
+        assert!(rendered.content.contains(
+            "</ac:structured-macro> and <ac:structured-macro ac:name=\"jira\" ac:schema-version=\"1\"><ac:parameter ac:name=\"key\">ABC-2</ac:parameter>"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn it_links_nothing_without_projects() {
+        assert!(JiraSettings::default().linker().is_none());
+    }
+
+    #[test]
+    fn it_rejects_keys_that_are_not_project_keys() {
+        for project in ["", "abc", "A", "AB-C", "1AB"] {
+            let settings = JiraSettings {
+                projects: vec![String::from("ABC"), String::from(project)],
+                ..JiraSettings::default()
+            };
+            assert!(
+                settings.validate().is_err(),
+                "{:?} should be rejected",
+                project
+            );
+        }
+        let settings = JiraSettings {
+            projects: vec![String::from("ABC"), String::from("X2_Y")],
+            ..JiraSettings::default()
+        };
+        assert!(settings.validate().is_ok());
+    }
+}
@@ -0,0 +1,101 @@
+//! Runs work on several pages at once, for --jobs.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::error::Result;
+
+/// Calls `f` for every item using up to `jobs` threads, returning the first error.
+///
+/// Once an item has failed no further items are started, but those already running are allowed
+/// to finish so that they aren't left half synced.
+pub(crate) fn run_jobs<T, F>(jobs: usize, items: &[T], f: F) -> Result<()>
+where
+    T: Sync,
+    F: Fn(&T) -> Result<()> + Sync,
+{
+    let jobs = jobs.clamp(1, items.len().max(1));
+    if jobs == 1 {
+        return items.iter().try_for_each(f);
+    }
+
+    let next = AtomicUsize::new(0);
+    let first_error = Mutex::new(None);
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                if first_error.lock().unwrap().is_some() {
+                    break;
+                }
+                let Some(item) = items.get(next.fetch_add(1, Ordering::SeqCst)) else {
+                    break;
+                };
+                if let Err(err) = f(item) {
+                    first_error.lock().unwrap().get_or_insert(err);
+                }
+            });
+        }
+    });
+
+    match first_error.into_inner().unwrap() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use crate::error::{ConfluenceError, TestResult};
+
+    use super::*;
+
+    #[test]
+    fn it_runs_every_item() -> TestResult {
+        let items: Vec<usize> = (1..=20).collect();
+        let total = AtomicUsize::new(0);
+
+        run_jobs(4, &items, |i| {
+            total.fetch_add(*i, Ordering::SeqCst);
+            Ok(())
+        })?;
+
+        assert_eq!(total.into_inner(), 210);
+        Ok(())
+    }
+
+    #[test]
+    fn it_returns_the_error_from_a_failed_item() {
+        let items: Vec<usize> = (1..=20).collect();
+
+        let result = run_jobs(4, &items, |i| {
+            if *i == 7 {
+                Err(ConfluenceError::generic_error("page 7 failed"))
+            } else {
+                Ok(())
+            }
+        });
+
+        assert_eq!(result.unwrap_err().to_string(), "page 7 failed");
+    }
+
+    #[test]
+    fn it_treats_zero_jobs_as_one() -> TestResult {
+        let items = vec![1, 2, 3];
+        let total = AtomicUsize::new(0);
+
+        run_jobs(0, &items, |i| {
+            total.fetch_add(*i, Ordering::SeqCst);
+            Ok(())
+        })?;
+
+        assert_eq!(total.into_inner(), 6);
+        Ok(())
+    }
+}
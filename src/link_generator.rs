@@ -5,15 +5,18 @@ use std::{
     path::Path,
 };
 
-use comrak::nodes::NodeLink;
+use comrak::nodes::{NodeLink, NodeWikiLink};
 
 use crate::{
+    anchors::{confluence_anchor, find_heading},
+    confluence_links::ConfluenceLink,
     confluence_page::{ConfluenceNode, ConfluenceNodeType, ConfluencePageData},
     confluence_storage_renderer::{escape_href, ConfluenceStorageRenderer},
     console::print_warning,
     error::{ConfluenceError, Result},
     local_link::LocalLink,
     markdown_page::MarkdownPage,
+    wiki_links::{resolve_wiki_link, split_anchor},
 };
 
 #[derive(Debug)]
@@ -23,10 +26,10 @@ pub struct LinkGenerator {
     homepage_id: String,
     filename_to_id: HashMap<String, String>,
     filename_to_title: HashMap<String, String>,
+    filename_to_headings: HashMap<String, Vec<String>>,
     title_to_file: HashMap<String, String>,
     title_to_id: HashMap<String, String>,
     folders: HashSet<String>,
-    page_attachment_pair_to_id: HashMap<(String, String), String>,
 }
 
 impl LinkGenerator {
@@ -37,10 +40,10 @@ impl LinkGenerator {
             homepage_id: homepage_id.into(),
             filename_to_id: HashMap::default(),
             filename_to_title: HashMap::default(),
+            filename_to_headings: HashMap::default(),
             title_to_file: HashMap::default(),
             title_to_id: HashMap::default(),
             folders: HashSet::default(),
-            page_attachment_pair_to_id: HashMap::default(),
         }
     }
 
@@ -67,6 +70,8 @@ impl LinkGenerator {
 
         self.filename_to_title
             .insert(filename.clone(), title.clone());
+        self.filename_to_headings
+            .insert(filename.clone(), markdown_page.headings.clone());
 
         Ok(())
     }
@@ -140,7 +145,7 @@ impl LinkGenerator {
         )
     }
 
-    fn get_page_url(&self, filename: &Path) -> Option<String> {
+    pub(crate) fn get_page_url(&self, filename: &Path) -> Option<String> {
         if filename == Path::new("index.md") {
             return Some(self.id_to_url(&self.homepage_id));
         }
@@ -152,6 +157,23 @@ impl LinkGenerator {
         }
     }
 
+    pub(crate) fn get_title_file(&self, title: &str) -> Option<String> {
+        self.title_to_file.get(title).cloned()
+    }
+
+    /// The file a `[[wiki link]]` is to, by title or by file name.
+    pub(crate) fn get_wiki_link_file(&self, url: &str) -> Option<String> {
+        resolve_wiki_link(url, &self.title_to_file, &self.filename_to_title)
+    }
+
+    /// The anchor Confluence has for the heading of the page that the link's anchor is for.
+    fn get_heading_anchor(&self, path: &Path, anchor: &str) -> Option<String> {
+        let file = Self::path_to_string(path).ok()?;
+        let title = self.filename_to_title.get(&file)?;
+        let heading = find_heading(title, self.filename_to_headings.get(&file)?, anchor)?;
+        Some(confluence_anchor(title, heading))
+    }
+
     fn get_file_title(&self, path: &Path) -> Option<String> {
         let s = Self::path_to_string(path).unwrap();
         self.filename_to_title.get(&s).cloned()
@@ -163,6 +185,12 @@ impl LinkGenerator {
         confluence_formatter: &mut ConfluenceStorageRenderer,
         no_children: bool,
     ) -> io::Result<()> {
+        if ConfluenceLink::is_confluence_link(&nl.url) {
+            // checked when the page was parsed
+            let link = ConfluenceLink::from_url(&nl.url).map_err(io::Error::other)?;
+            return link.write_enter(&mut confluence_formatter.output, no_children);
+        }
+
         // Links Confluence resolves itself: external URLs, and mailto: links which it renders
         // as an ordinary anchor.
         if !LocalLink::is_local_link(&nl.url) {
@@ -187,7 +215,10 @@ impl LinkGenerator {
             if let Some(anchor) = local_link.anchor {
                 link_empty = false;
                 confluence_formatter.output.write_all(b"#")?;
-                confluence_formatter.output.write_all(anchor.as_bytes())?;
+                let anchor = self
+                    .get_heading_anchor(&local_link.target, &anchor)
+                    .unwrap_or(anchor);
+                escape_href(&mut confluence_formatter.output, anchor.as_bytes())?;
             }
 
             if link_empty {
@@ -224,7 +255,9 @@ impl LinkGenerator {
         nl: &NodeLink,
         confluence_formatter: &mut ConfluenceStorageRenderer,
     ) -> io::Result<()> {
-        if !LocalLink::is_local_link(&nl.url) {
+        if ConfluenceLink::is_confluence_link(&nl.url) {
+            ConfluenceLink::write_exit(&mut confluence_formatter.output)?;
+        } else if !LocalLink::is_local_link(&nl.url) {
             confluence_formatter.output.write_all(b"</a>")?;
         } else {
             let local_link = relative_local_link(nl, confluence_formatter);
@@ -236,6 +269,35 @@ impl LinkGenerator {
         Ok(())
     }
 
+    pub fn enter_wiki_link(
+        &self,
+        nwl: &NodeWikiLink,
+        confluence_formatter: &mut ConfluenceStorageRenderer,
+    ) -> io::Result<()> {
+        confluence_formatter.output.write_all(b"<a href=\"")?;
+        let (_, anchor) = split_anchor(&nwl.url);
+        let url = self
+            .get_wiki_link_file(&nwl.url)
+            .and_then(|file| self.get_page_url(Path::new(&file)));
+        match url {
+            Some(url) => confluence_formatter.output.write_all(url.as_bytes())?,
+            None => print_warning(&format!(
+                "wiki link [[{}]] in {} couldn't be resolved",
+                nwl.url,
+                confluence_formatter.source.display(),
+            )),
+        }
+        if let Some(anchor) = anchor {
+            confluence_formatter.output.write_all(b"#")?;
+            let anchor = self
+                .get_wiki_link_file(&nwl.url)
+                .and_then(|file| self.get_heading_anchor(Path::new(&file), anchor))
+                .unwrap_or_else(|| String::from(anchor));
+            escape_href(&mut confluence_formatter.output, anchor.as_bytes())?;
+        }
+        confluence_formatter.output.write_all(b"\">")
+    }
+
     pub fn is_folder(&self, title: &str) -> bool {
         self.folders.contains(title)
     }
@@ -262,25 +324,6 @@ impl LinkGenerator {
                 .link_page_to_filename(&node.title, confluence_page)
                 .is_none()
     }
-
-    pub fn attachment_id(&self, _relative_path: &str, _page: &MarkdownPage) -> Option<String> {
-        let pair = &(_page.source.clone(), _relative_path.to_string());
-        self.page_attachment_pair_to_id.get(pair).cloned()
-    }
-
-    // TODO: make the pair part of the attachment struct
-    pub(crate) fn register_attachment_id(
-        &mut self,
-        page_source: &str,
-        attachment_path: &str,
-        id: &str,
-    ) {
-        let key = (String::from(page_source), String::from(attachment_path));
-        let result = self
-            .page_attachment_pair_to_id
-            .insert(key, String::from(id));
-        assert!(result.is_none(), "Should only register an attachment once")
-    }
 }
 
 fn relative_local_link(
@@ -2,32 +2,43 @@ use std::env;
 use std::path::PathBuf;
 use std::process::ExitCode;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 use confluence_client::ConfluenceClient;
 use dotenvy::dotenv;
 use markdown_space::MarkdownSpace;
 
 mod alerts;
+mod anchors;
 mod archive;
 mod attachments;
 mod builtins;
 mod checksum;
+mod code_blocks;
 mod confluence_client;
+mod confluence_links;
 mod confluence_page;
 mod confluence_paginator;
 mod confluence_space;
 mod confluence_storage_renderer;
 mod console;
+mod containers;
+mod diagrams;
+mod drift;
 mod error;
 mod folders;
 mod frontmatter;
 mod helpers;
 mod imports;
+mod includes;
+mod incremental;
+mod jira;
+mod jobs;
 mod link_generator;
 mod local_link;
 mod markdown_page;
 mod markdown_space;
+mod math;
 mod mentions;
 mod page_covers;
 mod page_emojis;
@@ -35,18 +46,30 @@ mod page_metadata;
 mod page_properties;
 mod page_statuses;
 mod parent;
+mod plan;
+mod pull;
+mod report;
 mod responses;
 mod restrictions;
 mod retry;
+mod snippets;
 mod sort;
+mod space_config;
+mod storage_to_markdown;
 mod sync;
 mod sync_operation;
+mod sync_state;
+mod tables;
 mod template_renderer;
 #[cfg(test)]
 mod test_helpers;
+mod wiki_links;
+mod xhtml;
 
+use crate::archive::OrphanPolicy;
+use crate::drift::DriftPolicy;
 use crate::error::{ConfluenceError, Result};
-use crate::retry::RetryConfig;
+use crate::space_config::{Restrictions, SpaceConfig};
 use crate::sync::sync_space;
 
 fn check_environment_vars() -> Result<()> {
@@ -60,13 +83,36 @@ fn check_environment_vars() -> Result<()> {
     }
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Import an existing Confluence space into the --space directory as markdown. The directory
+    /// must be named after the space key (unless --space-key is given) and not already contain an
+    /// index.md.
+    #[command(alias = "import")]
+    Pull,
+}
+
 #[derive(Parser, Debug, Default)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to the space to update
     #[arg(short, long)]
     space: String,
 
+    /// The key of the Confluence space to sync to, if it isn't the name of the --space directory
+    /// (ie, to sync the same markdown to a staging space). Can also be set in marked-space.yml.
+    #[arg(long)]
+    space_key: Option<String>,
+
+    /// Sync under this existing page (by ID or title) instead of the space homepage. index.md is
+    /// synced to a page beneath it, leaving the page itself alone, and only the pages under
+    /// index.md are archived. Can also be set in marked-space.yml.
+    #[arg(long)]
+    parent: Option<String>,
+
     /// Write intermediate output to this directory
     #[arg(short, long)]
     output: Option<String>,
@@ -76,46 +122,145 @@ pub struct Args {
     host: Option<String>,
 
     /// Set the user identified by the token to the sole editor of pages. Default is to make the
-    /// space editable to anyone who has access to the space.
-    #[arg(long)]
+    /// space editable to anyone who has access to the space. The same as
+    /// --restrictions=single-editor.
+    #[arg(long, conflicts_with = "restrictions")]
     single_editor: bool,
 
+    /// Who can edit the pages, overriding marked-space.yml. Defaults to open.
+    #[arg(long, value_enum)]
+    restrictions: Option<Restrictions>,
+
     /// Set the user identified by the token to the sole editor of pages. Default is to make the
     /// space editable to anyone who has access to the space.
     #[arg(long)]
     check: bool,
 
+    /// Show what a sync would change in the space, without changing anything. Like --check,
+    /// this still reads the current state of the space from Confluence.
+    #[arg(long)]
+    plan: bool,
+
     /// How many times to retry a request that was rate limited (429) or failed transiently.
     /// Can also be specified in $MARKED_SPACE_MAX_RETRIES.
     #[arg(long)]
     max_retries: Option<u32>,
+
+    /// Write a JSON report of what happened to each page (and any warnings or errors) to this
+    /// file.
+    #[arg(long)]
+    report: Option<String>,
+
+    /// What to do with pages that were edited in Confluence since they were last synced.
+    #[arg(long, value_enum, default_value_t)]
+    on_drift: DriftPolicy,
+
+    /// Show a diff of the changes made in Confluence to pages that were edited since they were
+    /// last synced.
+    #[arg(long)]
+    drift_diff: bool,
+
+    /// Number of pages to sync in parallel. Parents are still synced before their children.
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+
+    /// Where to keep the state of the last sync, used to skip pages that haven't changed since.
+    /// Defaults to .marked-space/state.json in the space directory.
+    #[arg(long)]
+    state_file: Option<String>,
+
+    /// Sync every page in full, even those that haven't changed since the last sync.
+    #[arg(long)]
+    ignore_state: bool,
+
+    /// Only sync the pages whose files changed since this git revision (ie, a branch or commit),
+    /// along with the pages that depend on them.
+    #[arg(long)]
+    since: Option<String>,
+
+    /// Only sync these files (relative to the space directory), along with the pages that
+    /// depend on them.
+    #[arg(long, num_args = 1..)]
+    only: Vec<String>,
+
+    /// What to do with pages whose markdown has been deleted. Defaults to archive.
+    #[arg(long, value_enum)]
+    orphans: Option<OrphanPolicy>,
+}
+
+impl Args {
+    /// Fills in anything not given on the command line from the space's config file.
+    fn apply_config(&mut self, config: &SpaceConfig) {
+        if self.single_editor {
+            self.restrictions = Some(Restrictions::SingleEditor);
+        }
+        self.restrictions = self.restrictions.or(config.restrictions);
+        self.single_editor = self.restrictions == Some(Restrictions::SingleEditor);
+        self.orphans = self.orphans.or(config.orphans);
+        self.parent = self.parent.take().or_else(|| config.parent.clone());
+    }
 }
 
 fn main() -> Result<ExitCode> {
     load_dotenv_if_exists();
 
-    let args = Args::parse();
+    let mut args = Args::parse();
 
     check_environment_vars()?;
 
     let dir = PathBuf::from(args.space.clone());
-    let mut markdown_space = MarkdownSpace::from_directory(&dir)?;
+    let markdown_space = match args.command {
+        Some(Command::Pull) => None,
+        None => Some(MarkdownSpace::from_directory_with_key(
+            &dir,
+            args.space_key.as_deref(),
+        )?),
+    };
+    let config = match markdown_space {
+        Some(ref markdown_space) => markdown_space.config.clone(),
+        None => SpaceConfig::from_directory(&dir)?,
+    };
+    args.apply_config(&config);
 
-    let host = match (args.host.clone(), env::var("CONFLUENCE_HOST").ok()) {
-        (Some(host), _) => host,
-        (_, Some(envvar)) => envvar,
+    let host = match (
+        args.host.clone(),
+        env::var("CONFLUENCE_HOST").ok(),
+        config.host.clone(),
+    ) {
+        (Some(host), _, _) => host,
+        (_, Some(envvar), _) => envvar,
+        (_, _, Some(config_host)) => config_host,
         _ => {
-            eprintln!("Couldn't determine host from either --host or $CONFLUENCE_HOST");
+            eprintln!(
+                "Couldn't determine host from either --host, $CONFLUENCE_HOST or {}",
+                space_config::CONFIG_FILE
+            );
             return Ok(ExitCode::FAILURE);
         }
     };
-    let mut retry_config = RetryConfig::from_env();
+    let mut retry_config = config.retry_config();
     if let Some(max_retries) = args.max_retries {
         retry_config.max_retries = max_retries;
     }
     let confluence_client = ConfluenceClient::new(host.as_str()).with_retry_config(retry_config);
 
-    match sync_space(confluence_client, &mut markdown_space, args) {
+    let report_path = args.report.clone();
+    let result = match markdown_space {
+        None => pull::pull_space(
+            &confluence_client,
+            &dir,
+            args.space_key.as_deref().or(config.space_key.as_deref()),
+        ),
+        Some(mut markdown_space) => sync_space(confluence_client, &mut markdown_space, args),
+    };
+    if let Err(ref err) = result {
+        report::record_error(&format!("{:#}", err));
+    }
+    if let Some(report_path) = report_path {
+        report::write_report(&PathBuf::from(report_path), result.is_ok())?;
+    }
+
+    match result {
         Ok(_) => Ok(ExitCode::SUCCESS),
         Err(err) => {
             eprintln!("Error: {:#?}", err);
@@ -137,3 +282,35 @@ fn load_dotenv_if_exists() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_command_line_overrides_the_config_file() {
+        let config = SpaceConfig {
+            restrictions: Some(Restrictions::SingleEditor),
+            orphans: Some(OrphanPolicy::Keep),
+            ..SpaceConfig::default()
+        };
+
+        let mut args = Args::parse_from(["marked-space", "--space", "team"]);
+        args.apply_config(&config);
+        assert!(args.single_editor);
+        assert_eq!(args.orphans, Some(OrphanPolicy::Keep));
+
+        let mut args = Args::parse_from([
+            "marked-space",
+            "--space",
+            "team",
+            "--restrictions",
+            "open",
+            "--orphans",
+            "archive",
+        ]);
+        args.apply_config(&config);
+        assert!(!args.single_editor);
+        assert_eq!(args.orphans, Some(OrphanPolicy::Archive));
+    }
+}
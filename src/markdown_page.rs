@@ -1,14 +1,32 @@
 use std::{
-    fs::File,
-    io::{self},
+    collections::HashMap,
+    fs,
+    ops::{Deref, DerefMut},
     path::{Path, PathBuf},
 };
 
 use crate::{
-    attachments::Attachment, checksum::sha256_digest, confluence_page::ConfluencePageData,
-    confluence_storage_renderer::render_confluence_storage, frontmatter::FrontMatter,
-    helpers::collect_text, link_generator::LinkGenerator, local_link::LocalLink,
-    parent::get_parent_file, template_renderer::TemplateRenderer,
+    alerts::{render_alerts, AlertSettings},
+    attachments::Attachment,
+    checksum::sha256_digest,
+    code_blocks::CodeBlockInfo,
+    confluence_links::ConfluenceLink,
+    confluence_page::ConfluencePageData,
+    confluence_storage_renderer::render_confluence_storage,
+    containers::{mark_containers, render_containers},
+    diagrams::{render_diagrams, DiagramSettings},
+    frontmatter::FrontMatter,
+    helpers::collect_text,
+    includes::Includer,
+    jira::{link_jira_issues, JiraLinker},
+    link_generator::LinkGenerator,
+    local_link::LocalLink,
+    math::{render_math, MathSettings},
+    parent::get_parent_file,
+    snippets::repository_root,
+    tables::render_tables,
+    template_renderer::TemplateRenderer,
+    xhtml::{check_storage, restore_storage, source_line, take_storage},
 };
 use anyhow::Context;
 use comrak::{
@@ -18,14 +36,52 @@ use comrak::{
 
 use crate::{error::ConfluenceError, Result};
 
-pub struct MarkdownPage<'a> {
+/// Everything about a markdown page except its parsed content. Unlike the content, this can be
+/// shared between threads, so it's all that the (parallel) sync of a page's details gets.
+#[derive(Debug)]
+pub struct PageInfo {
     pub title: String,
     pub source: String,
-    root: &'a AstNode<'a>,
     pub attachments: Vec<Attachment>,
     pub local_links: Vec<LocalLink>,
+    /// The targets of `[[wiki links]]`, which are resolved once every page's title is known.
+    pub wiki_links: Vec<String>,
+    /// Links to pages in other spaces.
+    pub confluence_links: Vec<ConfluenceLink>,
+    /// The text of every heading but the title, which links to the page can have anchors for.
+    pub headings: Vec<String>,
     pub front_matter: FrontMatter,
     pub warnings: Vec<String>,
+    /// Other files the content came from (ie, snippets and includes), so the page is synced again when they
+    /// change.
+    pub dependencies: Vec<PathBuf>,
+}
+
+impl PageInfo {
+    pub(crate) fn is_folder(&self) -> bool {
+        self.front_matter.folder
+    }
+}
+
+pub struct MarkdownPage<'a> {
+    info: PageInfo,
+    root: &'a AstNode<'a>,
+    /// The lines of front matter before the markdown, for reporting where errors are.
+    line_offset: usize,
+}
+
+impl Deref for MarkdownPage<'_> {
+    type Target = PageInfo;
+
+    fn deref(&self) -> &PageInfo {
+        &self.info
+    }
+}
+
+impl DerefMut for MarkdownPage<'_> {
+    fn deref_mut(&mut self) -> &mut PageInfo {
+        &mut self.info
+    }
 }
 
 pub fn remove_prefix(prefix: &Path, page_path: &Path) -> Result<String> {
@@ -55,15 +111,27 @@ impl<'a> MarkdownPage<'a> {
     ) -> Result<MarkdownPage<'a>> {
         let source_string = remove_prefix(space_dir, markdown_page)?;
         // let markdown_page = space_dir.join(source);
-        let file = File::open(markdown_page)?;
-        let mut reader = io::BufReader::new(file);
-        let (fm, original_content) =
-            FrontMatter::from_reader(&mut reader).with_context(|| source_string.clone())?;
+        let file_content = fs::read_to_string(markdown_page)?;
+        let (fm, original_content) = FrontMatter::from_reader(&mut file_content.as_bytes())
+            .with_context(|| source_string.clone())?;
+        let line_offset = line_offset(&file_content, &original_content);
 
         let content = template_renderer
             .render_template_str(&source_string, &original_content, &fm)
             .context(format!("Loading markdown from file {}", source_string))?;
-        Self::parse_markdown(arena, source_string, markdown_page, &content, fm)
+        let mut page = Self::parse_markdown(
+            arena,
+            source_string,
+            markdown_page,
+            &content,
+            fm,
+            &repository_root(space_dir),
+        )?;
+        page.info
+            .dependencies
+            .extend(template_renderer.take_dependencies());
+        page.line_offset = line_offset;
+        Ok(page)
     }
 
     #[cfg(test)]
@@ -75,10 +143,20 @@ impl<'a> MarkdownPage<'a> {
         template_renderer: &mut TemplateRenderer,
     ) -> Result<MarkdownPage<'a>> {
         let (fm, original_content) = FrontMatter::from_str(content)?;
+        let line_offset = line_offset(content, &original_content);
         let content = template_renderer
             .render_template_str(source.as_str(), &original_content, &fm)
             .context(format!("Failed to render markdown from file {}", source))?;
-        Self::parse_markdown(arena, source, markdown_page, &content, fm)
+        let mut page = Self::parse_markdown(
+            arena,
+            source,
+            markdown_page,
+            &content,
+            fm,
+            &repository_root(Path::new(".")),
+        )?;
+        page.line_offset = line_offset;
+        Ok(page)
     }
 
     fn options() -> Options<'a> {
@@ -92,6 +170,9 @@ impl<'a> MarkdownPage<'a> {
         options.extension.shortcodes = true;
         options.extension.tagfilter = true;
         options.extension.alerts = true;
+        options.extension.math_dollars = true;
+        options.extension.footnotes = true;
+        options.extension.wikilinks_title_after_pipe = true;
         options
     }
 
@@ -101,8 +182,14 @@ impl<'a> MarkdownPage<'a> {
         markdown_page: &Path,
         content: &str,
         fm: FrontMatter,
+        repository_root: &Path,
     ) -> Result<MarkdownPage<'a>> {
-        let root: &AstNode<'_> = parse_document(arena, content, &Self::options());
+        let options = Self::options();
+        let (content, storage) = take_storage(content);
+        let root: &AstNode<'_> = parse_document(arena, &mark_containers(&content), &options);
+        let mut includer = Includer::new(arena, &options, PathBuf::from(repository_root));
+        includer.expand(root, markdown_page);
+        restore_storage(arena, root, &storage);
 
         fn iter_nodes<'a, F>(node: &'a AstNode<'a>, f: &mut F)
         where
@@ -114,7 +201,9 @@ impl<'a> MarkdownPage<'a> {
             }
         }
 
-        let mut errors = Vec::<String>::default();
+        let mut errors = includer.errors;
+        errors.extend(render_containers(root));
+        errors.extend(render_tables(arena, root, fm.table.as_ref()));
         let mut warnings = Vec::<String>::default();
         if !fm.unknown_keys.is_empty() {
             warnings.push(format!(
@@ -133,6 +222,18 @@ impl<'a> MarkdownPage<'a> {
             }
         }
         let mut local_links = Vec::<LocalLink>::default();
+        let mut wiki_links = Vec::<String>::default();
+        let mut confluence_links = Vec::<ConfluenceLink>::default();
+        let mut headings = Vec::<String>::default();
+        for node in root.descendants() {
+            if let NodeValue::Link(ref mut link) | NodeValue::Image(ref mut link) =
+                node.data.borrow_mut().value
+            {
+                if let Some(url) = fm.links.get(&link.url) {
+                    link.url = url.clone();
+                }
+            }
+        }
         let mut first_heading: Option<&AstNode> = None;
         iter_nodes(root, &mut |node| match &mut node.data.borrow_mut().value {
             NodeValue::Heading(_heading) => {
@@ -143,13 +244,21 @@ impl<'a> MarkdownPage<'a> {
                     for n in node.children() {
                         collect_text(n, &mut text_content);
                     }
+                    headings.push(String::from_utf8_lossy(&text_content).into_owned());
                 }
             }
-            NodeValue::Image(image) => {
-                if LocalLink::is_local_link(&image.url) {
-                    attachments.push(Attachment::image(
-                        LocalLink::from_str(&image.url, markdown_page).unwrap(),
-                    ));
+            NodeValue::CodeBlock(code_block) => {
+                warnings.extend(CodeBlockInfo::parse(&code_block.info).warnings);
+            }
+            NodeValue::Image(image) if LocalLink::is_local_link(&image.url) => {
+                attachments.push(Attachment::image(
+                    LocalLink::from_str(&image.url, markdown_page).unwrap(),
+                ));
+            }
+            NodeValue::Link(node_link) if ConfluenceLink::is_confluence_link(&node_link.url) => {
+                match ConfluenceLink::from_url(&node_link.url) {
+                    Ok(link) => confluence_links.push(link),
+                    Err(err) => errors.push(err.to_string()),
                 }
             }
             // remote links are left alone
@@ -164,6 +273,7 @@ impl<'a> MarkdownPage<'a> {
                     errors.push(format!("Failed to parse local link: {}", node_link.url));
                 }
             }
+            NodeValue::WikiLink(wiki_link) => wiki_links.push(wiki_link.url.clone()),
             _ => (),
         });
 
@@ -190,13 +300,20 @@ impl<'a> MarkdownPage<'a> {
 
         if errors.is_empty() {
             Ok(MarkdownPage {
-                title,
-                source,
+                info: PageInfo {
+                    title,
+                    source,
+                    attachments,
+                    local_links,
+                    wiki_links,
+                    confluence_links,
+                    headings,
+                    warnings,
+                    front_matter: fm,
+                    dependencies: includer.included,
+                },
                 root,
-                attachments,
-                local_links,
-                warnings,
-                front_matter: fm,
+                line_offset: 0,
             })
         } else {
             Err(ConfluenceError::parsing_errors(source, errors))
@@ -204,10 +321,14 @@ impl<'a> MarkdownPage<'a> {
     }
 
     fn to_html_string(&self, link_generator: &LinkGenerator) -> Result<String> {
+        self.render_storage(link_generator, &Self::options())
+    }
+
+    fn render_storage(&self, link_generator: &LinkGenerator, options: &Options) -> Result<String> {
         let mut html = vec![];
         render_confluence_storage(
             self.root,
-            &Self::options(),
+            options,
             &mut html,
             link_generator,
             &PathBuf::from(self.source.clone()),
@@ -223,6 +344,7 @@ impl<'a> MarkdownPage<'a> {
     pub fn render(&self, link_generator: &LinkGenerator) -> Result<RenderedPage> {
         let rendered_html = self.to_html_string(link_generator)?.clone();
         let content = rendered_html;
+        self.check_storage(&content, link_generator)?;
         let title = self.title.clone();
         let page_path = PathBuf::from(self.source.clone());
         let parent = get_parent_file(&page_path).and_then(|f| link_generator.get_file_id(&f));
@@ -237,8 +359,71 @@ impl<'a> MarkdownPage<'a> {
         })
     }
 
-    pub(crate) fn is_folder(&self) -> bool {
-        self.front_matter.folder
+    /// Checks the page is valid storage format before Confluence rejects it, finding the line the
+    /// problem came from by rendering it again with the source positions.
+    fn check_storage(&self, content: &str, link_generator: &LinkGenerator) -> Result<()> {
+        let Err(err) = check_storage(content) else {
+            return Ok(());
+        };
+        let mut options = Self::options();
+        options.render.sourcepos = true;
+        let with_positions = self.render_storage(link_generator, &options)?;
+        let line = check_storage(&with_positions)
+            .err()
+            .and_then(|err| source_line(&with_positions, err.position));
+        Err(ConfluenceError::generic_error(match line {
+            Some(line) => format!(
+                "{}:{}: Invalid storage format: {}",
+                self.source,
+                line + self.line_offset,
+                err.message
+            ),
+            None => format!("{}: Invalid storage format: {}", self.source, err.message),
+        }))
+    }
+
+    /// Replaces the page's diagrams with images of them, which are attached to the page.
+    pub(crate) fn render_diagrams(
+        &mut self,
+        page_path: &Path,
+        settings: &DiagramSettings,
+        cache_dir: &Path,
+    ) -> Result<()> {
+        let images = render_diagrams(self.root, page_path, settings, cache_dir)
+            .with_context(|| self.source.clone())?;
+        self.info.attachments.extend(images);
+        Ok(())
+    }
+
+    /// Renders the page's alerts that have settings in marked-space.yml.
+    pub(crate) fn render_alerts(
+        &mut self,
+        arena: &'a Arena<AstNode<'a>>,
+        settings: &HashMap<String, AlertSettings>,
+    ) -> Result<()> {
+        render_alerts(arena, self.root, settings).with_context(|| self.source.clone())
+    }
+
+    /// Replaces the Jira issue keys in the page with Jira issue macros.
+    pub(crate) fn link_jira_issues(&mut self, arena: &'a Arena<AstNode<'a>>, linker: &JiraLinker) {
+        link_jira_issues(arena, self.root, linker);
+    }
+
+    /// Replaces the page's math with the configured macros, or with images of it.
+    pub(crate) fn render_math(
+        &mut self,
+        page_path: &Path,
+        settings: &MathSettings,
+        cache_dir: &Path,
+    ) -> Result<()> {
+        let images = render_math(self.root, page_path, settings, cache_dir)
+            .with_context(|| self.source.clone())?;
+        self.info.attachments.extend(images);
+        Ok(())
+    }
+
+    pub fn info(&self) -> &PageInfo {
+        &self.info
     }
 }
 
@@ -266,6 +451,14 @@ impl RenderedPage {
     }
 }
 
+/// The number of lines before the content, ie of front matter.
+fn line_offset(file_content: &str, content: &str) -> usize {
+    file_content
+        .lines()
+        .count()
+        .saturating_sub(content.lines().count())
+}
+
 #[cfg(test)]
 pub fn page_from_str<'a>(
     filename: &str,
@@ -672,6 +865,31 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn it_renders_footnotes_with_anchors() -> TestResult {
+        let arena = Arena::<AstNode>::new();
+        let page = page_from_str(
+            "page.md",
+            "# My Page Title\n\nA claim[^source] and again[^source].\n\n[^source]: The source.\n",
+            &arena,
+        )?;
+        let html_content = page.to_html_string(&LinkGenerator::default_test())?;
+
+        assert!(html_content.contains(
+            r#"<sup><ac:structured-macro ac:name="anchor"><ac:parameter ac:name="">fnref-source</ac:parameter></ac:structured-macro><ac:link ac:anchor="fn-source"><ac:plain-text-link-body><![CDATA[1]]></ac:plain-text-link-body></ac:link></sup>"#
+        ), "{}", html_content);
+        assert!(html_content.contains(r#"<ac:parameter ac:name="">fnref-source-2</ac:parameter>"#));
+        assert!(html_content.contains(
+            r#"<li><ac:structured-macro ac:name="anchor"><ac:parameter ac:name="">fn-source</ac:parameter></ac:structured-macro>"#
+        ), "{}", html_content);
+        assert!(html_content.contains(
+            r#"<ac:link ac:anchor="fnref-source-2"><ac:plain-text-link-body><![CDATA[↩2]]></ac:plain-text-link-body></ac:link></p>"#
+        ), "{}", html_content);
+        assert!(!html_content.contains("<section"));
+
+        Ok(())
+    }
+
     #[test]
     fn it_renders_templates() -> TestResult {
         let arena = Arena::<AstNode>::new();
@@ -1,16 +1,23 @@
 use clap::builder::OsStr;
 use comrak::{nodes::AstNode, Arena};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use regex::Regex;
 use walkdir::WalkDir;
 
 use crate::{
+    anchors::find_heading,
     console::{print_info, print_warning},
+    diagrams::DIAGRAM_DIR,
     error::{ConfluenceError, Result},
+    includes::INCLUDES_DIR,
     markdown_page::MarkdownPage,
+    report,
+    space_config::SpaceConfig,
     template_renderer::TemplateRenderer,
+    wiki_links::{resolve_wiki_link, split_anchor},
 };
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
 };
 
@@ -18,11 +25,40 @@ fn is_valid_space_key(space_key: &str) -> bool {
     Regex::new("^[A-Za-z0-9]+$").unwrap().is_match(space_key)
 }
 
+/// The key of the space in `dir`: the one given, or else the name of the directory.
+pub(crate) fn space_key_for(dir: &Path, space_key: Option<&str>) -> Result<String> {
+    let space_key = match space_key {
+        Some(space_key) => space_key,
+        None => dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default(),
+    };
+    if !is_valid_space_key(space_key) {
+        return Err(ConfluenceError::generic_error(format!(
+            "Invalid space directory/key '{}': can only be letters and numbers",
+            space_key
+        )));
+    }
+    Ok(String::from(space_key))
+}
+
 pub struct MarkdownSpace<'a> {
     pub key: String,
     pub arena: Arena<AstNode<'a>>,
     pub markdown_pages: Vec<PathBuf>,
     pub dir: PathBuf,
+    pub config: SpaceConfig,
+}
+
+fn ignore_globs(config: &SpaceConfig) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in config.ignore.iter() {
+        builder.add(Glob::new(pattern).map_err(|err| {
+            ConfluenceError::generic_error(format!("Invalid ignore glob '{}': {}", pattern, err))
+        })?);
+    }
+    Ok(builder.build()?)
 }
 
 impl<'a> MarkdownSpace<'a> {
@@ -33,6 +69,7 @@ impl<'a> MarkdownSpace<'a> {
             key: String::from(key),
             dir: PathBuf::from(dir),
             arena: Arena::new(),
+            config: SpaceConfig::default(),
         }
     }
 
@@ -51,23 +88,30 @@ impl<'a> MarkdownSpace<'a> {
         )
     }
 
+    #[cfg(test)]
     pub fn from_directory(dir: &Path) -> Result<Self> {
-        let space_key = dir.file_name().unwrap().to_str().unwrap();
-        if !is_valid_space_key(space_key) {
-            return Err(ConfluenceError::generic_error(format!(
-                "Invalid space directory/key '{}': can only be letters and numbers",
-                space_key
-            )));
-        }
-        print_info(&format!(
-            "Parsing space {} from {} ...",
-            space_key,
-            dir.display()
-        ));
+        Self::from_directory_with_key(dir, None)
+    }
+
+    /// As from_directory, but syncing to the space `space_key` (ie, from --space-key) rather than
+    /// the one named in the config or by the directory.
+    pub fn from_directory_with_key(dir: &Path, space_key: Option<&str>) -> Result<Self> {
+        let config = SpaceConfig::from_directory(dir)?;
+        let key = space_key_for(dir, space_key.or(config.space_key.as_deref()))?;
+        print_info(&format!("Parsing space {} from {} ...", key, dir.display()));
+        let ignored = ignore_globs(&config)?;
         let mut markdown_pages = Vec::<PathBuf>::default();
-        for entry in WalkDir::new(dir) {
+        let entries = WalkDir::new(dir).into_iter().filter_entry(|entry| {
+            !entry
+                .path()
+                .strip_prefix(dir)
+                .is_ok_and(|path| ignored.is_match(path))
+        });
+        for entry in entries {
             let entry = entry?;
-            if entry.path().starts_with(dir.join("_tera")) {
+            if entry.path().starts_with(dir.join("_tera"))
+                || entry.path().starts_with(dir.join(INCLUDES_DIR))
+            {
                 continue;
             }
             if entry.path().is_dir() {
@@ -81,13 +125,13 @@ impl<'a> MarkdownSpace<'a> {
                 markdown_pages.push(entry.into_path());
             }
         }
-        let key = String::from(dir.file_stem().unwrap().to_str().unwrap());
         if dir.exists() {
             Ok(MarkdownSpace {
                 markdown_pages,
                 key,
                 dir: PathBuf::from(dir),
                 arena: Arena::new(),
+                config,
             })
         } else {
             Err(crate::error::ConfluenceError::generic_error(
@@ -114,26 +158,62 @@ impl<'a> MarkdownSpace<'a> {
             .replace('\\', "/"))
     }
 
+    /// Applies the space's default labels and sort to a page.
+    fn apply_defaults(&self, markdown_page: &mut MarkdownPage) {
+        for label in self.config.labels.iter() {
+            if !markdown_page.front_matter.labels.contains(label) {
+                markdown_page.front_matter.labels.push(label.clone());
+            }
+        }
+        if markdown_page.front_matter.sort.is_none() {
+            markdown_page.front_matter.sort = self.config.sort;
+        }
+    }
+
     pub(crate) fn parse(
         &'a mut self,
         template_renderer: &mut TemplateRenderer,
     ) -> Result<Vec<MarkdownPage<'a>>> {
         let mut parse_errors = Vec::<anyhow::Error>::default();
         let mut titles: HashSet<String> = HashSet::default();
+        let jira_linker = self.config.jira.as_ref().and_then(|jira| jira.linker());
         let markdown_pages: Vec<MarkdownPage> = self
             .markdown_pages
             .iter()
             .map(|markdown_page_path| {
-                let markdown_page = MarkdownPage::from_file(
+                let mut markdown_page = MarkdownPage::from_file(
                     &self.dir,
                     markdown_page_path,
                     &self.arena,
                     template_renderer,
                 )?;
+                self.apply_defaults(&mut markdown_page);
+                if !self.config.alerts.is_empty() {
+                    markdown_page.render_alerts(&self.arena, &self.config.alerts)?;
+                }
+                if let Some(ref jira_linker) = jira_linker {
+                    markdown_page.link_jira_issues(&self.arena, jira_linker);
+                }
+                if let Some(ref diagrams) = self.config.diagrams {
+                    markdown_page.render_diagrams(
+                        markdown_page_path,
+                        diagrams,
+                        &self.dir.join(DIAGRAM_DIR),
+                    )?;
+                }
+                if let Some(ref math) = self.config.math {
+                    markdown_page.render_math(
+                        markdown_page_path,
+                        math,
+                        &self.dir.join(DIAGRAM_DIR),
+                    )?;
+                }
 
+                report::start_page(&markdown_page.source, &markdown_page.title);
                 for warning in markdown_page.warnings.iter() {
                     print_warning(warning);
                 }
+                report::end_page();
                 let title = markdown_page.title.to_owned();
                 let filename = markdown_page.source.replace('\\', "/");
                 if titles.contains(&title) {
@@ -202,6 +282,66 @@ impl<'a> MarkdownSpace<'a> {
             .filter_map(|r| r.map_err(|e| parse_errors.push(e)).ok())
             .collect();
 
+        // wiki links are by title, and anchors by the headings of the page they link to, so can
+        // only be checked once every page has been parsed
+        let title_to_file: HashMap<String, String> = markdown_pages
+            .iter()
+            .map(|page| (page.title.clone(), page.source.replace('\\', "/")))
+            .collect();
+        let file_to_title: HashMap<String, String> = title_to_file
+            .iter()
+            .map(|(title, file)| (file.clone(), title.clone()))
+            .collect();
+        let file_to_page: HashMap<String, &MarkdownPage> = markdown_pages
+            .iter()
+            .map(|page| (page.source.replace('\\', "/"), page))
+            .collect();
+        for markdown_page in markdown_pages.iter() {
+            let mut missing_anchors = Vec::<String>::default();
+            let local_anchors = markdown_page.local_links.iter().filter_map(|local_link| {
+                let anchor = local_link.anchor.as_deref()?;
+                let file = self.space_relative_path_string(&local_link.target).ok()?;
+                Some((file, anchor))
+            });
+            let wiki_anchors = markdown_page.wiki_links.iter().filter_map(|url| {
+                let anchor = split_anchor(url).1?;
+                let file = resolve_wiki_link(url, &title_to_file, &file_to_title)?;
+                Some((file, anchor))
+            });
+            for (file, anchor) in local_anchors.chain(wiki_anchors) {
+                if let Some(page) = file_to_page.get(&file) {
+                    if find_heading(&page.title, &page.headings, anchor).is_none() {
+                        missing_anchors.push(format!("{}#{}", file, anchor));
+                    }
+                }
+            }
+            if !missing_anchors.is_empty() {
+                parse_errors.push(
+                    ConfluenceError::MissingAnchor {
+                        source_file: markdown_page.source.clone(),
+                        anchors: missing_anchors.join(","),
+                    }
+                    .into(),
+                );
+            }
+
+            let missing_pages: Vec<&str> = markdown_page
+                .wiki_links
+                .iter()
+                .filter(|url| resolve_wiki_link(url, &title_to_file, &file_to_title).is_none())
+                .map(|url| url.as_str())
+                .collect();
+            if !missing_pages.is_empty() {
+                parse_errors.push(
+                    ConfluenceError::MissingFileLink {
+                        source_file: markdown_page.source.clone(),
+                        local_links: missing_pages.join(","),
+                    }
+                    .into(),
+                );
+            }
+        }
+
         if !parse_errors.is_empty() {
             let error_string: String = parse_errors
                 .iter()
@@ -228,7 +368,7 @@ mod tests {
 
     use crate::{
         attachments::Attachment, error::TestResult, local_link::LocalLink,
-        markdown_page::MarkdownPage, template_renderer::TemplateRenderer,
+        markdown_page::MarkdownPage, sort::Sort, template_renderer::TemplateRenderer,
     };
 
     use super::MarkdownSpace;
@@ -322,6 +462,49 @@ mod tests {
         assert!(format!("{:#}", error).contains("Duplicate title 'The Same Heading' in [markdown"))
     }
 
+    #[test]
+    fn it_checks_wiki_links_resolve() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("test/index.md")
+            .write_str("# Home\n## Top\nSee [[Page 2]] and [[subpage/markdown2|page 2]].\n")?;
+        temp.child("test/subpage/index.md")
+            .write_str("# Subpage\n")?;
+        temp.child("test/subpage/markdown2.md")
+            .write_str("# Page 2\nBack [[Home#Top]], but not [[Page 3]] or [[missing]].")?;
+        let mut space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        let result = parse_default(&mut space);
+
+        assert_eq!(
+            format!("{:#}", result.err().unwrap()),
+            "1 Error(s) parsing space:\n  Missing file for link in [subpage/markdown2.md] to [Page 3,missing]",
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_checks_anchors_are_for_headings() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("test/index.md").write_str(
+            "# Home\n## Setup Steps\n[here](#setup-steps), [there](other.md#Installing) and [[Other#missing]]\n",
+        )?;
+        temp.child("test/other.md")
+            .write_str("# Other\n## Installing\n[back](index.md#Home-SetupSteps), [nowhere](index.md#teardown)\n")?;
+        let mut space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        let result = parse_default(&mut space);
+
+        let error = format!("{:#}", result.err().unwrap());
+        assert!(
+            error.starts_with("2 Error(s) parsing space:\n"),
+            "{}",
+            error
+        );
+        assert!(error.contains("Missing heading for link in [index.md] to [other.md#missing]"));
+        assert!(error.contains("Missing heading for link in [other.md] to [index.md#teardown]"));
+        Ok(())
+    }
+
     #[test]
     fn it_checks_page_links_exist() -> TestResult {
         let temp = assert_fs::TempDir::new().unwrap();
@@ -353,6 +536,71 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn it_skips_files_matching_the_ignore_globs() -> Result {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("test/marked-space.yml")
+            .write_str("ignore: [\"drafts\", \"*.draft.md\"]")?;
+        temp.child("test/index.md").write_str("# Home")?;
+        temp.child("test/notes.draft.md").write_str("# Notes")?;
+        temp.child("test/drafts/index.md").write_str("# Drafts")?;
+
+        let space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        assert_eq!(
+            space.markdown_pages,
+            vec![temp.child("test/index.md").to_path_buf()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_uses_the_space_key_from_the_config() -> Result {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("docs/marked-space.yml")
+            .write_str("space-key: TEAMSTG")?;
+
+        let space = MarkdownSpace::from_directory(temp.child("docs").path())?;
+
+        assert_eq!(space.key, "TEAMSTG");
+        Ok(())
+    }
+
+    #[test]
+    fn it_prefers_the_given_space_key() -> Result {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("my-docs/marked-space.yml")
+            .write_str("space-key: TEAM")?;
+
+        let space =
+            MarkdownSpace::from_directory_with_key(temp.child("my-docs").path(), Some("TEAMSTG"))?;
+
+        assert_eq!(space.key, "TEAMSTG");
+        assert!(MarkdownSpace::from_directory_with_key(temp.path(), Some("NOT-VALID")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_applies_default_labels_and_sort() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("test/marked-space.yml")
+            .write_str("labels: [docs]\nsort: inc")?;
+        temp.child("test/index.md")
+            .write_str("---\nlabels: [home]\n---\n# Home")?;
+        temp.child("test/unsorted.md")
+            .write_str("---\nsort: none\n---\n# Unsorted")?;
+
+        let mut space = MarkdownSpace::from_directory(temp.child("test").path())?;
+        let mut pages = parse_default(&mut space)?;
+        pages.sort_by(|a, b| a.source.cmp(&b.source));
+
+        assert_eq!(pages[0].front_matter.labels, vec!["home", "docs"]);
+        assert_eq!(pages[0].front_matter.sort, Some(Sort::Incrementing));
+        assert_eq!(pages[1].front_matter.labels, vec!["docs"]);
+        assert_eq!(pages[1].front_matter.sort, Some(Sort::Unsorted));
+        Ok(())
+    }
+
     fn parse_default<'a>(
         space: &'a mut MarkdownSpace<'a>,
     ) -> anyhow::Result<Vec<MarkdownPage<'a>>, anyhow::Error> {
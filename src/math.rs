@@ -0,0 +1,209 @@
+//! Renders `$...$` and `$$...$$` math, either as a macro from a Confluence app that typesets LaTeX,
+//! or as images made with a local command, in the same way as diagrams.
+
+use std::{io::Write, path::Path};
+
+use anyhow::Context;
+use comrak::nodes::{AstNode, NodeMath, NodeValue};
+use quick_xml::escape::escape;
+use serde::Deserialize;
+
+use crate::{
+    attachments::Attachment,
+    diagrams::{add_attachment, render_image, DiagramFormat},
+    error::Result,
+    xhtml::cdata,
+};
+
+/// How to render math, from `math` in marked-space.yml.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct MathSettings {
+    /// The macro for `$...$`, which gets the LaTeX as its body.
+    pub inline_macro: String,
+    /// The macro for `$$...$$`.
+    pub block_macro: String,
+    /// Renders each formula into an image instead of using the macros, with `{input}` and
+    /// `{output}` as for diagrams.
+    pub command: Option<String>,
+    pub format: DiagramFormat,
+}
+
+impl Default for MathSettings {
+    fn default() -> Self {
+        Self {
+            inline_macro: String::from("mathjax-inline-macro"),
+            block_macro: String::from("mathjax-block-macro"),
+            command: None,
+            format: DiagramFormat::default(),
+        }
+    }
+}
+
+pub(crate) fn write_math_macro(
+    output: &mut impl Write,
+    settings: &MathSettings,
+    math: &NodeMath,
+) -> std::io::Result<()> {
+    let macro_name = if math.display_math {
+        &settings.block_macro
+    } else {
+        &settings.inline_macro
+    };
+    write!(
+        output,
+        "<ac:structured-macro ac:name=\"{}\"><ac:plain-text-body><![CDATA[{}]]></ac:plain-text-body></ac:structured-macro>",
+        escape(macro_name),
+        cdata(math.literal.trim())
+    )
+}
+
+fn write_math_image(output: &mut impl Write, name: &str, math: &NodeMath) -> std::io::Result<()> {
+    let align = if math.display_math {
+        r#" ac:align="center""#
+    } else {
+        ""
+    };
+    write!(
+        output,
+        "<ac:image{}><ri:attachment ri:filename=\"{}\"/></ac:image>",
+        align,
+        escape(name)
+    )
+}
+
+/// Replaces the math in the page with the configured macros, or with images of it, returning the
+/// images to attach.
+pub(crate) fn render_math<'a>(
+    root: &'a AstNode<'a>,
+    page_path: &Path,
+    settings: &MathSettings,
+    cache_dir: &Path,
+) -> Result<Vec<Attachment>> {
+    let mut attachments = Vec::<Attachment>::default();
+    for node in root.descendants() {
+        let math = match node.data.borrow().value {
+            NodeValue::Math(ref math) => math.clone(),
+            _ => continue,
+        };
+
+        let mut storage = Vec::default();
+        match settings.command {
+            Some(ref command) => {
+                let attachment = render_image(
+                    command,
+                    math.literal.trim(),
+                    "tex",
+                    settings.format,
+                    &cache_dir.join("math"),
+                    page_path,
+                )
+                .with_context(|| format!("Rendering math: {}", math.literal))?;
+                write_math_image(&mut storage, &attachment.link.attachment_name(), &math)?;
+                add_attachment(&mut attachments, attachment);
+            }
+            None => write_math_macro(&mut storage, settings, &math)?,
+        }
+        node.data.borrow_mut().value = NodeValue::Raw(String::from_utf8(storage)?);
+    }
+    Ok(attachments)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use comrak::{nodes::AstNode, Arena};
+
+    use crate::{
+        error::TestResult,
+        link_generator::LinkGenerator,
+        test_helpers::{markdown_page_from_str, test_render},
+    };
+
+    use super::*;
+
+    #[test]
+    fn it_renders_math_as_macros() -> TestResult {
+        let rendered = test_render("# Page\nInline $x^2$ and display:\n\n$$\\sum_{i=0}^n i$$\n")?;
+
+        assert!(rendered.content.contains(
+            "<ac:structured-macro ac:name=\"mathjax-inline-macro\"><ac:plain-text-body><![CDATA[x^2]]>"
+        ));
+        assert!(rendered.content.contains(
+            "<ac:structured-macro ac:name=\"mathjax-block-macro\"><ac:plain-text-body><![CDATA[\\sum_{i=0}^n i]]>"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn it_leaves_prices_alone() -> TestResult {
+        let rendered = test_render("# Page\nIt costs $5 or $10.\n")?;
+
+        assert!(rendered.content.contains("It costs $5 or $10."));
+        Ok(())
+    }
+
+    #[test]
+    fn it_uses_the_configured_macros() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let arena = Arena::<AstNode>::new();
+        let mut page = markdown_page_from_str("page.md", "# Page\nInline $x^2$\n", &arena)?;
+        let settings = MathSettings {
+            inline_macro: String::from("latex-inline"),
+            ..MathSettings::default()
+        };
+
+        page.render_math(&PathBuf::from("page.md"), &settings, temp.path())?;
+        let rendered = page.render(&LinkGenerator::default_test())?;
+
+        assert!(rendered
+            .content
+            .contains("<ac:structured-macro ac:name=\"latex-inline\">"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_escapes_the_macro_name() -> TestResult {
+        let settings = MathSettings {
+            inline_macro: String::from("latex\"><b>"),
+            ..MathSettings::default()
+        };
+        let math = NodeMath {
+            dollar_math: true,
+            display_math: false,
+            literal: String::from("x"),
+        };
+        let mut storage = Vec::new();
+
+        write_math_macro(&mut storage, &settings, &math)?;
+
+        assert!(String::from_utf8(storage)?
+            .starts_with("<ac:structured-macro ac:name=\"latex&quot;&gt;&lt;b&gt;\">"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_renders_math_as_images() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let arena = Arena::<AstNode>::new();
+        let mut page = markdown_page_from_str("page.md", "# Page\n$x^2$ and $x^2$\n", &arena)?;
+        let settings = MathSettings {
+            command: Some(String::from("cat {input}")),
+            ..MathSettings::default()
+        };
+
+        page.render_math(&PathBuf::from("page.md"), &settings, temp.path())?;
+        let rendered = page.render(&LinkGenerator::default_test())?;
+
+        assert_eq!(page.attachments.len(), 1);
+        let name = page.attachments[0].link.attachment_name();
+        assert!(name.starts_with("math-"));
+        assert_eq!(fs::read_to_string(&page.attachments[0].link.target)?, "x^2");
+        assert!(rendered.content.contains(&format!(
+            "<ac:image><ri:attachment ri:filename=\"{}\"/></ac:image>",
+            name
+        )));
+        Ok(())
+    }
+}
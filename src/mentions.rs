@@ -89,7 +89,7 @@ mod tests {
 
     use crate::{
         confluence_client, error::TestResult, frontmatter::FrontMatter,
-        template_renderer::TemplateRenderer,
+        template_renderer::TemplateRenderer, xhtml::mark_storage,
     };
 
     static NO_USERS: &str = r#"{"results":[],"start":0,"limit":25,"size":0,"totalSize":0,"cqlQuery":"user.fullname ~ \"dave\"","searchDuration":76,"_links":{"base":"https://jimjim256.atlassian.net/wiki","context":"/wiki"}}"#;
@@ -183,8 +183,7 @@ mod tests {
 
         assert_eq!(
             result,
-            // trailing space on the ac:link stops it from being seen as a markdown link
-            "<ac:link ><ri:user ri:account-id=\"some-atlassian-uuid\"/></ac:link>"
+            mark_storage("<ac:link ><ri:user ri:account-id=\"some-atlassian-uuid\"/></ac:link>")
         );
 
         Ok(())
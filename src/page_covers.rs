@@ -9,7 +9,7 @@ use serde_json::json;
 use void::Void;
 
 use crate::local_link::LocalLink;
-use crate::{link_generator::LinkGenerator, markdown_page::MarkdownPage};
+use crate::{attachments::AttachmentIds, markdown_page::PageInfo};
 
 pub fn string_or_struct<'de, T, D>(deserializer: D) -> std::result::Result<T, D::Error>
 where
@@ -83,13 +83,13 @@ impl FromStr for Cover {
     }
 }
 
-pub fn parse_cover(page: &MarkdownPage<'_>, link_generator: &LinkGenerator) -> serde_json::Value {
+pub fn parse_cover(page: &PageInfo, attachment_ids: &AttachmentIds) -> serde_json::Value {
     if let Some(source) = &page.front_matter.cover.source {
         let position = page.front_matter.cover.position;
         let result = if LocalLink::is_local_link(source) {
             let local_link =
                 LocalLink::from_str(source, &PathBuf::from(&page.source)).expect("Should be link");
-            json!({"id": link_generator.attachment_id(&local_link.attachment_name(), page).expect("should have attachment id"), "position": position})
+            json!({"id": attachment_ids.get(&local_link.attachment_name()).expect("should have attachment id"), "position": position})
         } else {
             json!({"id":source.clone(), "position": position})
         };
@@ -109,9 +109,9 @@ mod test {
     use serde_json::json;
 
     use crate::{
+        attachments::AttachmentIds,
         confluence_client::ConfluenceClient,
         error::TestResult,
-        link_generator::LinkGenerator,
         markdown_space::MarkdownSpace,
         page_properties::{get_property_updates, COVER_PICTURE_ID_PUBLISHED_PROP},
         responses::ContentProperty,
@@ -156,7 +156,7 @@ cover:
 
         let existing_properties: Vec<ContentProperty> = Vec::new();
         let property_updates =
-            get_property_updates(&page, &existing_properties, &LinkGenerator::default_test());
+            get_property_updates(&page, &existing_properties, &AttachmentIds::default());
 
         let expected_value = json!({"id": "https://example.com/image.png", "position": 50});
 
@@ -174,11 +174,11 @@ cover:
 
         assert_eq!(page.attachments.len(), 1, "Should add cover as attachment");
 
-        let mut link_generator = LinkGenerator::default_test();
-        link_generator.register_attachment_id(&page.source, "image.png", "some_other_id");
+        let attachment_ids =
+            AttachmentIds::from([(String::from("image.png"), String::from("some_other_id"))]);
 
         let existing_properties: Vec<ContentProperty> = Vec::new();
-        let property_updates = get_property_updates(&page, &existing_properties, &link_generator);
+        let property_updates = get_property_updates(&page, &existing_properties, &attachment_ids);
 
         let expected_value = json!({"id": "some_other_id", "position": 50});
 
@@ -221,9 +221,9 @@ cover:
         let arena = Arena::<AstNode>::new();
         let page = markdown_page_from_str("test.md", PAGE_WITH_POSITION, &arena)?;
 
-        let mut link_generator = LinkGenerator::default_test();
-        link_generator.register_attachment_id(&page.source, "image.png", "some_other_id");
-        let cover_value = parse_cover(&page, &link_generator);
+        let attachment_ids =
+            AttachmentIds::from([(String::from("image.png"), String::from("some_other_id"))]);
+        let cover_value = parse_cover(&page, &attachment_ids);
 
         assert_eq!(
             unwrap_value(&cover_value)?,
@@ -1,6 +1,6 @@
-use crate::{console::print_warning, markdown_page::MarkdownPage};
+use crate::{console::print_warning, markdown_page::PageInfo};
 
-pub(crate) fn parse_emoji(page: &MarkdownPage) -> Option<String> {
+pub(crate) fn parse_emoji(page: &PageInfo) -> Option<String> {
     let emoji_string = &page.front_matter.emoji;
     if emoji_string.is_empty() {
         None
@@ -24,8 +24,8 @@ mod tests {
     use serde_json::json;
 
     use crate::{
-        link_generator::LinkGenerator,
-        markdown_page::page_from_str,
+        attachments::AttachmentIds,
+        markdown_page::{page_from_str, MarkdownPage},
         page_properties::{get_property_updates, EMOJI_TITLE_PUBLISHED_PROP},
         responses::{ContentProperty, Version},
     };
@@ -105,7 +105,7 @@ emoji:  not_a_short_code
         let existing_properties: Vec<ContentProperty> = Vec::new();
 
         let property_updates =
-            get_property_updates(&page, &existing_properties, &LinkGenerator::default_test());
+            get_property_updates(&page, &existing_properties, &AttachmentIds::default());
 
         let expected_updates = vec![ContentProperty {
             id: String::from(""),
@@ -142,7 +142,7 @@ emoji:  not_a_short_code
         }];
 
         let property_updates =
-            get_property_updates(&page, &existing_properties, &LinkGenerator::default_test());
+            get_property_updates(&page, &existing_properties, &AttachmentIds::default());
 
         let expected_updates = vec![ContentProperty {
             id: String::from("123456"),
@@ -173,7 +173,7 @@ emoji:  not_a_short_code
         }];
 
         let property_updates =
-            get_property_updates(&page, &existing_properties, &LinkGenerator::default_test());
+            get_property_updates(&page, &existing_properties, &AttachmentIds::default());
 
         let expected_updates = vec![ContentProperty {
             id: String::from("123456"),
@@ -204,7 +204,7 @@ emoji:  not_a_short_code
         }];
 
         let property_updates =
-            get_property_updates(&page, &existing_properties, &LinkGenerator::default_test());
+            get_property_updates(&page, &existing_properties, &AttachmentIds::default());
 
         let expected_updates: Vec<ContentProperty> = Vec::new();
 
@@ -219,7 +219,7 @@ emoji:  not_a_short_code
         let existing_properties: Vec<ContentProperty> = Vec::new();
 
         let property_updates =
-            get_property_updates(&page, &existing_properties, &LinkGenerator::default_test());
+            get_property_updates(&page, &existing_properties, &AttachmentIds::default());
 
         let expected_updates: Vec<ContentProperty> = Vec::new();
 
@@ -2,21 +2,21 @@ use std::collections::{HashMap, HashSet};
 
 use serde_json::json;
 
-use crate::console::{print_status, Status};
+use crate::console::{print_planned, print_status, Status};
 use crate::error::{ConfluenceError, Result};
 use crate::page_covers::parse_cover;
 use crate::page_emojis::parse_emoji;
 use crate::responses::{self, ContentProperty, MultiEntityResult};
 use crate::{
-    confluence_client::ConfluenceClient, link_generator::LinkGenerator, markdown_page::MarkdownPage,
+    attachments::AttachmentIds, confluence_client::ConfluenceClient, markdown_page::PageInfo,
 };
 
 pub static EMOJI_TITLE_PUBLISHED_PROP: &str = "emoji-title-published";
 pub static COVER_PICTURE_ID_PUBLISHED_PROP: &str = "cover-picture-id-published";
 
 fn get_page_property_values(
-    page: &MarkdownPage,
-    link_generator: &LinkGenerator,
+    page: &PageInfo,
+    attachment_ids: &AttachmentIds,
 ) -> HashMap<String, serde_json::Value> {
     let mut result = HashMap::new();
     result.insert(
@@ -26,20 +26,20 @@ fn get_page_property_values(
 
     result.insert(
         String::from(COVER_PICTURE_ID_PUBLISHED_PROP),
-        json!(parse_cover(page, link_generator)),
+        json!(parse_cover(page, attachment_ids)),
     );
 
     result
 }
 
 pub fn get_property_updates(
-    page: &MarkdownPage<'_>,
+    page: &PageInfo,
     existing_properties: &[ContentProperty],
-    link_generator: &LinkGenerator,
+    attachment_ids: &AttachmentIds,
 ) -> Vec<ContentProperty> {
     let mut result = Vec::new();
 
-    let page_properties = get_page_property_values(page, link_generator);
+    let page_properties = get_page_property_values(page, attachment_ids);
     let mut page_property_keys: HashSet<String> = page_properties.keys().cloned().collect();
 
     for prop in existing_properties {
@@ -74,18 +74,47 @@ pub fn get_property_updates(
     result
 }
 
+/// Reports the property changes sync_page_properties would make, without making them.
+pub fn plan_page_properties(
+    confluence_client: &ConfluenceClient,
+    page: &PageInfo,
+    page_id: &str,
+    attachment_ids: &AttachmentIds,
+) -> Result<()> {
+    let prop_json = confluence_client
+        .get_properties(page_id)?
+        .error_for_status()?
+        .json::<MultiEntityResult<responses::ContentProperty>>()?;
+
+    for property_update in get_property_updates(page, &prop_json.results, attachment_ids) {
+        let status = if property_update.value.is_null() {
+            Status::Deleted
+        } else if property_update.id.is_empty() {
+            Status::Created
+        } else {
+            Status::Updated
+        };
+        print_planned(
+            status,
+            &format!("[{}] property {}", page.source, property_update.key),
+        );
+    }
+
+    Ok(())
+}
+
 pub fn sync_page_properties(
     confluence_client: &ConfluenceClient,
-    page: &MarkdownPage,
+    page: &PageInfo,
     page_id: &str,
-    link_generator: &LinkGenerator,
+    attachment_ids: &AttachmentIds,
 ) -> Result<()> {
     let prop_json = confluence_client
         .get_properties(page_id)?
         .error_for_status()?
         .json::<MultiEntityResult<responses::ContentProperty>>()?;
 
-    let property_updates = get_property_updates(page, &prop_json.results, link_generator);
+    let property_updates = get_property_updates(page, &prop_json.results, attachment_ids);
 
     for property_update in property_updates.iter() {
         let update_response = if property_update.value.is_null() {
@@ -5,14 +5,14 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     confluence_client::ConfluenceClient,
-    console::{self, print_status},
+    console::{self, print_planned, print_status},
     error::Result,
     link_generator::LinkGenerator,
-    markdown_page::MarkdownPage,
+    markdown_page::PageInfo,
     responses,
 };
 
-#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Hash)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum PageStatus {
     #[serde(rename = "draft")]
     RoughDraft,
@@ -35,20 +35,31 @@ impl std::fmt::Display for PageStatus {
     }
 }
 
+/// The names of Confluence's suggested content states for each status.
+const STANDARD_STATES: [(PageStatus, &str); 4] = [
+    (PageStatus::RoughDraft, "Rough draft"),
+    (PageStatus::InProgress, "In progress"),
+    (PageStatus::ReadyForReview, "Ready for review"),
+    (PageStatus::Verified, "Verified"),
+];
+
+impl PageStatus {
+    pub fn from_content_state_name(name: &str) -> Option<PageStatus> {
+        STANDARD_STATES
+            .iter()
+            .find(|(_, status_name)| *status_name == name)
+            .map(|(status, _)| *status)
+    }
+}
+
 #[derive(Debug)]
 pub struct ContentStates {
     states: HashMap<PageStatus, responses::ContentState>,
 }
 impl ContentStates {
     pub fn new(content_states: &[responses::ContentState]) -> Self {
-        let standard_states = vec![
-            (PageStatus::RoughDraft, "Rough draft"),
-            (PageStatus::InProgress, "In progress"),
-            (PageStatus::ReadyForReview, "Ready for review"),
-            (PageStatus::Verified, "Verified"),
-        ];
         let mut states = HashMap::new();
-        for (status, status_name) in standard_states {
+        for (status, status_name) in STANDARD_STATES {
             if let Some(content_state) = content_states.iter().find(|x| x.name == status_name) {
                 states.insert(status, content_state.clone());
             }
@@ -65,9 +76,58 @@ impl ContentStates {
     }
 }
 
+enum StatusChange<'a> {
+    Set(&'a PageStatus, serde_json::Value),
+    Remove,
+}
+
+fn status_change<'a>(
+    markdown_page: &'a PageInfo,
+    content_states: &ContentStates,
+    current_state: &serde_json::Value,
+) -> Result<Option<StatusChange<'a>>> {
+    if let Some(content_status) = &markdown_page.front_matter.status {
+        let desired_state = content_states.to_confluence_json(content_status)?;
+        if desired_state != current_state["contentState"] {
+            return Ok(Some(StatusChange::Set(content_status, desired_state)));
+        }
+    } else if !current_state["contentState"].is_null() {
+        return Ok(Some(StatusChange::Remove));
+    }
+
+    Ok(None)
+}
+
+/// Reports the status change sync_page_status would make, without making it.
+pub fn plan_page_status(
+    client: &ConfluenceClient,
+    markdown_page: &PageInfo,
+    link_generator: &LinkGenerator,
+    content_states: &ContentStates,
+) -> Result<()> {
+    let id = &link_generator
+        .get_file_id(&PathBuf::from(&markdown_page.source))
+        .expect("Should have id for file");
+    let current_state: serde_json::Value =
+        client.get_content_state(id)?.error_for_status()?.json()?;
+    match status_change(markdown_page, content_states, &current_state)? {
+        Some(StatusChange::Set(content_status, _)) => print_planned(
+            console::Status::Updated,
+            &format!("[{}] status to {}", markdown_page.source, content_status),
+        ),
+        Some(StatusChange::Remove) => print_planned(
+            console::Status::Deleted,
+            &format!("[{}] status", markdown_page.source),
+        ),
+        None => (),
+    }
+
+    Ok(())
+}
+
 pub fn sync_page_status(
     client: &ConfluenceClient,
-    markdown_page: &MarkdownPage,
+    markdown_page: &PageInfo,
     link_generator: &LinkGenerator,
     content_states: &ContentStates,
 ) -> Result<()> {
@@ -76,9 +136,8 @@ pub fn sync_page_status(
         .expect("Should have id for file");
     let current_state: serde_json::Value =
         client.get_content_state(id)?.error_for_status()?.json()?;
-    if let Some(content_status) = &markdown_page.front_matter.status {
-        let desired_state = content_states.to_confluence_json(content_status)?;
-        if desired_state != current_state["contentState"] {
+    match status_change(markdown_page, content_states, &current_state)? {
+        Some(StatusChange::Set(content_status, desired_state)) => {
             print_status(
                 console::Status::Updated,
                 &format!(
@@ -90,19 +149,16 @@ pub fn sync_page_status(
                 .set_content_state(id, "current", desired_state)?
                 .error_for_status()?;
         }
-    } else if !current_state["contentState"].is_null() {
-        print_status(
-            console::Status::Deleted,
-            &format!("[{}] removed status", markdown_page.source),
-        );
-        client
-            .remove_content_state(
-                &link_generator
-                    .get_file_id(&PathBuf::from(&markdown_page.source))
-                    .expect("Should have id for file"),
-                "current",
-            )?
-            .error_for_status()?;
+        Some(StatusChange::Remove) => {
+            print_status(
+                console::Status::Deleted,
+                &format!("[{}] removed status", markdown_page.source),
+            );
+            client
+                .remove_content_state(id, "current")?
+                .error_for_status()?;
+        }
+        None => (),
     }
 
     Ok(())
@@ -120,7 +176,7 @@ mod test {
         responses, test_helpers::register_mark_and_conf_page,
     };
 
-    use super::{sync_page_status, PageStatus};
+    use super::{plan_page_status, sync_page_status, PageStatus};
 
     #[test]
     fn it_returns_none_with_no_status() -> TestResult {
@@ -258,6 +314,40 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn it_only_reads_page_status_when_planning() -> TestResult {
+        let mut server = mockito::Server::new();
+        let host = server.host_with_port();
+
+        let set_mock = mock_set_content_state(&mut server).expect(0);
+        let get_mock = mock_current_content_state(
+            &mut server,
+            json!({
+              "lastUpdated": "<string>"
+            }),
+        );
+
+        let response = json!([{"id":13500442,"color":"#ffc400","name":"Rough draft"}]);
+        let states = serde_json::from_value::<Vec<responses::ContentState>>(response).unwrap();
+        let content_states = ContentStates::new(&states);
+
+        let markdown_space = MarkdownSpace::default("test", &PathBuf::from("test"));
+        let mut link_generator = LinkGenerator::default_test();
+        let markdown_page = register_mark_and_conf_page(
+            "1",
+            &mut link_generator,
+            markdown_space
+                .page_from_str("index.md", "---\nstatus: draft\n---\n# Title\nContent")?,
+        )?;
+
+        let client = ConfluenceClient::new_insecure(&host);
+        plan_page_status(&client, &markdown_page, &link_generator, &content_states)?;
+
+        get_mock.assert();
+        set_mock.assert();
+        Ok(())
+    }
+
     fn mock_current_content_state(
         server: &mut mockito::ServerGuard,
         body: serde_json::Value,
@@ -0,0 +1,430 @@
+use std::{collections::HashSet, path::Path, path::PathBuf};
+
+use crate::{
+    archive::{archive_description, start_node_report, unarchive_description, OrphanPolicy},
+    attachments::{plan_page_attachments, AttachmentIds},
+    confluence_client::ConfluenceClient,
+    confluence_page::ConfluenceNode,
+    confluence_space::ConfluenceSpace,
+    console::{print_planned, Status},
+    link_generator::LinkGenerator,
+    markdown_page::{MarkdownPage, PageInfo, RenderedPage},
+    page_properties::{get_property_updates, plan_page_properties},
+    page_statuses::plan_page_status,
+    parent::get_parent_file,
+    report,
+    responses::{self, MultiEntityResult},
+    restrictions::{plan_restrictions, RestrictionType},
+    sort::plan_sort,
+    sync::{output_content, page_up_to_date, parent_id, start_page_report},
+    Args, Result,
+};
+
+/// Reports everything a sync would do to the space, using only reads from Confluence.
+///
+/// This walks the same steps as sync_space and asks the same questions of the space, but
+/// prints the answers instead of acting on them.
+pub(crate) fn plan_space(
+    markdown_pages: &[MarkdownPage],
+    space: &ConfluenceSpace,
+    link_generator: &LinkGenerator,
+    space_dir: &Path,
+    confluence_client: &ConfluenceClient,
+    args: &Args,
+    drifted: &HashSet<String>,
+) -> Result<()> {
+    if args.orphans == Some(OrphanPolicy::Keep) {
+        space.keep_orphans(link_generator, space_dir);
+    } else {
+        for node in space.orphans(link_generator) {
+            start_node_report(node);
+            print_planned(
+                Status::Archived,
+                &archive_description(node, space_dir)
+                    .unwrap_or_else(|| format!("orphaned \"{}\"", node.title)),
+            );
+            report::end_page();
+        }
+    }
+
+    for node in space.pages_to_restore(link_generator) {
+        start_node_report(node);
+        print_planned(Status::Unarchived, &unarchive_description(node));
+        report::end_page();
+    }
+
+    let current_user: Option<serde_json::Value> = if args.single_editor {
+        Some(
+            confluence_client
+                .current_user()?
+                .error_for_status()?
+                .json()?,
+        )
+    } else {
+        None
+    };
+
+    for markdown_page in markdown_pages.iter() {
+        let existing_node = link_generator
+            .get_file_id(&PathBuf::from(&markdown_page.source))
+            .and_then(|id| space.get_existing_node(&id));
+
+        start_page_report(markdown_page, link_generator);
+        match existing_node {
+            None => plan_new_node(markdown_page, link_generator, args)?,
+            Some(node) if markdown_page.is_folder() => {
+                plan_folder(markdown_page, &node, link_generator, space)
+            }
+            Some(node) => {
+                if plan_page_content(markdown_page, &node, link_generator, space, args, drifted)? {
+                    plan_page(
+                        markdown_page,
+                        &node,
+                        link_generator,
+                        space,
+                        confluence_client,
+                        &current_user,
+                    )?;
+                }
+            }
+        }
+        plan_sort(
+            markdown_page,
+            &children(markdown_page, markdown_pages),
+            link_generator,
+            confluence_client,
+        )?;
+        report::end_page();
+    }
+
+    Ok(())
+}
+
+/// The pages directly under the page.
+fn children<'p>(markdown_page: &PageInfo, markdown_pages: &'p [MarkdownPage]) -> Vec<&'p PageInfo> {
+    let source = PathBuf::from(&markdown_page.source);
+    markdown_pages
+        .iter()
+        .filter(|page| page.source != "index.md")
+        .filter(|page| {
+            get_parent_file(Path::new(&page.source)).unwrap_or(PathBuf::from("index.md")) == source
+        })
+        .map(|page| &**page)
+        .collect()
+}
+
+fn plan_new_node(
+    markdown_page: &MarkdownPage,
+    link_generator: &LinkGenerator,
+    args: &Args,
+) -> Result<()> {
+    let kind = if markdown_page.is_folder() {
+        "folder"
+    } else {
+        "page"
+    };
+    print_planned(
+        Status::Created,
+        &format!(
+            "[{}] {} \"{}\"",
+            markdown_page.source, kind, markdown_page.title
+        ),
+    );
+    if markdown_page.is_folder() {
+        return Ok(());
+    }
+
+    if let Some(ref d) = args.output {
+        output_content(d, &markdown_page.render(link_generator)?)?;
+    }
+
+    // there are no ids until they're uploaded, but covers still need something to refer to
+    let mut attachment_ids = AttachmentIds::default();
+    for attachment in markdown_page.attachments.iter() {
+        attachment_ids.insert(attachment.link.attachment_name(), String::default());
+        print_planned(
+            Status::Created,
+            &format!("[{}] attachment", attachment.link.target.display()),
+        );
+    }
+
+    if !markdown_page.front_matter.labels.is_empty() {
+        print_planned(
+            Status::Created,
+            &format!(
+                "[{}] labels: {}",
+                markdown_page.source,
+                markdown_page.front_matter.labels.join(",")
+            ),
+        );
+    }
+
+    for property in get_property_updates(markdown_page, &[], &attachment_ids) {
+        print_planned(
+            Status::Created,
+            &format!("[{}] property {}", markdown_page.source, property.key),
+        );
+    }
+
+    if let Some(status) = &markdown_page.front_matter.status {
+        print_planned(
+            Status::Created,
+            &format!("[{}] status {}", markdown_page.source, status),
+        );
+    }
+
+    Ok(())
+}
+
+fn plan_folder(
+    markdown_page: &MarkdownPage,
+    existing_folder: &ConfluenceNode,
+    link_generator: &LinkGenerator,
+    space: &ConfluenceSpace,
+) {
+    let parent_id = get_parent_file(&PathBuf::from(&markdown_page.source))
+        .and_then(|f| link_generator.get_file_id(&f))
+        .or(Some(space.homepage_id.clone()));
+
+    if existing_folder.page_data().is_some() {
+        print_planned(
+            Status::Error,
+            &format!(
+                "[{}] \"{}\" is a page and cannot be converted to a folder",
+                markdown_page.source, existing_folder.title
+            ),
+        );
+    } else if existing_folder.parent_id != parent_id {
+        print_planned(
+            Status::Updated,
+            &format!(
+                "[{}] \"{}\" (moved)",
+                markdown_page.source, markdown_page.title
+            ),
+        );
+    }
+}
+
+/// Describes what about a page has changed, ie "moved, content".
+fn describe_page_changes(
+    existing_node: &ConfluenceNode,
+    rendered_page: &RenderedPage,
+    parent_id: &Option<String>,
+    version_message: &String,
+) -> String {
+    let mut changes = Vec::new();
+    if parent_id != &existing_node.parent_id {
+        changes.push("moved");
+    }
+    if existing_node.title != rendered_page.title {
+        changes.push("retitled");
+    }
+    if existing_node
+        .page_data()
+        .is_some_and(|p| &p.version.message != version_message)
+    {
+        changes.push("content");
+    }
+    changes.join(", ")
+}
+
+/// Reports whether the page's content would be updated. Returns false if the existing node
+/// can't be updated as a page at all.
+fn plan_page_content(
+    markdown_page: &MarkdownPage,
+    existing_node: &ConfluenceNode,
+    link_generator: &LinkGenerator,
+    space: &ConfluenceSpace,
+    args: &Args,
+    drifted: &HashSet<String>,
+) -> Result<bool> {
+    if existing_node.page_data().is_none() {
+        print_planned(
+            Status::Error,
+            &format!(
+                "[{}] \"{}\" is not a page and cannot be converted",
+                markdown_page.source, existing_node.title
+            ),
+        );
+        return Ok(false);
+    }
+
+    let rendered_page = markdown_page.render(link_generator)?;
+    if let Some(ref d) = args.output {
+        output_content(d, &rendered_page)?;
+    }
+
+    let parent_id = parent_id(space, &rendered_page);
+    let version_message = rendered_page.version_message();
+    if drifted.contains(&existing_node.id) {
+        print_planned(
+            Status::Skipped,
+            &format!(
+                "[{}] \"{}\" (edited in Confluence)",
+                rendered_page.source, rendered_page.title
+            ),
+        );
+    } else if !page_up_to_date(existing_node, &rendered_page, &parent_id, &version_message) {
+        print_planned(
+            Status::Updated,
+            &format!(
+                "[{}] \"{}\" ({})",
+                rendered_page.source,
+                rendered_page.title,
+                describe_page_changes(existing_node, &rendered_page, &parent_id, &version_message)
+            ),
+        );
+    }
+
+    Ok(true)
+}
+
+fn plan_page(
+    markdown_page: &MarkdownPage,
+    existing_node: &ConfluenceNode,
+    link_generator: &LinkGenerator,
+    space: &ConfluenceSpace,
+    confluence_client: &ConfluenceClient,
+    current_user: &Option<serde_json::Value>,
+) -> Result<()> {
+    let attachment_ids = plan_page_attachments(
+        confluence_client,
+        &existing_node.id,
+        &markdown_page.attachments,
+    )?;
+    plan_page_labels(
+        confluence_client,
+        &existing_node.id,
+        &markdown_page.source,
+        &markdown_page.front_matter.labels,
+    )?;
+    plan_page_status(
+        confluence_client,
+        markdown_page,
+        link_generator,
+        &space.content_states,
+    )?;
+    plan_page_properties(
+        confluence_client,
+        markdown_page,
+        &existing_node.id,
+        &attachment_ids,
+    )?;
+    if let Some(user) = current_user {
+        plan_restrictions(
+            RestrictionType::SingleEditor(user),
+            confluence_client,
+            existing_node,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn plan_page_labels(
+    confluence_client: &ConfluenceClient,
+    page_id: &str,
+    page_source: &str,
+    labels: &[String],
+) -> Result<()> {
+    let existing: HashSet<String> = confluence_client
+        .get_page_labels(page_id)?
+        .error_for_status()?
+        .json::<MultiEntityResult<responses::Label>>()?
+        .results
+        .into_iter()
+        .map(|label| label.name)
+        .collect();
+
+    let added: Vec<&str> = labels
+        .iter()
+        .filter(|label| !existing.contains(*label))
+        .map(String::as_str)
+        .collect();
+    if !added.is_empty() {
+        print_planned(
+            Status::Created,
+            &format!("[{}] labels: {}", page_source, added.join(",")),
+        );
+    }
+
+    let mut removed: Vec<&str> = existing
+        .iter()
+        .filter(|label| !labels.contains(label))
+        .map(String::as_str)
+        .collect();
+    removed.sort();
+    if !removed.is_empty() {
+        print_planned(
+            Status::Deleted,
+            &format!("[{}] labels: {}", page_source, removed.join(",")),
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        confluence_page::{ConfluenceNodeType, ConfluencePageData},
+        error::TestResult,
+        responses::{ContentStatus, Version},
+    };
+
+    use super::*;
+
+    fn existing_page(title: &str, parent_id: &str, message: &str) -> ConfluenceNode {
+        ConfluenceNode {
+            id: String::from("1"),
+            title: String::from(title),
+            parent_id: Some(String::from(parent_id)),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: String::from(message),
+                    number: 1,
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        }
+    }
+
+    fn rendered_page(title: &str) -> RenderedPage {
+        RenderedPage {
+            title: String::from(title),
+            content: String::default(),
+            source: String::from("test.md"),
+            parent: None,
+            checksum: String::default(),
+        }
+    }
+
+    #[test]
+    fn it_describes_moved_pages() -> TestResult {
+        let existing = existing_page("Title", "99", "message");
+        let description = describe_page_changes(
+            &existing,
+            &rendered_page("Title"),
+            &Some(String::from("100")),
+            &String::from("message"),
+        );
+
+        assert_eq!(description, "moved");
+        Ok(())
+    }
+
+    #[test]
+    fn it_describes_all_page_changes() -> TestResult {
+        let existing = existing_page("Old Title", "99", "old message");
+        let description = describe_page_changes(
+            &existing,
+            &rendered_page("New Title"),
+            &Some(String::from("100")),
+            &String::from("new message"),
+        );
+
+        assert_eq!(description, "moved, retitled, content");
+        Ok(())
+    }
+}
@@ -0,0 +1,479 @@
+//! Imports an existing Confluence space as markdown, for `marked-space pull`.
+//!
+//! The pages are laid out the way MarkdownSpace::from_directory expects: the homepage is the
+//! space's `index.md`, pages with children become a directory with an `index.md` and everything
+//! else is a markdown file named after its title. Attachments the page refers to are downloaded
+//! next to it.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    confluence_page::{ConfluenceNode, ConfluenceNodeType},
+    confluence_space::ConfluenceSpace,
+    console::{print_info, print_status, print_warning, Status},
+    error::{ConfluenceError, Result},
+    markdown_space::space_key_for,
+    page_properties::{COVER_PICTURE_ID_PUBLISHED_PROP, EMOJI_TITLE_PUBLISHED_PROP},
+    page_statuses::PageStatus,
+    responses::{self, BodySingle, ContentStatus, MultiEntityResult, PageSingleWithBody},
+    storage_to_markdown::{escape_markdown, PullLinks, StorageConverter},
+};
+
+/// Turns a title into something usable as a file name, ie "Getting Started!" to
+/// "getting-started".
+fn slugify(title: &str) -> String {
+    let slug = title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-");
+    if slug.is_empty() {
+        String::from("page")
+    } else {
+        slug
+    }
+}
+
+fn unique_slug(title: &str, used: &mut HashSet<String>) -> String {
+    let slug = slugify(title);
+    let mut candidate = slug.clone();
+    let mut n = 2;
+    while used.contains(&candidate) {
+        candidate = format!("{}-{}", slug, n);
+        n += 1;
+    }
+    used.insert(candidate.clone());
+    candidate
+}
+
+/// Works out which file each page and folder should be written to, relative to the space
+/// directory.
+fn layout(nodes: &[ConfluenceNode], homepage_id: &str) -> HashMap<String, PathBuf> {
+    let ids: HashSet<&str> = nodes.iter().map(|n| n.id.as_str()).collect();
+    let mut children = HashMap::<&str, Vec<&ConfluenceNode>>::new();
+    for node in nodes.iter().filter(|n| n.id != homepage_id) {
+        // Anything outside the homepage's tree is brought under it.
+        let parent = node
+            .parent_id
+            .as_deref()
+            .filter(|parent| ids.contains(parent))
+            .unwrap_or(homepage_id);
+        children.entry(parent).or_default().push(node);
+    }
+    for siblings in children.values_mut() {
+        siblings.sort_by(|a, b| a.title.cmp(&b.title).then(a.id.cmp(&b.id)));
+    }
+
+    let mut paths = HashMap::new();
+    paths.insert(String::from(homepage_id), PathBuf::from("index.md"));
+    let mut pending = vec![(homepage_id, PathBuf::new())];
+    while let Some((parent_id, dir)) = pending.pop() {
+        let mut used = HashSet::from([String::from("index")]);
+        for child in children.get(parent_id).into_iter().flatten() {
+            let slug = unique_slug(&child.title, &mut used);
+            let is_folder = matches!(child.data, ConfluenceNodeType::Folder(_));
+            if children.contains_key(child.id.as_str()) || is_folder {
+                let child_dir = dir.join(&slug);
+                paths.insert(child.id.clone(), child_dir.join("index.md"));
+                pending.push((&child.id, child_dir));
+            } else {
+                paths.insert(child.id.clone(), dir.join(format!("{}.md", slug)));
+            }
+        }
+    }
+    paths
+}
+
+fn yaml_string(s: &str) -> String {
+    serde_json::Value::String(String::from(s)).to_string()
+}
+
+#[derive(Default, Debug, PartialEq)]
+struct PulledFrontMatter {
+    labels: Vec<String>,
+    emoji: Option<String>,
+    status: Option<PageStatus>,
+    cover: Option<(String, u64)>,
+    folder: bool,
+}
+
+impl PulledFrontMatter {
+    fn render(&self) -> String {
+        let mut lines = Vec::new();
+        if self.folder {
+            lines.push(String::from("folder: true"));
+        }
+        if !self.labels.is_empty() {
+            lines.push(String::from("labels:"));
+            lines.extend(
+                self.labels
+                    .iter()
+                    .map(|label| format!("  - {}", yaml_string(label))),
+            );
+        }
+        if let Some(emoji) = &self.emoji {
+            lines.push(format!("emoji: {}", yaml_string(emoji)));
+        }
+        if let Some(status) = &self.status {
+            lines.push(format!("status: {}", status));
+        }
+        match &self.cover {
+            Some((source, 50)) => lines.push(format!("cover: {}", yaml_string(source))),
+            Some((source, position)) => {
+                lines.push(String::from("cover:"));
+                lines.push(format!("  source: {}", yaml_string(source)));
+                lines.push(format!("  position: {}", position));
+            }
+            None => (),
+        }
+
+        if lines.is_empty() {
+            String::new()
+        } else {
+            format!("---\n{}\n---\n\n", lines.join("\n"))
+        }
+    }
+}
+
+/// The file name for an attachment, which is the attachment's name unless another page in the
+/// same directory already has an attachment by that name.
+fn attachment_file_name(page_file: &Path, name: &str, written_files: &HashSet<PathBuf>) -> String {
+    let dir = page_file.parent().unwrap_or(Path::new(""));
+    if !written_files.contains(&dir.join(name)) {
+        return String::from(name);
+    }
+    let stem = page_file
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("page");
+    format!("{}-{}", stem, name)
+}
+
+/// Decodes the emoji property, which is stored as the hex codepoint of the emoji.
+fn emoji_shortcode(value: &serde_json::Value) -> Option<String> {
+    let codepoint = u32::from_str_radix(value.as_str()?, 16).ok()?;
+    let emoji = char::from_u32(codepoint)?.to_string();
+    emojis::get(&emoji)
+        .and_then(|e| e.shortcode())
+        .map(String::from)
+}
+
+struct SpacePuller<'a> {
+    client: &'a ConfluenceClient,
+    space_dir: &'a Path,
+    links: PullLinks<'a>,
+    written_files: HashSet<PathBuf>,
+}
+
+impl SpacePuller<'_> {
+    fn write_file(&mut self, path: &Path, content: &[u8]) -> Result<()> {
+        let full_path = self.space_dir.join(path);
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&full_path, content)?;
+        self.written_files.insert(PathBuf::from(path));
+        Ok(())
+    }
+
+    fn pull_folder(&mut self, node: &ConfluenceNode, path: &Path) -> Result<()> {
+        let front_matter = PulledFrontMatter {
+            folder: true,
+            ..Default::default()
+        };
+        let content = format!(
+            "{}# {}\n",
+            front_matter.render(),
+            escape_markdown(&node.title)
+        );
+        self.write_file(path, content.as_bytes())
+    }
+
+    fn pull_page(&mut self, node: &ConfluenceNode, path: &Path) -> Result<()> {
+        let page = self
+            .client
+            .get_page_with_body(&node.id)?
+            .error_for_status()?
+            .json::<PageSingleWithBody>()?;
+        let storage = match page.body {
+            BodySingle::Storage(body) => body.value,
+            _ => String::new(),
+        };
+
+        let attachments = self
+            .client
+            .get_attachments(&node.id)?
+            .error_for_status()?
+            .json::<MultiEntityResult<responses::Attachment>>()?
+            .results;
+        let attachment_files: HashMap<String, String> = attachments
+            .iter()
+            .map(|a| {
+                (
+                    a.title.clone(),
+                    attachment_file_name(path, &a.title, &self.written_files),
+                )
+            })
+            .collect();
+
+        let mut converter = StorageConverter::new(&self.links, path, attachment_files.clone());
+        let body = converter.convert(&storage)?;
+        let front_matter = self.pull_front_matter(node, &attachments, &mut converter)?;
+
+        for warning in converter.warnings.iter() {
+            print_warning(warning);
+        }
+        let used_attachments = converter.used_attachments;
+
+        let content = format!(
+            "{}# {}\n\n{}\n",
+            front_matter.render(),
+            escape_markdown(&page.title),
+            body
+        );
+        self.write_file(path, content.as_bytes())?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for attachment in attachments
+            .iter()
+            .filter(|a| used_attachments.contains(&a.title))
+        {
+            let file = dir.join(&attachment_files[&attachment.title]);
+            let content = self
+                .client
+                .download_attachment(&attachment.download_link)?
+                .error_for_status()?
+                .bytes()?;
+            self.write_file(&file, &content)?;
+        }
+
+        Ok(())
+    }
+
+    fn pull_front_matter(
+        &self,
+        node: &ConfluenceNode,
+        attachments: &[responses::Attachment],
+        converter: &mut StorageConverter,
+    ) -> Result<PulledFrontMatter> {
+        let labels = self
+            .client
+            .get_page_labels(&node.id)?
+            .error_for_status()?
+            .json::<MultiEntityResult<responses::Label>>()?
+            .results
+            .into_iter()
+            .map(|label| label.name)
+            .collect();
+
+        let properties = self
+            .client
+            .get_properties(&node.id)?
+            .error_for_status()?
+            .json::<MultiEntityResult<responses::ContentProperty>>()?
+            .results;
+        let property = |key: &str| properties.iter().find(|p| p.key == key);
+
+        let emoji = property(EMOJI_TITLE_PUBLISHED_PROP).and_then(|p| emoji_shortcode(&p.value));
+
+        // The cover is wrapped json, with either the file ID of an attachment or a URL.
+        let cover = property(COVER_PICTURE_ID_PUBLISHED_PROP)
+            .and_then(|p| p.value.as_str())
+            .and_then(|wrapped| serde_json::from_str::<serde_json::Value>(wrapped).ok())
+            .and_then(|cover| {
+                let id = cover["id"].as_str()?;
+                let position = cover["position"].as_u64().unwrap_or(50);
+                let source = match attachments.iter().find(|a| a.file_id == id) {
+                    Some(attachment) => converter.attachment_file(&attachment.title),
+                    None => String::from(id),
+                };
+                Some((source, position))
+            });
+
+        let current_state: serde_json::Value = self
+            .client
+            .get_content_state(&node.id)?
+            .error_for_status()?
+            .json()?;
+        let status = current_state["contentState"]["name"]
+            .as_str()
+            .and_then(PageStatus::from_content_state_name);
+
+        Ok(PulledFrontMatter {
+            labels,
+            emoji,
+            status,
+            cover,
+            folder: false,
+        })
+    }
+}
+
+/// Imports the Confluence space `space_key` (or else the one matching the name of `space_dir`)
+/// into that directory.
+pub(crate) fn pull_space(
+    client: &ConfluenceClient,
+    space_dir: &Path,
+    space_key: Option<&str>,
+) -> Result<()> {
+    let space_key = &space_key_for(space_dir, space_key)?;
+    if space_dir.join("index.md").exists() {
+        return Err(ConfluenceError::generic_error(format!(
+            "{} already contains a space (index.md exists): pull only imports into a new directory",
+            space_dir.display()
+        )));
+    }
+
+    print_info(&format!(
+        "Pulling space {} into {} ...",
+        space_key,
+        space_dir.display()
+    ));
+    let space = ConfluenceSpace::get(client, space_key)?;
+    let nodes: Vec<ConfluenceNode> = ConfluenceNode::get_all(client, &space)?
+        .into_iter()
+        .filter(|node| {
+            node.page_data()
+                .is_none_or(|p| matches!(p.status, ContentStatus::Current))
+        })
+        .collect();
+
+    let paths = layout(&nodes, &space.homepage_id);
+    let id_to_file: HashMap<String, PathBuf> = paths.clone();
+    let title_to_file: HashMap<String, PathBuf> = nodes
+        .iter()
+        .filter_map(|node| Some((node.title.clone(), paths.get(&node.id)?.clone())))
+        .collect();
+
+    let mut puller = SpacePuller {
+        client,
+        space_dir,
+        links: PullLinks {
+            host: &client.hostname,
+            space_key,
+            title_to_file: &title_to_file,
+            id_to_file: &id_to_file,
+        },
+        written_files: HashSet::new(),
+    };
+
+    let mut ordered: Vec<&ConfluenceNode> = nodes.iter().collect();
+    ordered.sort_by_key(|node| paths.get(&node.id));
+    for node in ordered {
+        let Some(path) = paths.get(&node.id) else {
+            continue;
+        };
+        match node.data {
+            ConfluenceNodeType::Folder(_) => puller.pull_folder(node, path)?,
+            ConfluenceNodeType::Page(_) => puller.pull_page(node, path)?,
+        }
+        print_status(
+            Status::Created,
+            &format!("[{}] \"{}\"", path.display(), node.title),
+        );
+    }
+
+    print_info(&format!(
+        "Pulled {} pages. Check the result with --check before syncing.",
+        paths.len()
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        confluence_page::{ConfluenceFolder, ConfluencePageData},
+        responses::Version,
+    };
+
+    use super::*;
+
+    fn page(id: &str, title: &str, parent_id: Option<&str>) -> ConfluenceNode {
+        ConfluenceNode {
+            id: String::from(id),
+            title: String::from(title),
+            parent_id: parent_id.map(String::from),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: String::default(),
+                    number: 1,
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        }
+    }
+
+    #[test]
+    fn it_lays_out_pages_like_a_markdown_space() {
+        let folder = ConfluenceNode {
+            id: String::from("5"),
+            title: String::from("A Folder"),
+            parent_id: Some(String::from("1")),
+            data: ConfluenceNodeType::Folder(ConfluenceFolder {}),
+        };
+        let nodes = vec![
+            page("1", "Home", None),
+            page("2", "Getting Started!", Some("1")),
+            page("3", "Child Page", Some("2")),
+            page("4", "Child Page", Some("2")),
+            folder,
+            page("6", "Lost Page", Some("999")),
+        ];
+
+        let paths = layout(&nodes, "1");
+
+        assert_eq!(paths["1"], PathBuf::from("index.md"));
+        assert_eq!(paths["2"], PathBuf::from("getting-started/index.md"));
+        assert_eq!(paths["3"], PathBuf::from("getting-started/child-page.md"));
+        assert_eq!(paths["4"], PathBuf::from("getting-started/child-page-2.md"));
+        assert_eq!(paths["5"], PathBuf::from("a-folder/index.md"));
+        assert_eq!(paths["6"], PathBuf::from("lost-page.md"));
+    }
+
+    #[test]
+    fn it_renders_front_matter() {
+        let front_matter = PulledFrontMatter {
+            labels: vec![String::from("one"), String::from("two: three")],
+            emoji: Some(String::from("smile")),
+            status: Some(PageStatus::Verified),
+            cover: Some((String::from("cover.png"), 30)),
+            folder: false,
+        };
+
+        assert_eq!(
+            front_matter.render(),
+            "---\nlabels:\n  - \"one\"\n  - \"two: three\"\nemoji: \"smile\"\nstatus: verified\ncover:\n  source: \"cover.png\"\n  position: 30\n---\n\n"
+        );
+        assert_eq!(PulledFrontMatter::default().render(), "");
+    }
+
+    #[test]
+    fn it_decodes_emoji_properties() {
+        assert_eq!(
+            emoji_shortcode(&serde_json::json!("1f604")),
+            Some(String::from("smile"))
+        );
+        assert_eq!(emoji_shortcode(&serde_json::json!("nonsense")), None);
+    }
+
+    #[test]
+    fn it_prefixes_attachments_that_would_collide() {
+        let written = HashSet::from([PathBuf::from("dir/image.png")]);
+        assert_eq!(
+            attachment_file_name(Path::new("dir/page.md"), "image.png", &written),
+            "page-image.png"
+        );
+        assert_eq!(
+            attachment_file_name(Path::new("other/page.md"), "image.png", &written),
+            "image.png"
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Machine readable record of a sync, written out with `--report`.
+//!
+//! Everything marked-space tells the user already goes through the `console` functions, so
+//! rather than threading a report through every sync step, the console records each status,
+//! warning and error here as well. Sync marks which page it is working on with `start_page`,
+//! and anything recorded until `end_page` is attributed to that page. Anything else (ie,
+//! failing to parse the space) ends up at the top level of the report.
+
+use std::{
+    cell::RefCell,
+    fs::File,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::{console::Status, error::Result};
+
+#[derive(Serialize, Debug, Default)]
+pub struct Report {
+    pub space: String,
+    pub host: String,
+    pub mode: String,
+    pub success: bool,
+    pub pages: Vec<PageEntry>,
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct PageEntry {
+    pub source: String,
+    pub title: String,
+    pub page_id: Option<String>,
+    pub url: Option<String>,
+    /// The most significant thing that happened to the page, ie "created" rather than the
+    /// "updated" that follows it.
+    pub action: Status,
+    pub operations: Vec<Operation>,
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Operation {
+    pub status: Status,
+    /// True if this is only what would have happened (ie, with --plan).
+    pub planned: bool,
+    pub description: String,
+}
+
+static REPORT: Lazy<Mutex<Report>> = Lazy::new(|| Mutex::new(Report::default()));
+
+thread_local! {
+    // The source of the page this thread is currently working on.
+    static CURRENT_PAGE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+fn report() -> MutexGuard<'static, Report> {
+    REPORT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn current_page() -> Option<String> {
+    CURRENT_PAGE.with(|current| current.borrow().clone())
+}
+
+fn with_page_entry<F: FnOnce(&mut PageEntry)>(report: &mut Report, source: &str, f: F) {
+    if let Some(entry) = report.pages.iter_mut().find(|e| e.source == source) {
+        f(entry);
+    } else {
+        let mut entry = PageEntry {
+            source: String::from(source),
+            ..Default::default()
+        };
+        f(&mut entry);
+        report.pages.push(entry);
+    }
+}
+
+pub fn start_run(space: &str, host: &str, mode: &str) {
+    let mut report = report();
+    report.space = String::from(space);
+    report.host = String::from(host);
+    report.mode = String::from(mode);
+}
+
+/// Attributes everything recorded by this thread to the page at `source` until `end_page`.
+pub fn start_page(source: &str, title: &str) {
+    with_page_entry(&mut report(), source, |entry| {
+        entry.title = String::from(title);
+    });
+    CURRENT_PAGE.with(|current| *current.borrow_mut() = Some(String::from(source)));
+}
+
+pub fn set_page_link(source: &str, page_id: &str, url: Option<String>) {
+    with_page_entry(&mut report(), source, |entry| {
+        entry.page_id = Some(String::from(page_id));
+        entry.url = url;
+    });
+}
+
+/// True if an error has been reported against the page, ie an attachment that failed to upload.
+pub fn page_has_errors(source: &str) -> bool {
+    report()
+        .pages
+        .iter()
+        .any(|e| e.source == source && !e.errors.is_empty())
+}
+
+pub fn end_page() {
+    CURRENT_PAGE.with(|current| *current.borrow_mut() = None);
+}
+
+pub fn record_status(status: Status, description: &str, planned: bool) {
+    let mut report = report();
+    let operation = Operation {
+        status,
+        planned,
+        description: String::from(description),
+    };
+    if let Some(source) = current_page() {
+        with_page_entry(&mut report, &source, |entry| {
+            if status.significance() > entry.action.significance() {
+                entry.action = status;
+            }
+            if let Status::Error = status {
+                entry.errors.push(operation.description.clone());
+            }
+            entry.operations.push(operation);
+        });
+    } else if let Status::Error = status {
+        report.errors.push(operation.description);
+    }
+}
+
+pub fn record_warning(warning: &str) {
+    let mut report = report();
+    if let Some(source) = current_page() {
+        with_page_entry(&mut report, &source, |entry| {
+            entry.warnings.push(String::from(warning))
+        });
+    } else {
+        report.warnings.push(String::from(warning));
+    }
+}
+
+pub fn record_error(error: &str) {
+    let mut report = report();
+    if let Some(source) = current_page() {
+        with_page_entry(&mut report, &source, |entry| {
+            entry.action = Status::Error;
+            entry.errors.push(String::from(error))
+        });
+    } else {
+        report.errors.push(String::from(error));
+    }
+}
+
+pub fn write_report(path: &Path, success: bool) -> Result<()> {
+    let mut report = report();
+    report.success = success;
+    serde_json::to_writer_pretty(File::create(path)?, &*report)?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub fn page_entry(source: &str) -> Option<PageEntry> {
+    report().pages.iter().find(|e| e.source == source).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        console::{print_error, print_status, print_warning, Status},
+        error::TestResult,
+        sync_operation::SyncOperation,
+    };
+
+    use super::*;
+
+    // The report is shared by every test, so each test uses its own page source.
+
+    #[test]
+    fn it_records_statuses_against_the_current_page() -> TestResult {
+        start_page("report-statuses.md", "Report Statuses");
+        set_page_link(
+            "report-statuses.md",
+            "123",
+            Some(String::from("https://example.com/123")),
+        );
+        SyncOperation::start(String::from("[report-statuses.md] page"), true).end(Status::Updated);
+        SyncOperation::start(String::from("[image.png] attachment"), false).end(Status::Skipped);
+        end_page();
+
+        let entry = page_entry("report-statuses.md").expect("should have an entry");
+        assert_eq!(entry.title, "Report Statuses");
+        assert_eq!(entry.page_id, Some(String::from("123")));
+        assert_eq!(entry.url, Some(String::from("https://example.com/123")));
+        assert_eq!(entry.action, Status::Updated);
+        assert_eq!(entry.operations.len(), 2);
+        assert_eq!(entry.operations[1].description, "[image.png] attachment");
+        Ok(())
+    }
+
+    #[test]
+    fn it_records_warnings_and_errors_against_the_current_page() -> TestResult {
+        start_page("report-problems.md", "Report Problems");
+        print_warning("something odd");
+        print_error("something broke");
+        end_page();
+
+        let entry = page_entry("report-problems.md").expect("should have an entry");
+        assert_eq!(entry.warnings, vec![String::from("something odd")]);
+        assert_eq!(entry.errors, vec![String::from("something broke")]);
+        assert_eq!(entry.action, Status::Error);
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_the_most_significant_action() -> TestResult {
+        start_page("report-created.md", "Report Created");
+        print_status(Status::Created, "page");
+        print_status(Status::Updated, "content");
+        print_status(Status::Skipped, "attachment");
+        end_page();
+
+        let entry = page_entry("report-created.md").expect("should have an entry");
+        assert_eq!(entry.action, Status::Created);
+        Ok(())
+    }
+}
@@ -130,6 +130,8 @@ pub struct Attachment {
     pub page_id: String,
     pub comment: String,
     pub file_id: String, // File ID of the attachment. This is the ID referenced in atlas_doc_format bodies and is distinct from the attachment ID.
+    #[serde(default)]
+    pub download_link: String,
 }
 
 #[derive(Deserialize, Debug)]
@@ -1,7 +1,9 @@
 use serde_json::json;
 
 use crate::{
-    confluence_client::ConfluenceClient, confluence_page::ConfluenceNode, console::print_status,
+    confluence_client::ConfluenceClient,
+    confluence_page::ConfluenceNode,
+    console::{print_planned, print_status},
 };
 
 pub enum RestrictionType<'a> {
@@ -9,6 +11,19 @@ pub enum RestrictionType<'a> {
     OpenSpace,                           // anyone in the space can edit
 }
 
+impl std::fmt::Display for RestrictionType<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestrictionType::SingleEditor(user) => write!(
+                f,
+                "single-editor:{}",
+                user["accountId"].as_str().unwrap_or_default()
+            ),
+            RestrictionType::OpenSpace => f.write_str("open"),
+        }
+    }
+}
+
 fn restriction_body(editor_list: &serde_json::Value) -> serde_json::Value {
     json!({
         "results": [
@@ -87,6 +102,27 @@ pub fn sync_restrictions(
     Ok(())
 }
 
+/// Reports the restriction change sync_restrictions would make, without making it.
+pub fn plan_restrictions(
+    restriction_type: RestrictionType,
+    confluence_client: &ConfluenceClient,
+    existing_node: &ConfluenceNode,
+) -> anyhow::Result<()> {
+    if let RestrictionType::SingleEditor(user) = restriction_type {
+        let existing_restrictions = confluence_client
+            .get_restrictions_by_operation(&existing_node.id)?
+            .error_for_status()?
+            .json::<serde_json::Value>()?;
+        if should_update_restrictions(user, &existing_restrictions)? {
+            print_planned(
+                crate::console::Status::Updated,
+                &format!("permissions of \"{}\"", existing_node.title),
+            );
+        }
+    }
+    Ok(())
+}
+
 fn should_update_restrictions(
     user: &serde_json::Value,
     existing_restrictions: &serde_json::Value,
@@ -42,15 +42,19 @@ impl Default for RetryConfig {
 
 impl RetryConfig {
     pub fn from_env() -> Self {
-        let defaults = RetryConfig::default();
+        RetryConfig::default().with_env()
+    }
+
+    /// Overrides these settings with any set in the environment.
+    pub fn with_env(self) -> Self {
         RetryConfig {
-            max_retries: env_parsed(MAX_RETRIES_ENV).unwrap_or(defaults.max_retries),
+            max_retries: env_parsed(MAX_RETRIES_ENV).unwrap_or(self.max_retries),
             initial_backoff: env_parsed(INITIAL_BACKOFF_MS_ENV)
                 .map(Duration::from_millis)
-                .unwrap_or(defaults.initial_backoff),
+                .unwrap_or(self.initial_backoff),
             max_backoff: env_parsed(MAX_BACKOFF_SECS_ENV)
                 .map(Duration::from_secs)
-                .unwrap_or(defaults.max_backoff),
+                .unwrap_or(self.max_backoff),
         }
     }
 
@@ -0,0 +1,272 @@
+//! The `snippet` builtin, which includes lines from a file in the repository as a code block so
+//! that the docs can't go stale:
+//!
+//! ```markdown
+//! {{ snippet(path="../src/main.rs", lines="10-20") }}
+//! {{ snippet(path="../src/main.rs", region="setup") }}
+//! ```
+//!
+//! Regions are marked with `ANCHOR: name` and `ANCHOR_END: name` comments, as in mdBook.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::code_blocks::CodeBlockInfo;
+
+/// The root of the git repository the space is in, which snippets can't include files from
+/// outside of, or the space directory if it isn't in one.
+pub(crate) fn repository_root(space_dir: &Path) -> PathBuf {
+    let space_dir = fs::canonicalize(space_dir).unwrap_or_else(|_| PathBuf::from(space_dir));
+    space_dir
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .map(PathBuf::from)
+        .unwrap_or(space_dir)
+}
+
+/// Resolves the path relative to the page's directory, as for links.
+fn resolve(page_dir: &Path, root: &Path, path: &str) -> tera::Result<PathBuf> {
+    let resolved = fs::canonicalize(page_dir.join(path))
+        .map_err(|err| tera::Error::msg(format!("Snippet file {}: {}", path, err)))?;
+    if !resolved.starts_with(root) {
+        return Err(tera::Error::msg(format!(
+            "Snippet file {} is outside of the repository",
+            path
+        )));
+    }
+    Ok(resolved)
+}
+
+/// The 1-based, inclusive line range, ie "10-20", "10-" or "10".
+fn select_lines<'a>(lines: &[&'a str], range: &str) -> tera::Result<Vec<&'a str>> {
+    let invalid = || tera::Error::msg(format!("Invalid line range: {}", range));
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let start: usize = start.trim().parse().map_err(|_| invalid())?;
+    let end: usize = match end.trim() {
+        "" => lines.len(),
+        end => end.parse().map_err(|_| invalid())?,
+    };
+    if start == 0 || start > end || end > lines.len() {
+        return Err(tera::Error::msg(format!(
+            "Line range {} is outside of the file's {} lines",
+            range,
+            lines.len()
+        )));
+    }
+    Ok(lines[start - 1..end].to_vec())
+}
+
+fn is_marker(line: &str) -> bool {
+    line.contains("ANCHOR:") || line.contains("ANCHOR_END:")
+}
+
+fn marker_name<'l>(line: &'l str, marker: &str) -> Option<&'l str> {
+    line.split_once(marker)
+        .map(|(_, name)| name.split_whitespace().next().unwrap_or_default())
+}
+
+fn select_region<'a>(lines: &[&'a str], region: &str) -> tera::Result<Vec<&'a str>> {
+    let start = lines
+        .iter()
+        .position(|line| marker_name(line, "ANCHOR:") == Some(region))
+        .ok_or_else(|| tera::Error::msg(format!("No ANCHOR: {} in the file", region)))?;
+    let end = lines[start..]
+        .iter()
+        .position(|line| marker_name(line, "ANCHOR_END:") == Some(region))
+        .map(|end| start + end)
+        .ok_or_else(|| tera::Error::msg(format!("No ANCHOR_END: {} in the file", region)))?;
+    Ok(lines[start + 1..end].to_vec())
+}
+
+/// Removes the indentation common to every line.
+fn dedent(lines: &[&str]) -> String {
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|line| line.get(indent..).unwrap_or_default().trim_end())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+/// A fence that the code can't close early.
+fn fence_for(code: &str) -> String {
+    let mut longest = 0;
+    let mut current = 0;
+    for c in code.chars() {
+        current = if c == '`' { current + 1 } else { 0 };
+        longest = longest.max(current);
+    }
+    "`".repeat(longest.max(2) + 1)
+}
+
+fn string_arg<'a>(
+    args: &'a HashMap<String, tera::Value>,
+    name: &str,
+) -> tera::Result<Option<&'a str>> {
+    match args.get(name) {
+        None => Ok(None),
+        Some(tera::Value::String(s)) => Ok(Some(s)),
+        Some(tera::Value::Number(n)) if name == "lines" => Err(tera::Error::msg(format!(
+            "lines must be a string, ie lines=\"{}\"",
+            n
+        ))),
+        Some(_) => Err(tera::Error::msg(format!("{} must be a string", name))),
+    }
+}
+
+pub(crate) struct Snippets {
+    /// The directory of the page being rendered, which paths are relative to.
+    pub page_dir: PathBuf,
+    pub root: PathBuf,
+    /// The files included, so that the page is synced again when they change.
+    pub dependencies: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl Snippets {
+    fn snippet(&self, args: &HashMap<String, tera::Value>) -> tera::Result<String> {
+        let path = string_arg(args, "path")?.ok_or("Missing 'path'")?;
+        let file = resolve(&self.page_dir, &self.root, path)?;
+        let content = fs::read_to_string(&file)
+            .map_err(|err| tera::Error::msg(format!("Reading snippet file {}: {}", path, err)))?;
+        let lines: Vec<&str> = content.lines().collect();
+
+        let selected = match (string_arg(args, "lines")?, string_arg(args, "region")?) {
+            (Some(_), Some(_)) => Err(tera::Error::msg("Use either lines or region, not both"))?,
+            (Some(range), None) => select_lines(&lines, range)?,
+            (None, Some(region)) => select_region(&lines, region)?,
+            (None, None) => lines,
+        };
+        // markers for other regions aren't part of the snippet
+        let selected: Vec<&str> = selected.into_iter().filter(|l| !is_marker(l)).collect();
+        let code = dedent(&selected);
+
+        let language = match string_arg(args, "lang")? {
+            Some(language) => String::from(language),
+            None => file
+                .extension()
+                .and_then(|ext| CodeBlockInfo::parse(&ext.to_string_lossy()).language)
+                .unwrap_or_default(),
+        };
+
+        self.dependencies.lock().unwrap().push(file);
+        let fence = fence_for(&code);
+        Ok(format!("{}{}\n{}\n{}", fence, language, code, fence))
+    }
+}
+
+impl tera::Function for Snippets {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        Ok(tera::Value::String(self.snippet(args)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    const SOURCE: &str = r#"use std::io;
+
+fn main() {
+    // ANCHOR: greeting
+    let name = "world";
+    println!("Hello {}!", name);
+    // ANCHOR_END: greeting
+}
+"#;
+
+    fn snippets(temp: &assert_fs::TempDir) -> Snippets {
+        Snippets {
+            page_dir: temp.child("docs").to_path_buf(),
+            root: fs::canonicalize(temp.path()).unwrap(),
+            dependencies: Arc::default(),
+        }
+    }
+
+    fn args(args: &[(&str, &str)]) -> HashMap<String, tera::Value> {
+        args.iter()
+            .map(|(k, v)| (String::from(*k), tera::Value::from(*v)))
+            .collect()
+    }
+
+    #[test]
+    fn it_includes_a_region() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("src/main.rs").write_str(SOURCE)?;
+        temp.child("docs/index.md").write_str("# Docs")?;
+        let snippets = snippets(&temp);
+
+        let snippet =
+            snippets.snippet(&args(&[("path", "../src/main.rs"), ("region", "greeting")]))?;
+
+        assert_eq!(
+            snippet,
+            "```rust\nlet name = \"world\";\nprintln!(\"Hello {}!\", name);\n```"
+        );
+        assert_eq!(
+            *snippets.dependencies.lock().unwrap(),
+            vec![fs::canonicalize(temp.child("src/main.rs").path())?]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_includes_a_line_range() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("src/main.rs").write_str(SOURCE)?;
+        temp.child("docs/index.md").write_str("# Docs")?;
+
+        let snippet = snippets(&temp).snippet(&args(&[
+            ("path", "../src/main.rs"),
+            ("lines", "3-"),
+            ("lang", "rs"),
+        ]))?;
+
+        assert_eq!(
+            snippet,
+            "```rs\nfn main() {\n    let name = \"world\";\n    println!(\"Hello {}!\", name);\n}\n```"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_files_outside_of_the_repository() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("repo/docs/index.md").write_str("# Docs")?;
+        temp.child("secret.txt").write_str("secret")?;
+        let snippets = Snippets {
+            page_dir: temp.child("repo/docs").to_path_buf(),
+            root: fs::canonicalize(temp.child("repo").path())?,
+            dependencies: Arc::default(),
+        };
+
+        let err = snippets
+            .snippet(&args(&[("path", "../../secret.txt")]))
+            .unwrap_err();
+
+        assert!(err.to_string().contains("outside of the repository"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_invalid_line_ranges() {
+        let lines = vec!["a", "b"];
+
+        assert!(select_lines(&lines, "2-3").is_err());
+        assert!(select_lines(&lines, "0-1").is_err());
+        assert!(select_lines(&lines, "x").is_err());
+        assert_eq!(select_lines(&lines, "2").unwrap(), vec!["b"]);
+    }
+}
@@ -6,15 +6,15 @@ use serde::{Deserialize, Serialize};
 
 use crate::confluence_client::ConfluenceClient;
 use crate::confluence_paginator::ConfluencePaginator;
-use crate::console::print_status;
 use crate::console::Status::Reordered;
+use crate::console::{print_planned, print_status};
 use crate::link_generator::LinkGenerator;
-use crate::markdown_page::MarkdownPage;
+use crate::markdown_page::PageInfo;
 use crate::responses::Descendant;
 
 use crate::error::Result;
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Sort {
     #[serde(rename = "inc")]
     Incrementing,
@@ -24,9 +24,13 @@ pub enum Sort {
 
 trait MoveContent {
     fn move_content(&mut self, content_id: &str, operation: &str, target: &str) -> Result<()>;
+
+    fn report_move(&self, title: &str) {
+        print_status(Reordered, title);
+    }
 }
 
-impl MoveContent for ConfluenceClient {
+impl MoveContent for &ConfluenceClient {
     fn move_content(&mut self, content_id: &str, operation: &str, target: &str) -> Result<()> {
         self.move_page_relative(content_id, operation, target)?
             .error_for_status()?;
@@ -34,6 +38,19 @@ impl MoveContent for ConfluenceClient {
     }
 }
 
+/// Records the moves a sort would make, for --plan.
+struct PlannedMoves;
+
+impl MoveContent for PlannedMoves {
+    fn move_content(&mut self, _content_id: &str, _operation: &str, _target: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn report_move(&self, title: &str) {
+        print_planned(Reordered, title);
+    }
+}
+
 /// A more complex than it should be sorting algorithm to get around the fact that the Confluence
 /// API only supports relative moves.
 ///
@@ -62,7 +79,7 @@ fn sort_descendants<T: MoveContent>(
 
     if sorted_descendants[0].id != server_state[0].id {
         move_content.move_content(&sorted_descendants[0].id, "before", &server_state[0].id)?;
-        print_status(Reordered, &sorted_descendants[0].title);
+        move_content.report_move(&sorted_descendants[0].title);
         let source_pos = server_state
             .iter()
             .position(|d| d.id == sorted_descendants[0].id)
@@ -84,7 +101,7 @@ fn sort_descendants<T: MoveContent>(
             let page_id = &next_sorted.id;
 
             move_content.move_content(page_id, "after", after_target_id)?;
-            print_status(Reordered, &server_state[i].title);
+            move_content.report_move(&server_state[i].title);
 
             let target_pos = i + 1;
             let source_pos = server_state
@@ -109,29 +126,83 @@ fn sort_descendants<T: MoveContent>(
     Ok(())
 }
 
-pub fn sync_sort(
-    markdown_page: &MarkdownPage,
+fn get_descendants(
+    markdown_page: &PageInfo,
     link_generator: &LinkGenerator,
-    confluence_client: &mut ConfluenceClient,
-) -> Result<()> {
+    confluence_client: &ConfluenceClient,
+) -> Result<Vec<Descendant>> {
     let page_id = link_generator
         .get_file_id(&PathBuf::from(&markdown_page.source))
         .expect("Should all be created");
 
-    if markdown_page.front_matter.sort != Sort::Unsorted {
-        // TODO: should be able to construct this ourselves
-        let response = if markdown_page.is_folder() {
-            confluence_client.get_folder_descendants(page_id)?
-        } else {
-            confluence_client.get_page_descendants(page_id)?
+    // TODO: should be able to construct this ourselves
+    let response = if markdown_page.is_folder() {
+        confluence_client.get_folder_descendants(page_id)?
+    } else {
+        confluence_client.get_page_descendants(page_id)?
+    };
+
+    let mut iter = ConfluencePaginator::<Descendant>::new(confluence_client);
+
+    Ok(iter.start(response)?.filter_map(|d| d.ok()).collect())
+}
+
+/// The children the page will have after a sync: those in Confluence, then the pages that don't
+/// exist yet, which are created after them.
+fn planned_descendants(
+    markdown_page: &PageInfo,
+    children: &[&PageInfo],
+    link_generator: &LinkGenerator,
+    confluence_client: &ConfluenceClient,
+) -> Result<Vec<Descendant>> {
+    let mut all_descendants_data =
+        match link_generator.get_file_id(&PathBuf::from(&markdown_page.source)) {
+            Some(_) => get_descendants(markdown_page, link_generator, confluence_client)?,
+            None => Vec::default(),
         };
+    for child in children {
+        if link_generator
+            .get_file_id(&PathBuf::from(&child.source))
+            .is_none()
+        {
+            all_descendants_data.push(Descendant {
+                // there's no ID yet, and the source can't be mistaken for one
+                id: child.source.clone(),
+                title: child.title.clone(),
+                _type: String::from(if child.is_folder() { "folder" } else { "page" }),
+                parent_id: String::default(),
+            });
+        }
+    }
+    Ok(all_descendants_data)
+}
 
-        let mut iter = ConfluencePaginator::<Descendant>::new(confluence_client);
+/// Reports the reordering sync_sort would do, without moving anything. `children` are the pages
+/// directly under the page, so that the ones that don't exist yet are sorted too.
+pub fn plan_sort(
+    markdown_page: &PageInfo,
+    children: &[&PageInfo],
+    link_generator: &LinkGenerator,
+    confluence_client: &ConfluenceClient,
+) -> Result<()> {
+    if markdown_page.front_matter.sort == Some(Sort::Incrementing) {
+        let all_descendants_data =
+            planned_descendants(markdown_page, children, link_generator, confluence_client)?;
+        sort_descendants(&all_descendants_data, &mut PlannedMoves)?;
+    }
 
-        let all_descendants_data: Vec<Descendant> =
-            iter.start(response)?.filter_map(|d| d.ok()).collect();
+    Ok(())
+}
 
-        sort_descendants(&all_descendants_data, confluence_client)?;
+pub fn sync_sort(
+    markdown_page: &PageInfo,
+    link_generator: &LinkGenerator,
+    confluence_client: &ConfluenceClient,
+) -> Result<()> {
+    if markdown_page.front_matter.sort == Some(Sort::Incrementing) {
+        let all_descendants_data =
+            get_descendants(markdown_page, link_generator, confluence_client)?;
+        sort_descendants(&all_descendants_data, &mut { confluence_client })?;
     }
 
     Ok(())
@@ -150,7 +221,7 @@ mod test {
         test_helpers::register_mark_and_conf_page,
     };
 
-    use super::{sort_descendants, sync_sort, MoveContent};
+    use super::{planned_descendants, sort_descendants, sync_sort, MoveContent};
 
     struct TestServer {
         server: mockito::ServerGuard,
@@ -374,7 +445,10 @@ mod test {
                 .page_from_str("index.md", "---\nsort: inc\n---\n# Sorted Title\nContent")?,
         )?;
 
-        assert_eq!(sorted_markdown_page.front_matter.sort, Sort::Incrementing);
+        assert_eq!(
+            sorted_markdown_page.front_matter.sort,
+            Some(Sort::Incrementing)
+        );
 
         let all_descendants_data = vec![
             Descendant {
@@ -396,16 +470,56 @@ mod test {
 
         let mock = test_server.mock_move_page("2", "before", "3");
 
-        sync_sort(&markdown_page, &link_generator, &mut test_server.client)?;
+        sync_sort(&markdown_page, &link_generator, &test_server.client)?;
         assert!(!mock.matched());
 
-        sync_sort(
-            &sorted_markdown_page,
-            &link_generator,
-            &mut test_server.client,
-        )?;
+        sync_sort(&sorted_markdown_page, &link_generator, &test_server.client)?;
         assert!(mock.matched());
 
         Ok(())
     }
+
+    #[test]
+    fn it_plans_to_sort_pages_that_do_not_exist_yet() -> TestResult {
+        let mut test_server = TestServer::default();
+        let mut link_generator = LinkGenerator::default_test();
+        let markdown_space = MarkdownSpace::default("test", &PathBuf::from("test"));
+        let parent = register_mark_and_conf_page(
+            "1",
+            &mut link_generator,
+            markdown_space.page_from_str("index.md", "---\nsort: inc\n---\n# Home")?,
+        )?;
+        let existing = register_mark_and_conf_page(
+            "2",
+            &mut link_generator,
+            markdown_space.page_from_str("b.md", "# Page B")?,
+        )?;
+        let new = markdown_space.page_from_str("a.md", "# Page A")?;
+        link_generator.register_markdown_page(&new)?;
+        test_server.mock_descendants(
+            "1",
+            &vec![Descendant {
+                id: "2".into(),
+                title: "Page B".into(),
+                _type: "page".into(),
+                parent_id: "1".into(),
+            }],
+        );
+
+        let planned = planned_descendants(
+            &parent,
+            &[&existing, &new],
+            &link_generator,
+            &test_server.client,
+        )?;
+        let ids: Vec<&str> = planned.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["2", "a.md"]);
+
+        // a new page's children are all new
+        let new_parent = markdown_space.page_from_str("c/index.md", "---\nsort: inc\n---\n# C")?;
+        let planned =
+            planned_descendants(&new_parent, &[&new], &link_generator, &test_server.client)?;
+        assert_eq!(planned.len(), 1);
+        Ok(())
+    }
 }
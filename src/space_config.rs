@@ -0,0 +1,217 @@
+//! Space wide settings from `marked-space.yml` in the space directory, so that they can be
+//! committed alongside the markdown rather than repeated on every command line.
+//!
+//! Command line options take precedence over environment variables, which take precedence over
+//! the file.
+
+use std::{collections::HashMap, fs, path::Path, time::Duration};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    alerts::AlertSettings,
+    archive::OrphanPolicy,
+    diagrams::DiagramSettings,
+    error::{ConfluenceError, Result},
+    jira::JiraSettings,
+    math::MathSettings,
+    retry::RetryConfig,
+    sort::Sort,
+};
+
+pub const CONFIG_FILE: &str = "marked-space.yml";
+
+/// Who can edit the pages marked-space syncs.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Restrictions {
+    /// Anyone who can edit the space.
+    #[default]
+    Open,
+    /// Only the user the API token belongs to.
+    SingleEditor,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RetrySettings {
+    pub max_retries: Option<u32>,
+    pub initial_backoff_ms: Option<u64>,
+    pub max_backoff_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SpaceConfig {
+    pub host: Option<String>,
+    /// The key of the Confluence space, instead of the name of the directory.
+    pub space_key: Option<String>,
+    /// The ID or title of the page to sync under, instead of the space homepage.
+    pub parent: Option<String>,
+    /// Labels added to every page, on top of those in its front matter.
+    pub labels: Vec<String>,
+    /// How pages that don't say in their front matter sort their children.
+    pub sort: Option<Sort>,
+    pub restrictions: Option<Restrictions>,
+    /// Globs (relative to the space directory) of files and directories that aren't part of the
+    /// space.
+    pub ignore: Vec<String>,
+    pub retry: RetrySettings,
+    pub orphans: Option<OrphanPolicy>,
+    /// Directories (relative to the space directory) of templates that can be imported as if
+    /// they were in _tera.
+    pub templates: Vec<String>,
+    /// Renders mermaid and plantuml code blocks as images, if set.
+    pub diagrams: Option<DiagramSettings>,
+    /// How `$...$` and `$$...$$` math is rendered, if not with the default macros.
+    pub math: Option<MathSettings>,
+    /// Macros or panels for alert types (ie `decision` for `> [!DECISION]`), including GitHub's.
+    pub alerts: HashMap<String, AlertSettings>,
+    /// Links the issues of Jira projects.
+    pub jira: Option<JiraSettings>,
+}
+
+impl SpaceConfig {
+    /// Reads `marked-space.yml` from the space directory, if there is one.
+    pub fn from_directory(dir: &Path) -> Result<Self> {
+        let path = dir.join(CONFIG_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("Reading {}", path.display()))?;
+        Self::from_str(&content).with_context(|| format!("Parsing {}", path.display()))
+    }
+
+    fn from_str(content: &str) -> Result<Self> {
+        let config = match saphyr_serde::de::from_str::<Option<SpaceConfig>>(content) {
+            Ok(config) => config.unwrap_or_default(),
+            Err(err) => {
+                return Err(ConfluenceError::generic_error(format!(
+                    "Failed to parse: {:?}",
+                    err
+                )))
+            }
+        };
+        if let Some(ref jira) = config.jira {
+            jira.validate()?;
+        }
+        Ok(config)
+    }
+
+    /// The retry settings from the file, overridden by any set in the environment.
+    pub fn retry_config(&self) -> RetryConfig {
+        let defaults = RetryConfig::default();
+        RetryConfig {
+            max_retries: self.retry.max_retries.unwrap_or(defaults.max_retries),
+            initial_backoff: self
+                .retry
+                .initial_backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.initial_backoff),
+            max_backoff: self
+                .retry
+                .max_backoff_secs
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.max_backoff),
+        }
+        .with_env()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_defaults_without_a_config_file() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+
+        assert_eq!(
+            SpaceConfig::from_directory(temp.path())?,
+            SpaceConfig::default()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_the_config_file() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child(CONFIG_FILE).write_str(
+            r##"
+host: example.atlassian.net
+space-key: TEAMSTG
+parent: Team Docs
+labels: [docs]
+sort: inc
+restrictions: single-editor
+ignore: ["drafts/**"]
+retry:
+  max-retries: 3
+  max-backoff-secs: 10
+orphans: keep
+templates: [../shared]
+diagrams:
+  mermaid: mmdc -i {input} -o {output} -t dark
+  keep-source: true
+alerts:
+  decision:
+    emoji: thinking
+    color: "#eae6ff"
+jira:
+  projects: [ABC]
+"##,
+        )?;
+
+        let config = SpaceConfig::from_directory(temp.path())?;
+
+        assert_eq!(config.host.as_deref(), Some("example.atlassian.net"));
+        assert_eq!(config.space_key.as_deref(), Some("TEAMSTG"));
+        assert_eq!(config.parent.as_deref(), Some("Team Docs"));
+        assert_eq!(config.labels, vec!["docs"]);
+        assert_eq!(config.sort, Some(Sort::Incrementing));
+        assert_eq!(config.restrictions, Some(Restrictions::SingleEditor));
+        assert_eq!(config.ignore, vec!["drafts/**"]);
+        assert_eq!(config.orphans, Some(OrphanPolicy::Keep));
+        assert_eq!(config.templates, vec!["../shared"]);
+        let diagrams = config.diagrams.as_ref().unwrap();
+        assert_eq!(diagrams.mermaid, "mmdc -i {input} -o {output} -t dark");
+        assert_eq!(diagrams.plantuml, DiagramSettings::default().plantuml);
+        assert!(diagrams.keep_source);
+        assert_eq!(config.alerts["decision"].color.as_deref(), Some("#eae6ff"));
+        assert_eq!(config.jira.as_ref().unwrap().projects, vec!["ABC"]);
+        assert_eq!(config.retry.max_retries, Some(3));
+        assert_eq!(config.retry_config().max_backoff, Duration::from_secs(10));
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_unknown_settings() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child(CONFIG_FILE)
+            .write_str("hots: example.atlassian.net\n")?;
+
+        let err = SpaceConfig::from_directory(temp.path()).unwrap_err();
+
+        assert!(format!("{:#}", err).contains("hots"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_invalid_jira_project_keys() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child(CONFIG_FILE)
+            .write_str("jira:\n  projects: [abc]\n")?;
+
+        let err = SpaceConfig::from_directory(temp.path()).unwrap_err();
+
+        assert!(format!("{:#}", err).contains("'abc' in jira projects"));
+        Ok(())
+    }
+}
@@ -0,0 +1,967 @@
+//! Converts Confluence storage format back into marked-space markdown, for `pull`.
+//!
+//! This is roughly the inverse of ConfluenceStorageRenderer: the elements it produces (code
+//! macros, panels, expands, task lists, tables, links and images) are turned back into the
+//! markdown that would produce them. Anything else Confluence can store is kept as raw storage
+//! format, which passes through marked-space unchanged.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use once_cell::sync::Lazy;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use regex::Regex;
+
+use crate::code_blocks::CodeBlockInfo;
+use crate::error::{ConfluenceError, Result};
+use crate::helpers::relative_path;
+
+#[derive(Debug, Clone)]
+enum XmlNode {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Default)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<XmlNode>,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|child| match child {
+            XmlNode::Element(element) => Some(element),
+            XmlNode::Text(_) => None,
+        })
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.elements().find(|element| element.name == name)
+    }
+
+    /// The value of an `ac:parameter`, for macros.
+    fn parameter(&self, name: &str) -> Option<String> {
+        self.elements()
+            .find(|element| element.name == "ac:parameter" && element.attr("ac:name") == Some(name))
+            .map(|element| element.text())
+    }
+
+    fn text(&self) -> String {
+        let mut result = String::new();
+        for child in self.children.iter() {
+            match child {
+                XmlNode::Element(element) => result.push_str(&element.text()),
+                XmlNode::Text(text) => result.push_str(text),
+            }
+        }
+        result
+    }
+}
+
+/// The named entities Confluence uses on top of the ones XML defines.
+fn resolve_entity(entity: &str) -> Option<&'static str> {
+    match entity {
+        "lt" => Some("<"),
+        "gt" => Some(">"),
+        "amp" => Some("&"),
+        "apos" => Some("'"),
+        "quot" => Some("\""),
+        "nbsp" => Some("\u{a0}"),
+        "ndash" => Some("–"),
+        "mdash" => Some("—"),
+        "lsquo" => Some("‘"),
+        "rsquo" => Some("’"),
+        "ldquo" => Some("“"),
+        "rdquo" => Some("”"),
+        "hellip" => Some("…"),
+        "middot" => Some("·"),
+        "bull" => Some("•"),
+        "laquo" => Some("«"),
+        "raquo" => Some("»"),
+        "times" => Some("×"),
+        "copy" => Some("©"),
+        "reg" => Some("®"),
+        "trade" => Some("™"),
+        "euro" => Some("€"),
+        _ => None,
+    }
+}
+
+fn parse_element(start: &BytesStart) -> Result<Element> {
+    let mut attributes = Vec::new();
+    for attribute in start.attributes() {
+        let attribute = attribute?;
+        let key = String::from_utf8_lossy(attribute.key.as_ref()).to_string();
+        let value = attribute.unescape_value_with(resolve_entity)?.to_string();
+        attributes.push((key, value));
+    }
+    Ok(Element {
+        name: String::from_utf8_lossy(start.name().as_ref()).to_string(),
+        attributes,
+        children: Vec::new(),
+    })
+}
+
+fn push_text(element: &mut Element, text: &str) {
+    if let Some(XmlNode::Text(existing)) = element.children.last_mut() {
+        existing.push_str(text);
+    } else {
+        element.children.push(XmlNode::Text(String::from(text)));
+    }
+}
+
+fn parse_storage(storage: &str) -> Result<Element> {
+    let wrapped = format!("<root>{}</root>", storage);
+    let mut reader = Reader::from_str(&wrapped);
+    let mut stack = vec![Element::default()];
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => stack.push(parse_element(&start)?),
+            Event::End(_) => {
+                let element = stack.pop().unwrap();
+                let parent = stack
+                    .last_mut()
+                    .ok_or(ConfluenceError::generic_error("Unbalanced storage format"))?;
+                parent.children.push(XmlNode::Element(element));
+            }
+            Event::Empty(start) => {
+                let element = parse_element(&start)?;
+                stack
+                    .last_mut()
+                    .unwrap()
+                    .children
+                    .push(XmlNode::Element(element));
+            }
+            Event::Text(text) => {
+                let text = text.unescape_with(resolve_entity)?;
+                push_text(stack.last_mut().unwrap(), &text);
+            }
+            Event::CData(cdata) => {
+                let text = String::from_utf8_lossy(&cdata).to_string();
+                push_text(stack.last_mut().unwrap(), &text);
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+
+    let mut document = stack
+        .pop()
+        .ok_or(ConfluenceError::generic_error("Unbalanced storage format"))?;
+    match document.children.pop() {
+        Some(XmlNode::Element(root)) if stack.is_empty() => Ok(root),
+        _ => Err(ConfluenceError::generic_error("Unbalanced storage format")),
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writes an element back out as storage format, for anything we can't express in markdown.
+fn to_storage(element: &Element) -> String {
+    let mut result = format!("<{}", element.name);
+    for (key, value) in element.attributes.iter() {
+        result.push_str(&format!(" {}=\"{}\"", key, escape_xml(value)));
+    }
+    if element.children.is_empty() {
+        result.push_str(" />");
+        return result;
+    }
+    result.push('>');
+    for child in element.children.iter() {
+        match child {
+            XmlNode::Element(child) => result.push_str(&to_storage(child)),
+            XmlNode::Text(text) if element.name.starts_with("ac:plain-text") => {
+                result.push_str(&format!("<![CDATA[{}]]>", text))
+            }
+            XmlNode::Text(text) => result.push_str(&escape_xml(text)),
+        }
+    }
+    result.push_str(&format!("</{}>", element.name));
+    result
+}
+
+/// Stops tera from treating text that happens to look like a template as one.
+fn escape_tera(text: &str) -> String {
+    static TERA_DELIMITERS: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{[{%#]").unwrap());
+    TERA_DELIMITERS
+        .replace_all(text, |captures: &regex::Captures| {
+            format!("{{{{ '{}' }}}}", &captures[0])
+        })
+        .to_string()
+}
+
+pub(crate) fn escape_markdown(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut result = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        let escape = match c {
+            '\\' | '`' | '*' | '[' | ']' | '<' => true,
+            // snake_case doesn't need escaping, as intraword underscores aren't emphasis
+            '_' => {
+                !(i > 0
+                    && chars[i - 1].is_alphanumeric()
+                    && chars.get(i + 1).is_some_and(|n| n.is_alphanumeric()))
+            }
+            _ => false,
+        };
+        if escape {
+            result.push('\\');
+        }
+        result.push(*c);
+    }
+    escape_tera(&result)
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    static WHITESPACE: Lazy<Regex> = Lazy::new(|| Regex::new(r"[ \t\r\n]+").unwrap());
+    WHITESPACE.replace_all(text, " ").to_string()
+}
+
+/// Wraps inline markdown in a delimiter, keeping surrounding spaces outside of it (`** a**`
+/// isn't strong).
+fn delimit(inner: &str, delimiter: &str) -> String {
+    let trimmed = inner.trim();
+    if trimmed.is_empty() {
+        return String::from(inner);
+    }
+    let leading = if inner.starts_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+    let trailing = if inner.ends_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+    format!("{leading}{delimiter}{trimmed}{delimiter}{trailing}")
+}
+
+fn link_destination(destination: &str) -> String {
+    if destination.contains([' ', '(', ')']) {
+        format!("<{}>", destination)
+    } else {
+        String::from(destination)
+    }
+}
+
+fn fence_for(code: &str) -> &'static str {
+    if code.contains("```") {
+        "~~~~"
+    } else {
+        "```"
+    }
+}
+
+fn indent(text: &str, first: &str, rest: &str) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 {
+                format!("{}{}", first, line)
+            } else if line.is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", rest, line)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn quote(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.is_empty() {
+                String::from(">")
+            } else {
+                format!("> {}", line)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "ul"
+            | "ol"
+            | "table"
+            | "blockquote"
+            | "pre"
+            | "hr"
+            | "div"
+            | "section"
+            | "ac:task-list"
+            | "ac:adf-extension"
+            | "ac:layout"
+            | "ac:layout-section"
+            | "ac:layout-cell"
+    ) || (name == "ac:structured-macro")
+}
+
+/// Where the pages and attachments of the space being pulled ended up, for resolving links.
+pub struct PullLinks<'a> {
+    pub host: &'a str,
+    pub space_key: &'a str,
+    pub title_to_file: &'a HashMap<String, PathBuf>,
+    pub id_to_file: &'a HashMap<String, PathBuf>,
+}
+
+pub struct StorageConverter<'a> {
+    links: &'a PullLinks<'a>,
+    page_path: PathBuf,
+    /// Attachment names on Confluence to the file they were saved as, relative to the page.
+    attachment_files: HashMap<String, String>,
+    /// The attachments the page actually references, which are the only ones worth keeping.
+    pub used_attachments: HashSet<String>,
+    pub warnings: Vec<String>,
+}
+
+impl<'a> StorageConverter<'a> {
+    pub fn new(
+        links: &'a PullLinks<'a>,
+        page_path: &Path,
+        attachment_files: HashMap<String, String>,
+    ) -> Self {
+        Self {
+            links,
+            page_path: PathBuf::from(page_path),
+            attachment_files,
+            used_attachments: HashSet::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn convert(&mut self, storage: &str) -> Result<String> {
+        let root = parse_storage(storage).map_err(|err| {
+            ConfluenceError::generic_error(format!(
+                "Couldn't read storage format for {}: {}",
+                self.page_path.display(),
+                err
+            ))
+        })?;
+        Ok(self.blocks(&root.children, "\n\n"))
+    }
+
+    fn warn(&mut self, warning: String) {
+        self.warnings
+            .push(format!("[{}] {}", self.page_path.display(), warning));
+    }
+
+    pub fn attachment_file(&mut self, name: &str) -> String {
+        self.used_attachments.insert(String::from(name));
+        self.attachment_files
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| String::from(name))
+    }
+
+    fn page_link(&self, file: &Path, anchor: Option<&str>) -> String {
+        let mut destination = relative_path(self.page_path.parent().unwrap_or(Path::new("")), file);
+        if let Some(anchor) = anchor {
+            destination.push('#');
+            destination.push_str(anchor);
+        }
+        link_destination(&destination)
+    }
+
+    fn blocks(&mut self, nodes: &[XmlNode], separator: &str) -> String {
+        let mut blocks = Vec::<String>::new();
+        let mut inline_run = Vec::<XmlNode>::new();
+
+        for node in nodes.iter() {
+            match node {
+                XmlNode::Element(element) if is_block(&element.name) => {
+                    self.flush_inline(&mut inline_run, &mut blocks);
+                    let block = self.block(element);
+                    if !block.trim().is_empty() {
+                        blocks.push(block);
+                    }
+                }
+                _ => inline_run.push(node.clone()),
+            }
+        }
+        self.flush_inline(&mut inline_run, &mut blocks);
+
+        blocks.join(separator)
+    }
+
+    fn flush_inline(&mut self, inline_run: &mut Vec<XmlNode>, blocks: &mut Vec<String>) {
+        let paragraph = self.inline(inline_run, false);
+        if !paragraph.trim().is_empty() {
+            blocks.push(String::from(paragraph.trim()));
+        }
+        inline_run.clear();
+    }
+
+    fn block(&mut self, element: &Element) -> String {
+        match element.name.as_str() {
+            "p" => String::from(self.inline(&element.children, false).trim()),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level: usize = element.name[1..].parse().unwrap();
+                format!(
+                    "{} {}",
+                    "#".repeat(level),
+                    self.inline(&element.children, false).trim()
+                )
+            }
+            "ul" => self.list(element, None),
+            "ol" => {
+                let start = element
+                    .attr("start")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(1);
+                self.list(element, Some(start))
+            }
+            "ac:task-list" => self.task_list(element),
+            "blockquote" => quote(&self.blocks(&element.children, "\n\n")),
+            "pre" => {
+                let code = element.text();
+                let fence = fence_for(&code);
+                format!("{}\n{}\n{}", fence, escape_tera(code.trim_end()), fence)
+            }
+            "hr" => String::from("---"),
+            "table" => self.table(element),
+            "ac:structured-macro" => self.block_macro(element),
+            "ac:adf-extension" => self.adf_extension(element),
+            _ => self.blocks(&element.children, "\n\n"),
+        }
+    }
+
+    fn list(&mut self, element: &Element, start: Option<usize>) -> String {
+        let mut items = Vec::new();
+        for (i, item) in element.elements().filter(|e| e.name == "li").enumerate() {
+            let marker = match start {
+                Some(start) => format!("{}. ", start + i),
+                None => String::from("- "),
+            };
+            let content = self.blocks(&item.children, "\n");
+            items.push(indent(&content, &marker, &" ".repeat(marker.len())));
+        }
+        items.join("\n")
+    }
+
+    fn task_list(&mut self, element: &Element) -> String {
+        let mut items = Vec::new();
+        for task in element.elements().filter(|e| e.name == "ac:task") {
+            let complete = task
+                .child("ac:task-status")
+                .is_some_and(|status| status.text().trim() == "complete");
+            let body = task
+                .child("ac:task-body")
+                .map(|body| self.blocks(&body.children, "\n"))
+                .unwrap_or_default();
+            let marker = if complete { "- [x] " } else { "- [ ] " };
+            items.push(indent(&body, marker, "  "));
+        }
+        items.join("\n")
+    }
+
+    fn table(&mut self, element: &Element) -> String {
+        let mut rows = Vec::<Vec<String>>::new();
+        let mut row_elements = Vec::<&Element>::new();
+        for child in element.elements() {
+            match child.name.as_str() {
+                "tr" => row_elements.push(child),
+                "thead" | "tbody" | "tfoot" => {
+                    row_elements.extend(child.elements().filter(|e| e.name == "tr"))
+                }
+                _ => (),
+            }
+        }
+        for row in row_elements {
+            let cells = row
+                .elements()
+                .filter(|e| e.name == "th" || e.name == "td")
+                .map(|cell| {
+                    self.inline(&cell.children, true)
+                        .trim()
+                        .trim_end_matches("<br/>")
+                        .replace('|', "\\|")
+                })
+                .collect();
+            rows.push(cells);
+        }
+        if rows.is_empty() {
+            return String::new();
+        }
+
+        let columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+        let format_row = |row: &Vec<String>| {
+            let mut cells = row.clone();
+            cells.resize(columns, String::new());
+            format!("| {} |", cells.join(" | "))
+        };
+        let mut lines = vec![
+            format_row(&rows[0]),
+            format!("|{}", " --- |".repeat(columns)),
+        ];
+        lines.extend(rows[1..].iter().map(format_row));
+        lines.join("\n")
+    }
+
+    fn rich_text_body(&mut self, element: &Element) -> String {
+        element
+            .child("ac:rich-text-body")
+            .map(|body| self.blocks(&body.children, "\n\n"))
+            .unwrap_or_default()
+    }
+
+    fn alert(&mut self, alert_type: &str, title: Option<String>, body: &Element) -> String {
+        let default_title = alert_type[0..1].to_string() + &alert_type[1..].to_lowercase();
+        let mut children = body.children.clone();
+
+        // marked-space renders the title as a strong paragraph at the start of the panel
+        let mut title = title;
+        let first = children
+            .iter()
+            .position(|c| !matches!(c, XmlNode::Text(t) if t.trim().is_empty()));
+        if let Some(index) = first {
+            if let XmlNode::Element(ref first) = children[index] {
+                let only_strong = first.name == "p"
+                    && first.elements().count() == 1
+                    && first.child("strong").is_some()
+                    && first.text().trim() == first.child("strong").unwrap().text().trim();
+                if title.is_none() && only_strong {
+                    title = Some(String::from(first.text().trim()));
+                    children.remove(index);
+                }
+            }
+        }
+
+        let header = match title {
+            Some(title) if title != default_title => format!("[!{}] {}", alert_type, title),
+            _ => format!("[!{}]", alert_type),
+        };
+        let body = self.blocks(&children, "\n\n");
+        quote(&format!("{}\n{}", header, body))
+    }
+
+    fn block_macro(&mut self, element: &Element) -> String {
+        let name = element.attr("ac:name").unwrap_or_default();
+        match name {
+            "code" => {
+                let info = CodeBlockInfo {
+                    language: element.parameter("language").filter(|l| !l.is_empty()),
+                    title: element.parameter("title"),
+                    line_numbers: element.parameter("linenumbers").as_deref() == Some("true"),
+                    first_line: element
+                        .parameter("firstline")
+                        .and_then(|first_line| first_line.parse().ok()),
+                    theme: element.parameter("theme"),
+                    collapse: element.parameter("collapse").as_deref() == Some("true"),
+                    ..CodeBlockInfo::default()
+                };
+                let code = element
+                    .child("ac:plain-text-body")
+                    .map(|body| body.text())
+                    .unwrap_or_default();
+                let fence = fence_for(&code);
+                format!(
+                    "{}{}\n{}\n{}",
+                    fence,
+                    info,
+                    escape_tera(code.trim_end()),
+                    fence
+                )
+            }
+            "info" | "tip" | "note" | "warning" => {
+                let alert_type = match name {
+                    "info" => "NOTE",
+                    "tip" => "TIP",
+                    "note" => "WARNING",
+                    _ => "CAUTION",
+                };
+                let body = element
+                    .child("ac:rich-text-body")
+                    .cloned()
+                    .unwrap_or_default();
+                self.alert(alert_type, element.parameter("title"), &body)
+            }
+            "expand" => {
+                let title = element.parameter("title").unwrap_or_default();
+                let body = self.rich_text_body(element);
+                quote(format!("[!NOTE] [expand] {}\n{}", escape_markdown(&title), body).trim_end())
+            }
+            "toc" => String::from("{{ toc() }}"),
+            "children" => String::from("{{ children() }}"),
+            _ => self.raw_macro(element),
+        }
+    }
+
+    fn adf_extension(&mut self, element: &Element) -> String {
+        let node = element.child("ac:adf-node");
+        if let Some(node) = node.filter(|n| n.attr("type") == Some("panel")) {
+            let panel_type = node
+                .elements()
+                .find(|e| e.name == "ac:adf-attribute" && e.attr("key") == Some("panel-type"))
+                .map(|e| e.text())
+                .unwrap_or_default();
+            let alert_type = match panel_type.as_str() {
+                "note" => Some("IMPORTANT"),
+                "info" => Some("NOTE"),
+                "success" => Some("TIP"),
+                "warning" => Some("WARNING"),
+                "error" => Some("CAUTION"),
+                _ => None,
+            };
+            if let (Some(alert_type), Some(content)) = (alert_type, node.child("ac:adf-content")) {
+                let content = content.clone();
+                return self.alert(alert_type, None, &content);
+            }
+        }
+        self.raw_macro(element)
+    }
+
+    fn raw_macro(&mut self, element: &Element) -> String {
+        let name = element
+            .attr("ac:name")
+            .or(element.attr("type"))
+            .unwrap_or(&element.name);
+        self.warn(format!(
+            "kept \"{}\" as storage format, as it has no markdown equivalent",
+            name
+        ));
+        to_storage(element)
+    }
+
+    fn inline(&mut self, nodes: &[XmlNode], in_table: bool) -> String {
+        let mut result = String::new();
+        for node in nodes.iter() {
+            match node {
+                XmlNode::Text(text) => {
+                    result.push_str(&escape_markdown(&collapse_whitespace(text)))
+                }
+                XmlNode::Element(element) => {
+                    result.push_str(&self.inline_element(element, in_table))
+                }
+            }
+        }
+        result
+    }
+
+    fn inline_element(&mut self, element: &Element, in_table: bool) -> String {
+        let inner = |converter: &mut Self| converter.inline(&element.children, in_table);
+        match element.name.as_str() {
+            "strong" | "b" => delimit(&inner(self), "**"),
+            "em" | "i" => delimit(&inner(self), "_"),
+            "s" | "del" | "strike" => delimit(&inner(self), "~~"),
+            "code" => {
+                let code = element.text();
+                if code.contains('`') {
+                    format!("`` {} ``", code)
+                } else {
+                    format!("`{}`", code)
+                }
+            }
+            "br" => {
+                if in_table {
+                    String::from("<br/>")
+                } else {
+                    String::from("\\\n")
+                }
+            }
+            "a" => self.anchor(element, in_table),
+            "ac:link" => self.ac_link(element, in_table),
+            "ac:image" => self.image(element),
+            "ac:emoticon" => String::from(element.attr("ac:emoji-fallback").unwrap_or_default()),
+            "time" => String::from(element.attr("datetime").unwrap_or_default()),
+            "sup" | "sub" | "u" => to_storage(element),
+            "ac:structured-macro" if element.attr("ac:name") == Some("view-file") => {
+                self.view_file(element)
+            }
+            "ac:structured-macro" => to_storage(element),
+            "ac:placeholder" => String::new(),
+            name if is_block(name) => {
+                // ie, paragraphs in table cells
+                let separator = if in_table { "<br/>" } else { " " };
+                let text = self.inline(&element.children, in_table);
+                format!("{}{}", text.trim(), separator)
+            }
+            _ => inner(self),
+        }
+    }
+
+    fn anchor(&mut self, element: &Element, in_table: bool) -> String {
+        static PAGE_URL: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"^https?://([^/]+)/wiki/spaces/([^/]+)/pages/(\d+)[^#]*(?:#(.*))?$")
+                .unwrap()
+        });
+        let href = element.attr("href").unwrap_or_default();
+        let text = self.inline(&element.children, in_table);
+
+        if let Some(captures) = PAGE_URL.captures(href) {
+            if &captures[1] == self.links.host && &captures[2] == self.links.space_key {
+                if let Some(file) = self.links.id_to_file.get(&captures[3]) {
+                    let anchor = captures.get(4).map(|m| m.as_str());
+                    return format!("[{}]({})", text.trim(), self.page_link(file, anchor));
+                }
+            }
+        }
+
+        if href.is_empty() {
+            text
+        } else if text == href {
+            format!("<{}>", href)
+        } else {
+            format!("[{}]({})", text.trim(), link_destination(href))
+        }
+    }
+
+    fn ac_link(&mut self, element: &Element, in_table: bool) -> String {
+        let anchor = element.attr("ac:anchor");
+        let body = if let Some(body) = element.child("ac:plain-text-link-body") {
+            Some(escape_markdown(&body.text()))
+        } else {
+            element
+                .child("ac:link-body")
+                .map(|body| self.inline(&body.children, in_table))
+        };
+
+        if let Some(page) = element.child("ri:page") {
+            let title = page.attr("ri:content-title").unwrap_or_default();
+            let other_space = page
+                .attr("ri:space-key")
+                .is_some_and(|key| key != self.links.space_key);
+            if !other_space {
+                if let Some(file) = self.links.title_to_file.get(title) {
+                    let text = body.unwrap_or_else(|| escape_markdown(title));
+                    return format!("[{}]({})", text, self.page_link(file, anchor));
+                }
+                self.warn(format!(
+                    "link to \"{}\" isn't to a page in this space",
+                    title
+                ));
+            }
+            return to_storage(element);
+        }
+
+        if let Some(attachment) = element.child("ri:attachment") {
+            let name = attachment.attr("ri:filename").unwrap_or_default();
+            let file = self.attachment_file(name);
+            let text = body.unwrap_or_else(|| escape_markdown(name));
+            return format!("[{}]({})", text, link_destination(&file));
+        }
+
+        if element.child("ri:user").is_some() {
+            return to_storage(element);
+        }
+
+        if let Some(anchor) = anchor {
+            let text = body.unwrap_or_else(|| escape_markdown(anchor));
+            return format!("[{}](#{})", text, anchor);
+        }
+
+        to_storage(element)
+    }
+
+    fn image(&mut self, element: &Element) -> String {
+        let alt = element.attr("ac:alt").unwrap_or_default();
+        let title = element
+            .attr("ac:title")
+            .map(|title| format!(" \"{}\"", title.replace('"', "\\\"")))
+            .unwrap_or_default();
+        if let Some(attachment) = element.child("ri:attachment") {
+            let name = attachment.attr("ri:filename").unwrap_or_default();
+            let file = self.attachment_file(name);
+            format!("![{}]({}{})", alt, link_destination(&file), title)
+        } else if let Some(url) = element.child("ri:url") {
+            let url = url.attr("ri:value").unwrap_or_default();
+            format!("![{}]({}{})", alt, link_destination(url), title)
+        } else {
+            to_storage(element)
+        }
+    }
+
+    fn view_file(&mut self, element: &Element) -> String {
+        let name = element
+            .elements()
+            .find(|e| e.name == "ac:parameter" && e.attr("ac:name") == Some("name"))
+            .and_then(|p| p.child("ri:attachment"))
+            .and_then(|a| a.attr("ri:filename"));
+        match name {
+            Some(name) => {
+                let file = self.attachment_file(name);
+                format!("[{}]({})", escape_markdown(name), link_destination(&file))
+            }
+            None => to_storage(element),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::TestResult;
+
+    use super::*;
+
+    fn convert(storage: &str) -> Result<String> {
+        let mut title_to_file = HashMap::new();
+        title_to_file.insert(String::from("Other Page"), PathBuf::from("sub/other.md"));
+        let mut id_to_file = HashMap::new();
+        id_to_file.insert(String::from("1234"), PathBuf::from("sub/other.md"));
+        let links = PullLinks {
+            host: "example.atlassian.net",
+            space_key: "TEAM",
+            title_to_file: &title_to_file,
+            id_to_file: &id_to_file,
+        };
+        let mut converter = StorageConverter::new(&links, Path::new("page.md"), HashMap::new());
+        converter.convert(storage)
+    }
+
+    #[test]
+    fn it_converts_paragraphs_and_emphasis() -> TestResult {
+        assert_eq!(
+            convert("<p>Hello <strong>bold</strong> and <em>italic</em>&nbsp;text</p><p>Two</p>")?,
+            "Hello **bold** and _italic_\u{a0}text\n\nTwo"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_converts_headings_and_lists() -> TestResult {
+        assert_eq!(
+            convert("<h2>Heading</h2><ul><li><p>one</p><ul><li>nested</li></ul></li><li>two</li></ul><ol start=\"3\"><li>three</li></ol>")?,
+            "## Heading\n\n- one\n  - nested\n- two\n\n3. three"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_converts_code_macros() -> TestResult {
+        let storage = r#"<ac:structured-macro ac:name="code" ac:schema-version="1"><ac:parameter ac:name="language">rust</ac:parameter><ac:plain-text-body><![CDATA[fn main() {
+    println!("{{ hello }}");
+}]]></ac:plain-text-body></ac:structured-macro>"#;
+        assert_eq!(
+            convert(storage)?,
+            "```rust\nfn main() {\n    println!(\"{{ '{{' }} hello }}\");\n}\n```"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_code_macro_parameters() -> TestResult {
+        let storage = r#"<ac:structured-macro ac:name="code"><ac:parameter ac:name="language">bash</ac:parameter><ac:parameter ac:name="title">Install</ac:parameter><ac:parameter ac:name="linenumbers">true</ac:parameter><ac:plain-text-body><![CDATA[make]]></ac:plain-text-body></ac:structured-macro>"#;
+        assert_eq!(
+            convert(storage)?,
+            "```bash title=Install linenumbers\nmake\n```"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_converts_panels_to_alerts() -> TestResult {
+        let storage = r#"<ac:structured-macro ac:name="info" ac:schema-version="1"><ac:rich-text-body>
+<p><strong>Note</strong></p>
+<p>Some information</p>
+</ac:rich-text-body></ac:structured-macro><ac:structured-macro ac:name="warning"><ac:rich-text-body><p><strong>Danger</strong></p><p>Careful</p></ac:rich-text-body></ac:structured-macro>"#;
+        assert_eq!(
+            convert(storage)?,
+            "> [!NOTE]\n> Some information\n\n> [!CAUTION] Danger\n> Careful"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_strong_paragraphs_after_the_title() -> TestResult {
+        let storage = r#"<ac:structured-macro ac:name="info"><ac:rich-text-body><p><strong>Note</strong></p><p>First</p><p><strong>Note</strong></p><p>Second</p></ac:rich-text-body></ac:structured-macro>"#;
+        assert_eq!(
+            convert(storage)?,
+            "> [!NOTE]\n> First\n>\n> **Note**\n>\n> Second"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_converts_adf_panels_and_expands() -> TestResult {
+        let storage = r#"<ac:adf-extension><ac:adf-node type="panel"><ac:adf-attribute key="panel-type">note</ac:adf-attribute><ac:adf-content><p>Key information</p></ac:adf-content></ac:adf-node></ac:adf-extension><ac:structured-macro ac:name="expand"><ac:parameter ac:name="title">More</ac:parameter><ac:rich-text-body><p>Hidden</p></ac:rich-text-body></ac:structured-macro>"#;
+        assert_eq!(
+            convert(storage)?,
+            "> [!IMPORTANT]\n> Key information\n\n> [!NOTE] [expand] More\n> Hidden"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_converts_task_lists() -> TestResult {
+        let storage = "<ac:task-list><ac:task><ac:task-id>1</ac:task-id><ac:task-status>complete</ac:task-status><ac:task-body>done</ac:task-body></ac:task><ac:task><ac:task-id>2</ac:task-id><ac:task-status>incomplete</ac:task-status><ac:task-body>todo</ac:task-body></ac:task></ac:task-list>";
+        assert_eq!(convert(storage)?, "- [x] done\n- [ ] todo");
+        Ok(())
+    }
+
+    #[test]
+    fn it_converts_tables() -> TestResult {
+        let storage = "<table><tbody><tr><th>A</th><th>B</th></tr><tr><td><p>a|1</p></td><td>b<br/>2</td></tr></tbody></table>";
+        assert_eq!(
+            convert(storage)?,
+            "| A | B |\n| --- | --- |\n| a\\|1 | b<br/>2 |"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_converts_page_links() -> TestResult {
+        let storage = r#"<p><ac:link ac:anchor="Section"><ri:page ri:content-title="Other Page"/><ac:plain-text-link-body><![CDATA[the other page]]></ac:plain-text-link-body></ac:link> and <a href="https://example.atlassian.net/wiki/spaces/TEAM/pages/1234">again</a></p>"#;
+        assert_eq!(
+            convert(storage)?,
+            "[the other page](sub/other.md#Section) and [again](sub/other.md)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_links_to_other_spaces_as_storage() -> TestResult {
+        let storage = r#"<p><ac:link><ri:page ri:space-key="OTHER" ri:content-title="Elsewhere" /></ac:link></p>"#;
+        assert_eq!(
+            convert(storage)?,
+            r#"<ac:link><ri:page ri:space-key="OTHER" ri:content-title="Elsewhere" /></ac:link>"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_converts_images() -> TestResult {
+        let storage = r#"<ac:image ac:align="center" ac:title="A title"><ri:attachment ri:filename="my image.png"/></ac:image><ac:image><ri:url ri:value="https://example.com/logo.png"/></ac:image>"#;
+        assert_eq!(
+            convert(storage)?,
+            "![](<my image.png> \"A title\")![](https://example.com/logo.png)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_escapes_markdown_in_text() -> TestResult {
+        assert_eq!(
+            convert("<p>a *star* and snake_case and [brackets]</p>")?,
+            "a \\*star\\* and snake_case and \\[brackets\\]"
+        );
+        Ok(())
+    }
+}
@@ -9,52 +9,55 @@ use anyhow::Ok;
 use serde_json::json;
 
 use crate::{
+    archive::OrphanPolicy,
     attachments::sync_page_attachments,
     confluence_client::ConfluenceClient,
+    confluence_links::check_confluence_links,
     confluence_page::ConfluenceNode,
     confluence_space::ConfluenceSpace,
     console::{print_error, print_info, print_status, Status},
+    drift::check_drift,
     error::ConfluenceError,
     folders::sync_folder,
+    incremental::{changed_files, retain_changed_pages},
+    jobs::run_jobs,
     link_generator::LinkGenerator,
-    markdown_page::{MarkdownPage, RenderedPage},
+    markdown_page::{PageInfo, RenderedPage},
     markdown_space::MarkdownSpace,
     page_properties::sync_page_properties,
     page_statuses::sync_page_status,
+    parent::get_parent_file,
+    plan::plan_space,
+    report,
     responses::{self, MultiEntityResult},
     restrictions::{sync_restrictions, RestrictionType},
     sort::sync_sort,
     sync_operation::SyncOperation,
+    sync_state::{PageState, SyncState, DEFAULT_STATE_FILE},
     template_renderer::TemplateRenderer,
     Args, Result,
 };
 
-// Returns the ID of the page that the content was synced to.
+// Returns the version of the page once the content is synced.
 fn sync_page_content(
     confluence_client: &ConfluenceClient,
     space: &ConfluenceSpace,
-    rendered_page: RenderedPage,
+    rendered_page: &RenderedPage,
     existing_node: &ConfluenceNode,
-) -> Result<()> {
+) -> Result<i32> {
     let page_data = existing_node.page_data().unwrap();
     let op = SyncOperation::start(
         format!("[{}] \"{}\"", rendered_page.source, rendered_page.title),
         true,
     );
 
-    let parent_id = if rendered_page.is_home_page() {
-        None
-    } else if let Some(parent) = rendered_page.parent.clone() {
-        Some(parent)
-    } else {
-        Some(space.homepage_id.clone())
-    };
+    let parent_id = parent_id(space, rendered_page);
 
     let id = existing_node.id.clone();
     let version_message = rendered_page.version_message();
-    if page_up_to_date(existing_node, &rendered_page, &parent_id, &version_message) {
+    if page_up_to_date(existing_node, rendered_page, &parent_id, &version_message) {
         op.end(Status::Skipped);
-        return Ok(());
+        return Ok(page_data.version.number);
     }
 
     let update_payload = json!({
@@ -79,11 +82,22 @@ fn sync_page_content(
         Err(ConfluenceError::failed_request(resp))
     } else {
         op.end(Status::Updated);
-        Ok(())
+        let updated: responses::PageSingleWithoutBody = resp.json()?;
+        Ok(updated.version.number)
+    }
+}
+
+pub(crate) fn parent_id(space: &ConfluenceSpace, rendered_page: &RenderedPage) -> Option<String> {
+    if rendered_page.is_home_page() {
+        space.homepage_parent_id.clone()
+    } else if let Some(parent) = rendered_page.parent.clone() {
+        Some(parent)
+    } else {
+        Some(space.homepage_id.clone())
     }
 }
 
-fn page_up_to_date(
+pub(crate) fn page_up_to_date(
     existing_node: &ConfluenceNode,
     page: &RenderedPage,
     parent_id: &Option<String>,
@@ -95,17 +109,33 @@ fn page_up_to_date(
 }
 
 pub fn sync_space<'a>(
-    mut confluence_client: ConfluenceClient,
+    confluence_client: ConfluenceClient,
     markdown_space: &'a mut MarkdownSpace<'a>,
     args: Args,
 ) -> Result<()> {
     let space_key = markdown_space.key.clone();
     let space_dir = markdown_space.dir.clone();
 
+    let changed = changed_files(&space_dir, &args)?;
     let mut template_renderer = TemplateRenderer::new(markdown_space, &confluence_client)?;
-    let markdown_pages = markdown_space.parse(&mut template_renderer)?;
+    let mut markdown_pages = markdown_space.parse(&mut template_renderer)?;
 
     let mut space = ConfluenceSpace::get(&confluence_client, &space_key)?;
+    if let Some(ref parent) = args.parent {
+        let home_title = markdown_pages
+            .iter()
+            .find(|page| page.source == "index.md")
+            .map(|page| page.title.clone())
+            .ok_or(ConfluenceError::generic_error(
+                "--parent needs an index.md, to sync to a page under the parent",
+            ))?;
+        space.use_parent_page(
+            &confluence_client,
+            parent,
+            &home_title,
+            !args.plan && !args.check,
+        )?;
+    }
     let mut link_generator =
         LinkGenerator::new(&confluence_client.hostname, &space_key, &space.homepage_id);
 
@@ -117,7 +147,54 @@ pub fn sync_space<'a>(
         print_info("Using single editor restrictions")
     }
 
-    if !args.check {
+    let mode = if args.plan {
+        "plan"
+    } else if args.check {
+        "check"
+    } else {
+        "sync"
+    };
+    report::start_run(&space_key, &confluence_client.hostname, mode);
+
+    // Read before the drift check, as it knows which version of each page was synced last.
+    let mut sync_state = SyncState::load(
+        &args
+            .state_file
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| space_dir.join(DEFAULT_STATE_FILE)),
+    );
+    if args.ignore_state {
+        sync_state.forget_previous();
+    }
+
+    if args.plan {
+        print_info(&format!(
+            "Planning sync of space {} on {}...",
+            space_key, confluence_client.hostname
+        ));
+        space.read_all_pages(&confluence_client)?;
+        space.link_pages(&mut link_generator);
+        retain_changed_pages(&mut markdown_pages, &changed, &space_dir, &link_generator);
+        let drifted = check_drift(
+            &markdown_pages,
+            &space,
+            &link_generator,
+            &confluence_client,
+            &sync_state,
+            &args,
+        )?;
+        plan_space(
+            &markdown_pages,
+            &space,
+            &link_generator,
+            &space_dir,
+            &confluence_client,
+            &args,
+            &drifted,
+        )?;
+        print_info("Plan complete, nothing was changed");
+    } else if !args.check {
         print_info(&format!(
             "Synchronizing space {} on {}...",
             space_key, confluence_client.hostname
@@ -129,24 +206,78 @@ pub fn sync_space<'a>(
 
         space.read_all_pages(&confluence_client)?;
         space.link_pages(&mut link_generator);
-        space.archive_orphans(&link_generator, &space_dir, &confluence_client)?;
+        retain_changed_pages(&mut markdown_pages, &changed, &space_dir, &link_generator);
+        let drifted = check_drift(
+            &markdown_pages,
+            &space,
+            &link_generator,
+            &confluence_client,
+            &sync_state,
+            &args,
+        )?;
+        match args.orphans.unwrap_or_default() {
+            OrphanPolicy::Archive => {
+                space.archive_orphans(&link_generator, &space_dir, &confluence_client)?
+            }
+            OrphanPolicy::Keep => space.keep_orphans(&link_generator, &space_dir),
+        }
         space.restore_archived_pages(&link_generator, &confluence_client)?;
         space.create_initial_nodes(&mut link_generator, &confluence_client)?;
+
+        // Rendering has to happen here, as the parsed markdown can't be shared between threads.
+        let mut pages_to_sync = Vec::new();
         for markdown_page in markdown_pages.iter() {
-            if markdown_page.is_folder() {
-                sync_folder(markdown_page, &link_generator, &space, &confluence_client)?;
+            let rendered = if markdown_page.is_folder() {
+                None
             } else {
-                sync_page(
-                    markdown_page,
-                    &mut link_generator,
-                    &args,
-                    &space,
-                    &confluence_client,
-                    &current_user,
-                )?;
-            }
-            sync_sort(markdown_page, &link_generator, &mut confluence_client)?;
+                let rendered_page = markdown_page.render(&link_generator)?;
+                if let Some(ref d) = args.output {
+                    output_content(d, &rendered_page)?;
+                }
+                Some(rendered_page)
+            };
+            pages_to_sync.push(PageToSync {
+                info: markdown_page.info(),
+                rendered,
+            });
+        }
+
+        if args.jobs > 1 {
+            print_info(&format!("Syncing up to {} pages at a time", args.jobs));
         }
+        let context = SyncContext {
+            link_generator: &link_generator,
+            args: &args,
+            space: &space,
+            confluence_client: &confluence_client,
+            current_user: &current_user,
+            drifted: &drifted,
+            sync_state: &sync_state,
+        };
+        let synced = waves_by_depth(&pages_to_sync)
+            .into_iter()
+            .try_for_each(|wave| {
+                run_jobs(args.jobs, &wave, |page| {
+                    start_page_report(page.info, &link_generator);
+                    let result = match page.rendered {
+                        None => sync_folder(page.info, &link_generator, &space, &confluence_client),
+                        Some(_) => sync_page(page, &context),
+                    };
+                    report::end_page();
+                    result
+                })
+            });
+        // Saved even if the sync failed, so that the pages that did sync aren't synced again.
+        sync_state.save()?;
+        synced?;
+
+        // Sorting is done once everything has been moved into place.
+        run_jobs(args.jobs, &pages_to_sync, |page| {
+            start_page_report(page.info, &link_generator);
+            let result = sync_sort(page.info, &link_generator, &confluence_client);
+            report::end_page();
+            result
+        })?;
     } else {
         print_info(&format!(
             "Checking space {} on {}...",
@@ -154,46 +285,149 @@ pub fn sync_space<'a>(
         ));
         space.read_all_pages(&confluence_client)?;
         space.link_pages(&mut link_generator);
+        retain_changed_pages(&mut markdown_pages, &changed, &space_dir, &link_generator);
         for markdown_page in markdown_pages.iter() {
+            start_page_report(markdown_page, &link_generator);
             let rendered_page = markdown_page.render(&link_generator)?;
             if let Some(ref d) = args.output {
                 output_content(d, &rendered_page)?;
             }
+            report::end_page();
         }
+        let pages: Vec<&PageInfo> = markdown_pages.iter().map(|page| page.info()).collect();
+        check_confluence_links(&pages, &confluence_client)?;
         print_info("Check complete");
     }
 
     Ok(())
 }
 
-fn sync_page(
-    markdown_page: &MarkdownPage,
-    link_generator: &mut LinkGenerator,
-    args: &Args,
-    space: &ConfluenceSpace,
-    confluence_client: &ConfluenceClient,
-    current_user: &tera::Value,
-) -> Result<()> {
-    let rendered_page = markdown_page.render(link_generator)?;
-    if let Some(ref d) = args.output {
-        output_content(d, &rendered_page)?;
+/// Attributes anything reported from here on to the markdown page, until report::end_page.
+pub(crate) fn start_page_report(markdown_page: &PageInfo, link_generator: &LinkGenerator) {
+    let source = PathBuf::from(&markdown_page.source);
+    report::start_page(&markdown_page.source, &markdown_page.title);
+    if let Some(id) = link_generator.get_file_id(&source) {
+        report::set_page_link(
+            &markdown_page.source,
+            &id,
+            link_generator.get_page_url(&source),
+        );
+    }
+}
+
+/// A page that is ready to be synced, ie without anything that can't be shared between the
+/// threads used for --jobs.
+struct PageToSync<'p> {
+    info: &'p PageInfo,
+    /// None for folders, which have no content.
+    rendered: Option<RenderedPage>,
+}
+
+impl PageToSync<'_> {
+    /// How many pages (or folders) are above this one, so that parents can be synced first.
+    fn depth(&self) -> usize {
+        std::iter::successors(
+            get_parent_file(&PathBuf::from(&self.info.source)),
+            |parent| get_parent_file(parent),
+        )
+        .count()
+    }
+}
+
+/// Groups pages so that every page's parent is in an earlier group than the page itself.
+fn waves_by_depth<'a, 'p>(pages: &'a [PageToSync<'p>]) -> Vec<Vec<&'a PageToSync<'p>>> {
+    let mut waves: Vec<Vec<&PageToSync>> = Vec::new();
+    for page in pages {
+        let depth = page.depth();
+        if waves.len() <= depth {
+            waves.resize_with(depth + 1, Vec::new);
+        }
+        waves[depth].push(page);
     }
+    waves
+}
+
+/// Everything needed to sync a page, shared between the threads used for --jobs.
+struct SyncContext<'c> {
+    link_generator: &'c LinkGenerator,
+    args: &'c Args,
+    space: &'c ConfluenceSpace,
+    confluence_client: &'c ConfluenceClient,
+    current_user: &'c tera::Value,
+    drifted: &'c HashSet<String>,
+    sync_state: &'c SyncState,
+}
+
+fn sync_page(page: &PageToSync, context: &SyncContext) -> Result<()> {
+    let SyncContext {
+        link_generator,
+        args,
+        space,
+        confluence_client,
+        current_user,
+        drifted,
+        sync_state,
+    } = *context;
+    let markdown_page = page.info;
+    let rendered_page = page
+        .rendered
+        .as_ref()
+        .expect("error: Only pages are rendered.");
     let page_id = link_generator
         .get_file_id(&PathBuf::from(&rendered_page.source))
         .expect("error: All pages should have been created already.");
     let existing_page = space
         .get_existing_node(&page_id)
         .expect("error: Page should have been created already.");
-    if existing_page.page_data().is_none() {
+    let Some(page_data) = existing_page.page_data() else {
         return Err(anyhow::anyhow!("{} is not a page and cannot be converted (at this time). You'll need to delete it manually before marked-space can create it as a page", existing_page.title));
+    };
+
+    let restrictions_type = if args.single_editor {
+        RestrictionType::SingleEditor(current_user)
+    } else {
+        RestrictionType::OpenSpace
+    };
+    let page_state = PageState::new(
+        markdown_page,
+        rendered_page,
+        parent_id(space, rendered_page),
+        page_data.version.number,
+        &restrictions_type.to_string(),
+    )?;
+    if sync_state.is_unchanged(&existing_page.id, &page_state) {
+        print_status(
+            Status::Skipped,
+            &format!(
+                "[{}] \"{}\" (unchanged since the last sync)",
+                rendered_page.source, rendered_page.title
+            ),
+        );
+        return Ok(());
     }
-    sync_page_content(confluence_client, space, rendered_page, &existing_page)?;
-    sync_page_attachments(
+    sync_state.forget(&existing_page.id);
+
+    let version = if drifted.contains(&existing_page.id) {
+        print_status(
+            Status::Skipped,
+            &format!(
+                "[{}] \"{}\" (edited in Confluence)",
+                rendered_page.source, rendered_page.title
+            ),
+        );
+        None
+    } else {
+        Some(sync_page_content(
+            confluence_client,
+            space,
+            rendered_page,
+            &existing_page,
+        )?)
+    };
+    let attachment_ids = sync_page_attachments(
         confluence_client,
         &existing_page.id,
-        &markdown_page.source,
         &markdown_page.attachments,
-        link_generator,
     )?;
     sync_page_labels(
         confluence_client,
@@ -210,15 +444,23 @@ fn sync_page(
         confluence_client,
         markdown_page,
         &existing_page.id,
-        link_generator,
+        &attachment_ids,
     )?;
-    let restrictions_type = if args.single_editor {
-        RestrictionType::SingleEditor(current_user)
-    } else {
-        RestrictionType::OpenSpace
-    };
     sync_restrictions(restrictions_type, confluence_client, &existing_page)?;
 
+    // A page whose edits in Confluence were kept is left to be checked (and warned about) again.
+    if let Some(version) = version {
+        if !report::page_has_errors(&markdown_page.source) {
+            sync_state.record(
+                &existing_page.id,
+                PageState {
+                    version,
+                    ..page_state
+                },
+            );
+        }
+    }
+
     Ok(())
 }
 
@@ -276,7 +518,7 @@ fn sync_page_labels(
     Ok(())
 }
 
-fn output_content(d: &String, page: &RenderedPage) -> Result<()> {
+pub(crate) fn output_content(d: &String, page: &RenderedPage) -> Result<()> {
     let mut output_path = PathBuf::from(d);
     output_path.push(PathBuf::from(page.source.clone()).with_extension("xhtml"));
     if let Some(p) = output_path.parent() {
@@ -295,6 +537,7 @@ mod tests {
         confluence_page::{ConfluenceNode, ConfluenceNodeType, ConfluencePageData},
         markdown_page::MarkdownPage,
         template_renderer::TemplateRenderer,
+        test_helpers::markdown_page_from_str,
     };
 
     use self::responses::Version;
@@ -490,4 +733,41 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn it_syncs_parents_before_their_children() -> TestResult {
+        let arena = Arena::<AstNode>::new();
+        let pages = [
+            "subpages/child/grandchild.md",
+            "index.md",
+            "subpages/child/index.md",
+            "subpages/index.md",
+            "top.md",
+        ]
+        .map(|source| markdown_page_from_str(source, "# Title", &arena))
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+        let pages_to_sync: Vec<PageToSync> = pages
+            .iter()
+            .map(|page| PageToSync {
+                info: page.info(),
+                rendered: None,
+            })
+            .collect();
+
+        let waves: Vec<Vec<&str>> = waves_by_depth(&pages_to_sync)
+            .iter()
+            .map(|wave| wave.iter().map(|p| p.info.source.as_str()).collect())
+            .collect();
+
+        assert_eq!(
+            waves,
+            vec![
+                vec!["index.md", "subpages/index.md", "top.md"],
+                vec!["subpages/child/index.md"],
+                vec!["subpages/child/grandchild.md"],
+            ]
+        );
+        Ok(())
+    }
 }
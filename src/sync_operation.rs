@@ -1,4 +1,7 @@
-use crate::console::{print_status, Status};
+use crate::{
+    console::{print_status, Status},
+    report::record_status,
+};
 
 pub struct SyncOperation {
     desc: String,
@@ -14,6 +17,8 @@ impl SyncOperation {
     pub fn end(&self, status: Status) {
         if self.verbose || !matches!(status, Status::Skipped) {
             print_status(status, &self.desc);
+        } else {
+            record_status(status, &self.desc, false);
         }
     }
 }
@@ -0,0 +1,255 @@
+//! Remembers what was synced to each page, so that a page whose markdown hasn't changed since the
+//! last sync can be skipped without asking Confluence about its attachments, labels, properties,
+//! status or restrictions.
+//!
+//! The state is only trusted while the page's version in Confluence is the one marked-space left
+//! it at, so a page edited in Confluence is synced again in full.
+
+use std::{
+    collections::BTreeMap,
+    fs::{create_dir_all, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    attachments::attachment_hash,
+    console::print_warning,
+    error::Result,
+    markdown_page::{PageInfo, RenderedPage},
+    page_statuses::PageStatus,
+};
+
+/// Where the state is kept unless --state-file says otherwise, relative to the space directory.
+pub const DEFAULT_STATE_FILE: &str = ".marked-space/state.json";
+
+/// Everything that went into syncing a page.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PageState {
+    pub source: String,
+    /// The version of the page in Confluence once it was synced.
+    pub version: i32,
+    pub title: String,
+    pub parent_id: Option<String>,
+    /// The checksum of the rendered content.
+    pub checksum: String,
+    pub labels: Vec<String>,
+    /// The hash of each attachment, by name.
+    pub attachments: BTreeMap<String, String>,
+    pub properties: serde_json::Value,
+    pub status: Option<PageStatus>,
+    pub restrictions: String,
+}
+
+impl PageState {
+    /// The state the page should be in, given that it's currently at `version` in Confluence.
+    pub fn new(
+        page: &PageInfo,
+        rendered_page: &RenderedPage,
+        parent_id: Option<String>,
+        version: i32,
+        restrictions: &str,
+    ) -> Result<Self> {
+        let mut labels = page.front_matter.labels.clone();
+        labels.sort();
+        let mut attachments = BTreeMap::new();
+        for attachment in page.attachments.iter() {
+            attachments.insert(
+                attachment.link.attachment_name(),
+                attachment_hash(attachment)?,
+            );
+        }
+
+        Ok(Self {
+            source: page.source.replace('\\', "/"),
+            version,
+            title: rendered_page.title.clone(),
+            parent_id,
+            checksum: rendered_page.checksum.clone(),
+            labels,
+            attachments,
+            properties: json!({
+                "emoji": page.front_matter.emoji,
+                "cover": page.front_matter.cover,
+            }),
+            status: page.front_matter.status,
+            restrictions: String::from(restrictions),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct StateFile {
+    /// By page ID.
+    pages: BTreeMap<String, PageState>,
+}
+
+#[derive(Debug)]
+pub struct SyncState {
+    path: PathBuf,
+    previous: BTreeMap<String, PageState>,
+    synced: Mutex<BTreeMap<String, PageState>>,
+}
+
+impl SyncState {
+    /// Reads the state left by the last sync. A missing or unreadable state file just means
+    /// every page is synced in full.
+    pub fn load(path: &Path) -> Self {
+        let previous = match File::open(path) {
+            Ok(file) => match serde_json::from_reader::<_, StateFile>(BufReader::new(file)) {
+                Ok(state_file) => state_file.pages,
+                Err(err) => {
+                    print_warning(&format!(
+                        "Ignoring sync state in {}: {}",
+                        path.display(),
+                        err
+                    ));
+                    BTreeMap::new()
+                }
+            },
+            Err(_) => BTreeMap::new(),
+        };
+
+        Self {
+            path: PathBuf::from(path),
+            synced: Mutex::new(previous.clone()),
+            previous,
+        }
+    }
+
+    /// Forgets the last sync, so every page is synced in full (but the state is still saved).
+    pub fn forget_previous(&mut self) {
+        self.previous.clear();
+        self.synced.lock().unwrap().clear();
+    }
+
+    /// True if the page was last synced with exactly this state, and hasn't changed since.
+    pub fn is_unchanged(&self, page_id: &str, state: &PageState) -> bool {
+        self.previous.get(page_id) == Some(state)
+    }
+
+    /// The version of the page in Confluence once it was last synced, if it's known.
+    pub fn synced_version(&self, page_id: &str) -> Option<i32> {
+        self.previous.get(page_id).map(|state| state.version)
+    }
+
+    /// Forgets the state of a page that is being synced, until it's recorded again.
+    pub fn forget(&self, page_id: &str) {
+        self.synced.lock().unwrap().remove(page_id);
+    }
+
+    /// Records the state of a page that was synced without any errors.
+    pub fn record(&self, page_id: &str, state: PageState) {
+        self.synced
+            .lock()
+            .unwrap()
+            .insert(String::from(page_id), state);
+    }
+
+    /// Writes out the state of every page. Pages that weren't synced this time (ie, with --since)
+    /// keep their previous state, while those that failed are left out so that they are synced
+    /// in full next time.
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            create_dir_all(parent)?;
+        }
+        let state_file = StateFile {
+            pages: self.synced.lock().unwrap().clone(),
+        };
+        serde_json::to_writer_pretty(File::create(&self.path)?, &state_file)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+    use comrak::{nodes::AstNode, Arena};
+
+    use crate::{
+        error::TestResult, link_generator::LinkGenerator, test_helpers::markdown_page_from_str,
+    };
+
+    use super::*;
+
+    fn page_state(content: &str, version: i32) -> Result<PageState> {
+        let arena = Arena::<AstNode>::new();
+        let page = markdown_page_from_str("page.md", content, &arena)?;
+        let rendered_page = page.render(&LinkGenerator::default_test())?;
+        PageState::new(&page, &rendered_page, None, version, "open")
+    }
+
+    #[test]
+    fn it_skips_pages_synced_with_the_same_state() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let path = temp.child(DEFAULT_STATE_FILE);
+        let state = page_state("---\nlabels: [b, a]\n---\n# Page\ncontent", 3)?;
+
+        let sync_state = SyncState::load(path.path());
+        assert!(!sync_state.is_unchanged("42", &state));
+        sync_state.record("42", state.clone());
+        sync_state.save()?;
+
+        let sync_state = SyncState::load(path.path());
+        assert!(sync_state.is_unchanged("42", &state));
+        assert_eq!(state.labels, vec!["a", "b"]);
+        Ok(())
+    }
+
+    #[test]
+    fn it_syncs_pages_whose_inputs_or_version_changed() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let path = temp.child(DEFAULT_STATE_FILE);
+        let sync_state = SyncState::load(path.path());
+        sync_state.record("42", page_state("# Page\ncontent", 3)?);
+        sync_state.save()?;
+
+        let mut sync_state = SyncState::load(path.path());
+        assert!(!sync_state.is_unchanged("42", &page_state("# Page\nchanged", 3)?));
+        assert!(!sync_state.is_unchanged(
+            "42",
+            &page_state("---\nemoji: smile\n---\n# Page\ncontent", 3)?
+        ));
+        assert!(!sync_state.is_unchanged("42", &page_state("# Page\ncontent", 4)?));
+        assert!(sync_state.is_unchanged("42", &page_state("# Page\ncontent", 3)?));
+
+        sync_state.forget_previous();
+        assert!(!sync_state.is_unchanged("42", &page_state("# Page\ncontent", 3)?));
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_the_state_of_pages_not_synced_and_drops_failed_ones() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let path = temp.child(DEFAULT_STATE_FILE);
+        let sync_state = SyncState::load(path.path());
+        sync_state.record("1", page_state("# One", 1)?);
+        sync_state.record("2", page_state("# Two", 1)?);
+        sync_state.save()?;
+
+        let sync_state = SyncState::load(path.path());
+        sync_state.forget("2");
+        sync_state.save()?;
+
+        let sync_state = SyncState::load(path.path());
+        assert!(sync_state.is_unchanged("1", &page_state("# One", 1)?));
+        assert!(!sync_state.is_unchanged("2", &page_state("# Two", 1)?));
+        Ok(())
+    }
+
+    #[test]
+    fn it_ignores_a_corrupt_state_file() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let path = temp.child(DEFAULT_STATE_FILE);
+        path.write_str("not json")?;
+
+        let sync_state = SyncState::load(path.path());
+
+        assert!(!sync_state.is_unchanged("42", &page_state("# Page", 1)?));
+        Ok(())
+    }
+}
@@ -0,0 +1,451 @@
+//! Tables with Confluence's attributes: a wide or full width layout, column widths, numbered rows
+//! and a header column. They're set for a table with a comment on the line before it, or for
+//! every table in the page with `table` in the front matter:
+//!
+//! ```markdown
+//! <!-- table: full-width numbered header-column widths=200,400 -->
+//! | Name | Description |
+//! ```
+//!
+//! In those tables, a cell of just `<` is merged into the cell on its left, and one of just `^`
+//! into the cell above. With `list` the table is written as a list of rows, each a list of cells,
+//! so that cells can have more than one paragraph, lists or code blocks. The first row is the
+//! header.
+
+use comrak::{
+    nodes::{AstNode, NodeValue, TableAlignment},
+    Arena,
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+static TABLE_DIRECTIVE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^<!--\s*table:\s*(.*?)\s*-->$").unwrap());
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TableLayout {
+    #[default]
+    Default,
+    Wide,
+    FullWidth,
+}
+
+impl TableLayout {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TableLayout::Default => "default",
+            TableLayout::Wide => "wide",
+            TableLayout::FullWidth => "full-width",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TableAttributes {
+    pub layout: Option<TableLayout>,
+    /// The widths of the columns, in pixels.
+    pub widths: Vec<u32>,
+    /// Makes the first cell of every row a header.
+    pub header_column: bool,
+    /// Numbers the rows.
+    pub numbered: bool,
+}
+
+impl TableAttributes {
+    /// Applies the attributes from a table comment, ie `wide numbered widths=100,200`, returning
+    /// whether the table is written as a list.
+    fn apply(&mut self, directive: &str) -> Result<bool, String> {
+        let mut list = false;
+        for attribute in directive.split_whitespace() {
+            match attribute {
+                "default" => self.layout = Some(TableLayout::Default),
+                "wide" => self.layout = Some(TableLayout::Wide),
+                "full-width" => self.layout = Some(TableLayout::FullWidth),
+                "header-column" => self.header_column = true,
+                "numbered" => self.numbered = true,
+                "list" => list = true,
+                _ => match attribute.strip_prefix("widths=") {
+                    Some(widths) => {
+                        self.widths = widths
+                            .split(',')
+                            .map(|width| width.trim().parse::<u32>())
+                            .collect::<Result<_, _>>()
+                            .map_err(|_| format!("Invalid table widths '{}'", widths))?
+                    }
+                    None => return Err(format!("Unknown table attribute '{}'", attribute)),
+                },
+            }
+        }
+        Ok(list)
+    }
+
+    fn write_table(&self, output: &mut String) {
+        output.push_str("<table");
+        if let Some(layout) = self.layout {
+            output.push_str(&format!(" data-layout=\"{}\"", layout.as_str()));
+        }
+        if self.numbered {
+            output.push_str(" data-number-column=\"true\"");
+        }
+        output.push_str(">\n");
+        if !self.widths.is_empty() {
+            output.push_str("<colgroup>");
+            for width in self.widths.iter() {
+                output.push_str(&format!("<col style=\"width: {}.0px;\" />", width));
+            }
+            output.push_str("</colgroup>\n");
+        }
+    }
+}
+
+struct Row<'a> {
+    header: bool,
+    cells: Vec<&'a AstNode<'a>>,
+}
+
+/// What a cell is merged into, if it is.
+#[derive(Clone, Copy, PartialEq)]
+enum Merge {
+    Left,
+    Up,
+}
+
+/// The merge marker in the cell, if it's only that: `` `<` `` isn't one.
+fn merge<'a>(cell: &'a AstNode<'a>) -> Option<Merge> {
+    let mut text = String::default();
+    for node in cell.descendants().skip(1) {
+        match node.data.borrow().value {
+            NodeValue::Paragraph => (),
+            NodeValue::Text(ref literal) => text.push_str(literal),
+            _ => return None,
+        }
+    }
+    match text.trim() {
+        "<" => Some(Merge::Left),
+        "^" => Some(Merge::Up),
+        _ => None,
+    }
+}
+
+/// Renders the tables that have a table comment, or all of them if the page sets `table`,
+/// returning any errors in the table comments or merged cells.
+pub(crate) fn render_tables<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    root: &'a AstNode<'a>,
+    page_attributes: Option<&TableAttributes>,
+) -> Vec<String> {
+    let defaults = page_attributes.cloned().unwrap_or_default();
+    let mut errors = Vec::<String>::default();
+    let mut tables = Vec::<(&'a AstNode<'a>, TableAttributes)>::default();
+    let directives: Vec<(&'a AstNode<'a>, String)> = root
+        .descendants()
+        .filter_map(|node| match node.data.borrow().value {
+            NodeValue::HtmlBlock(ref html_block) => TABLE_DIRECTIVE
+                .captures(html_block.literal.trim())
+                .map(|captures| (node, String::from(&captures[1]))),
+            _ => None,
+        })
+        .collect();
+    for (directive, attributes) in directives {
+        let mut table_attributes = defaults.clone();
+        let list = match table_attributes.apply(&attributes) {
+            Ok(list) => list,
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        };
+        let table = directive.next_sibling();
+        let is_table = table.is_some_and(|table| match table.data.borrow().value {
+            NodeValue::List(_) => list,
+            NodeValue::Table(_) => !list,
+            _ => false,
+        });
+        match table {
+            Some(table) if is_table => {
+                directive.detach();
+                tables.push((table, table_attributes));
+            }
+            _ => errors.push(format!(
+                "The table comment '{}' isn't followed by a {}",
+                attributes,
+                if list { "list" } else { "table" }
+            )),
+        }
+    }
+
+    if page_attributes.is_some() {
+        for node in root.descendants() {
+            if matches!(node.data.borrow().value, NodeValue::Table(_))
+                && !tables.iter().any(|(table, _)| table.same_node(node))
+            {
+                tables.push((node, defaults.clone()));
+            }
+        }
+    }
+
+    for (table, attributes) in tables {
+        let (rows, alignments) = match table.data.borrow().value {
+            NodeValue::Table(ref node_table) => (
+                table
+                    .children()
+                    .map(|row| Row {
+                        header: matches!(row.data.borrow().value, NodeValue::TableRow(true)),
+                        cells: row.children().collect(),
+                    })
+                    .collect(),
+                node_table.alignments.clone(),
+            ),
+            _ => match list_rows(table) {
+                Some(rows) => (rows, Vec::default()),
+                None => {
+                    errors.push(String::from(
+                        "Each item of a list table should be a list of its cells",
+                    ));
+                    continue;
+                }
+            },
+        };
+        let merges: Vec<Vec<Option<Merge>>> = rows
+            .iter()
+            .map(|row| row.cells.iter().map(|cell| merge(cell)).collect())
+            .collect();
+        // the columns and rows each cell spans, and whether it's merged into another
+        let mut spans: Vec<Vec<(usize, usize)>> = rows
+            .iter()
+            .map(|row| vec![(1, 1); row.cells.len()])
+            .collect();
+        let mut merged: Vec<Vec<bool>> = rows
+            .iter()
+            .map(|row| vec![false; row.cells.len()])
+            .collect();
+        for (r, row) in rows.iter().enumerate() {
+            for c in 0..row.cells.len() {
+                if merges[r][c].is_some() {
+                    continue;
+                }
+                let colspan = 1 + merges[r][c + 1..]
+                    .iter()
+                    .take_while(|merge| **merge == Some(Merge::Left))
+                    .count();
+                let rowspan = 1 + rows[r + 1..]
+                    .iter()
+                    .zip(merges[r + 1..].iter())
+                    .take_while(|(below, merges)| {
+                        below.header == row.header && merges.get(c) == Some(&Some(Merge::Up))
+                    })
+                    .count();
+                spans[r][c] = (colspan, rowspan);
+                for below in r..r + rowspan {
+                    for right in c..(c + colspan).min(merged[below].len()) {
+                        if (below, right) != (r, c) {
+                            merged[below][right] = merges[below][right].is_some();
+                        }
+                    }
+                }
+            }
+        }
+        for (r, row) in merges.iter().enumerate() {
+            for (c, merge) in row.iter().enumerate() {
+                if merge.is_some() && !merged[r][c] {
+                    errors.push(format!(
+                        "The '{}' in row {}, column {} of a table has no cell to merge into",
+                        if *merge == Some(Merge::Left) {
+                            "<"
+                        } else {
+                            "^"
+                        },
+                        r + 1,
+                        c + 1
+                    ));
+                }
+            }
+        }
+
+        let raw = |html: String| arena.alloc(NodeValue::Raw(html).into());
+        let mut html = String::default();
+        attributes.write_table(&mut html);
+        for (r, row) in rows.iter().enumerate() {
+            if row.header {
+                html.push_str("<thead>\n");
+            } else if r == 0 || rows[r - 1].header {
+                html.push_str("<tbody>\n");
+            }
+            html.push_str("<tr>");
+            for (c, cell) in row.cells.iter().enumerate() {
+                if merged[r][c] {
+                    continue;
+                }
+                let (colspan, rowspan) = spans[r][c];
+                let tag = if row.header || (c == 0 && attributes.header_column) {
+                    "th"
+                } else {
+                    "td"
+                };
+                html.push_str(&format!("<{}", tag));
+                if colspan > 1 {
+                    html.push_str(&format!(" colspan=\"{}\"", colspan));
+                }
+                if rowspan > 1 {
+                    html.push_str(&format!(" rowspan=\"{}\"", rowspan));
+                }
+                match alignments.get(c) {
+                    Some(TableAlignment::Left) => html.push_str(" align=\"left\""),
+                    Some(TableAlignment::Right) => html.push_str(" align=\"right\""),
+                    Some(TableAlignment::Center) => html.push_str(" align=\"center\""),
+                    _ => (),
+                }
+                html.push('>');
+                table.insert_before(raw(std::mem::take(&mut html)));
+                let children: Vec<&'a AstNode<'a>> = cell.children().collect();
+                for child in children {
+                    table.insert_before(child);
+                }
+                html.push_str(&format!("</{}>", tag));
+            }
+            html.push_str("</tr>\n");
+            if row.header {
+                html.push_str("</thead>\n");
+            }
+        }
+        if rows.last().is_some_and(|row| !row.header) {
+            html.push_str("</tbody>\n");
+        }
+        html.push_str("</table>\n");
+        table.insert_before(raw(html));
+        table.detach();
+    }
+    errors
+}
+
+/// The rows of a table written as a list of lists.
+fn list_rows<'a>(list: &'a AstNode<'a>) -> Option<Vec<Row<'a>>> {
+    list.children()
+        .enumerate()
+        .map(|(i, item)| {
+            let cells = item.first_child()?;
+            match cells.data.borrow().value {
+                NodeValue::List(_) if cells.next_sibling().is_none() => Some(Row {
+                    header: i == 0,
+                    cells: cells.children().collect(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::TestResult, test_helpers::test_render, xhtml::check_storage};
+
+    #[test]
+    fn it_renders_table_attributes() -> TestResult {
+        let rendered = test_render(
+            "# Page\n<!-- table: full-width numbered header-column widths=100,300 -->\n\
+            | Name | Description |\n| :--- | ----------- |\n| `a` | The *first* |\n| b | < |\n",
+        )?;
+
+        assert_eq!(
+            rendered.content.trim(),
+            r#"<table data-layout="full-width" data-number-column="true">
+<colgroup><col style="width: 100.0px;" /><col style="width: 300.0px;" /></colgroup>
+<thead>
+<tr><th align="left">Name</th><th>Description</th></tr>
+</thead>
+<tbody>
+<tr><th align="left"><code>a</code></th><td>The <em>first</em></td></tr>
+<tr><th colspan="2" align="left">b</th></tr>
+</tbody>
+</table>"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_merges_cells() -> TestResult {
+        let rendered = test_render(
+            "# Page\n<!-- table: default -->\n| A | B | C |\n| - | - | - |\n\
+            | 1 | 2 | < |\n| ^ | 3 | 4 |\n| ^ | x |   |\n",
+        )?;
+
+        assert!(
+            rendered.content.contains(
+                "<tbody>\n<tr><td rowspan=\"3\">1</td><td colspan=\"2\">2</td></tr>\n\
+                <tr><td>3</td><td>4</td></tr>\n<tr><td>x</td><td></td></tr>\n</tbody>"
+            ),
+            "{}",
+            rendered.content
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_leaves_plain_tables_alone() -> TestResult {
+        let rendered = test_render("# Page\n| A | B |\n| - | - |\n| 1 | < |\n| ^ | `<` |\n")?;
+
+        assert!(
+            rendered.content.contains(
+                "<table>\n<thead>\n<tr>\n<th>A</th>\n<th>B</th>\n</tr>\n</thead>\n<tbody>\n\
+                <tr>\n<td>1</td>\n<td>&lt;</td>\n</tr>\n\
+                <tr>\n<td>^</td>\n<td><code>&lt;</code></td>"
+            ),
+            "{}",
+            rendered.content
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_cells_with_nothing_to_merge_into() {
+        let err = test_render("# Page\n<!-- table: default -->\n| A | B |\n| - | - |\n| < | 1 |\n")
+            .err()
+            .unwrap();
+        assert!(format!("{:#}", err)
+            .contains("The '<' in row 2, column 1 of a table has no cell to merge into"));
+
+        let err =
+            test_render("# Page\n<!-- table: default -->\n| A | B |\n| - | - |\n| ^ | `<` |\n")
+                .err()
+                .unwrap();
+        assert!(format!("{:#}", err)
+            .contains("The '^' in row 2, column 1 of a table has no cell to merge into"));
+    }
+
+    #[test]
+    fn it_renders_list_tables() -> TestResult {
+        let rendered = test_render(
+            "---\ntable:\n  layout: wide\n---\n# Page\n<!-- table: list -->\n\
+            - - Step\n  - Detail\n- - Install\n  - Run:\n\n    ```sh\n    make\n    ```\n\n    \
+            Then check.\n",
+        )?;
+
+        assert!(
+            rendered.content.starts_with(
+                "<table data-layout=\"wide\">\n<thead>\n<tr><th>\n<p>Step</p>\n</th><th>\n\
+                <p>Detail</p>\n</th></tr>\n</thead>\n<tbody>\n<tr><td>\n<p>Install</p>\n</td><td>\n\
+                <p>Run:</p>\n<ac:structured-macro ac:name=\"code\""
+            ),
+            "{}",
+            rendered.content
+        );
+        assert!(check_storage(&rendered.content).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_bad_table_comments() {
+        let err = test_render("# Page\n<!-- table: sideways -->\n| A |\n| - |\n")
+            .err()
+            .unwrap();
+        assert!(format!("{:#}", err).contains("Unknown table attribute 'sideways'"));
+
+        let err = test_render("# Page\n<!-- table: wide -->\nNot a table\n")
+            .err()
+            .unwrap();
+        assert!(format!("{:#}", err).contains("isn't followed by a table"));
+    }
+}
@@ -1,19 +1,32 @@
 use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use anyhow::bail;
 use tera::{self, Tera, Value};
+use walkdir::WalkDir;
 
-use crate::builtins::add_builtins;
+use crate::builtins::{add_builtins, StorageFunction};
 use crate::confluence_client::ConfluenceClient;
 use crate::error::Result;
 use crate::frontmatter::FrontMatter;
 use crate::imports::generate_import_lines;
 use crate::markdown_space::MarkdownSpace;
 use crate::mentions::CachedMentions;
+use crate::snippets::{repository_root, Snippets};
+use crate::xhtml::{escape_markers, mark_storage};
 
 pub struct TemplateRenderer {
     tera: Tera,
     space_key: String,
+    space_dir: PathBuf,
+    repository_root: PathBuf,
+    /// The files included or imported by the page being rendered.
+    dependencies: Arc<Mutex<Vec<PathBuf>>>,
+    /// The file of each template under _tera, which can be in a templates directory outside the
+    /// space.
+    template_paths: HashMap<String, PathBuf>,
 }
 
 fn make_metadata_lookup(metadata: serde_json::Value) -> impl tera::Function {
@@ -36,16 +49,80 @@ fn make_metadata_lookup(metadata: serde_json::Value) -> impl tera::Function {
     )
 }
 
+/// Adds the templates in a directory from the space config, named as if they were in _tera so
+/// that pages can import them the same way.
+fn add_templates_from(tera: &mut Tera, templates_dir: &Path) -> Result<()> {
+    if !templates_dir.is_dir() {
+        bail!(
+            "Template directory {} does not exist",
+            templates_dir.display()
+        );
+    }
+    let mut templates = Vec::new();
+    for entry in WalkDir::new(templates_dir) {
+        let entry = entry?;
+        if entry.path().extension().is_some_and(|ext| ext == "md") {
+            let name = entry
+                .path()
+                .strip_prefix(templates_dir)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            templates.push((entry.into_path(), Some(format!("_tera/{}", name))));
+        }
+    }
+    tera.add_template_files(templates)?;
+    Ok(())
+}
+
+/// Marks the storage markup in the templates under _tera, which is written as it is, unlike the
+/// text of the pages. Returns the file of each template, which Tera forgets once it's marked.
+fn mark_templates(tera: &mut Tera) -> Result<HashMap<String, PathBuf>> {
+    let mut templates = Vec::new();
+    let mut paths = HashMap::new();
+    for name in tera.get_template_names() {
+        if !name.starts_with("_tera/") {
+            continue;
+        }
+        if let Some(ref path) = tera.get_template(name)?.path {
+            templates.push((String::from(name), mark_storage(&fs::read_to_string(path)?)));
+            paths.insert(String::from(name), PathBuf::from(path));
+        }
+    }
+    tera.add_raw_templates(templates)?;
+    Ok(paths)
+}
+
 // Required method
 impl TemplateRenderer {
     pub fn new(space: &MarkdownSpace, client: &ConfluenceClient) -> Result<TemplateRenderer> {
         let space_key = space.key.clone();
         let mut tera = Tera::new(space.dir.join("**/*.md").into_os_string().to_str().unwrap())?;
+        for templates_dir in space.config.templates.iter() {
+            add_templates_from(&mut tera, &space.dir.join(templates_dir))?;
+        }
+        let template_paths = mark_templates(&mut tera)?;
 
         add_builtins(&mut tera)?;
-        tera.register_function("mention", CachedMentions::new(client.clone()));
+        tera.register_function(
+            "mention",
+            StorageFunction(CachedMentions::new(client.clone())),
+        );
+
+        Ok(TemplateRenderer {
+            template_paths,
+            ..TemplateRenderer::with_tera(tera, space_key, &space.dir)
+        })
+    }
 
-        Ok(TemplateRenderer { tera, space_key })
+    fn with_tera(tera: Tera, space_key: String, space_dir: &Path) -> TemplateRenderer {
+        TemplateRenderer {
+            tera,
+            space_key,
+            space_dir: PathBuf::from(space_dir),
+            repository_root: repository_root(space_dir),
+            dependencies: Arc::default(),
+            template_paths: HashMap::default(),
+        }
     }
 
     #[cfg(test)]
@@ -54,7 +131,7 @@ impl TemplateRenderer {
         let space_key = String::from("SPACE");
         add_builtins(&mut tera)?;
 
-        Ok(TemplateRenderer { tera, space_key })
+        Ok(TemplateRenderer::with_tera(tera, space_key, Path::new(".")))
     }
 
     #[cfg(test)]
@@ -65,9 +142,12 @@ impl TemplateRenderer {
         let space_key = String::from("SPACE");
         add_builtins(&mut tera)?;
 
-        tera.register_function("mention", CachedMentions::new(client.clone()));
+        tera.register_function(
+            "mention",
+            StorageFunction(CachedMentions::new(client.clone())),
+        );
 
-        Ok(TemplateRenderer { tera, space_key })
+        Ok(TemplateRenderer::with_tera(tera, space_key, Path::new(".")))
     }
 
     pub fn render_template_str(
@@ -82,32 +162,55 @@ impl TemplateRenderer {
         context.insert("fm", fm);
         self.tera
             .register_function("metadata", make_metadata_lookup(fm.metadata.clone()));
+        self.dependencies.lock().unwrap().clear();
+        self.tera.register_function(
+            "snippet",
+            Snippets {
+                page_dir: self
+                    .space_dir
+                    .join(source)
+                    .parent()
+                    .map(PathBuf::from)
+                    .unwrap_or_default(),
+                root: self.repository_root.clone(),
+                dependencies: self.dependencies.clone(),
+            },
+        );
 
         for import in fm.imports.iter() {
-            if !self
-                .tera
-                .get_template_names()
-                .any(|x| *x == String::from("_tera/") + import)
-            {
+            let name = format!("_tera/{}", import);
+            if !self.tera.get_template_names().any(|x| *x == name) {
                 bail!(
                     "Import '{}' does not exist under the _tera directory",
                     import
                 );
             }
+            if let Some(path) = self.template_paths.get(&name) {
+                self.dependencies
+                    .lock()
+                    .unwrap()
+                    .push(fs::canonicalize(path).unwrap_or_else(|_| path.clone()));
+            }
         }
 
-        let import_text = generate_import_lines(fm) + content;
+        let import_text = generate_import_lines(fm) + &escape_markers(content);
 
         Ok(self.tera.render_str(&import_text, &context)?)
     }
 
+    /// The files included or imported by the page last rendered.
+    pub fn take_dependencies(&mut self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.dependencies.lock().unwrap())
+    }
+
     #[cfg(test)]
     pub fn add_raw_template(
         &mut self,
         template_name: &str,
         macro_str: &str,
     ) -> std::result::Result<(), tera::Error> {
-        self.tera.add_raw_template(template_name, macro_str)
+        self.tera
+            .add_raw_template(template_name, &mark_storage(macro_str))
     }
 }
 
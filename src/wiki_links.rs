@@ -0,0 +1,129 @@
+//! Wiki links, as in Obsidian: `[[Page Title]]`, `[[folder/file|label]]` or
+//! `[[Page Title#Section]]`, which link to a page in the space by its title or by its file.
+
+use std::collections::HashMap;
+
+/// Splits the target of a wiki link from its anchor, if it has one.
+pub(crate) fn split_anchor(url: &str) -> (&str, Option<&str>) {
+    match url.split_once('#') {
+        Some((target, anchor)) if !anchor.is_empty() => (target.trim(), Some(anchor.trim())),
+        Some((target, _)) => (target.trim(), None),
+        None => (url.trim(), None),
+    }
+}
+
+/// The (space relative) file of the page a wiki link is to: the page with that title, or else
+/// that file, with or without `.md`, or else the only file with that name anywhere in the space.
+pub(crate) fn resolve_wiki_link(
+    url: &str,
+    title_to_file: &HashMap<String, String>,
+    file_to_title: &HashMap<String, String>,
+) -> Option<String> {
+    let (target, _) = split_anchor(url);
+    if let Some(file) = title_to_file.get(target) {
+        return Some(file.clone());
+    }
+
+    let target = target.trim_start_matches("./").replace('\\', "/");
+    let filename = if target.ends_with(".md") {
+        target
+    } else {
+        format!("{}.md", target)
+    };
+    if file_to_title.contains_key(&filename) {
+        return Some(filename);
+    }
+
+    let suffix = format!("/{}", filename);
+    let mut matches = file_to_title.keys().filter(|file| file.ends_with(&suffix));
+    match (matches.next(), matches.next()) {
+        (Some(file), None) => Some(file.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::{nodes::AstNode, Arena};
+
+    use crate::{
+        error::TestResult,
+        link_generator::LinkGenerator,
+        test_helpers::{markdown_page_from_str, register_mark_and_conf_page},
+    };
+
+    use super::*;
+
+    fn files() -> (HashMap<String, String>, HashMap<String, String>) {
+        let pages = [
+            ("Home", "index.md"),
+            ("Getting Started", "guides/getting-started.md"),
+            ("Setup", "guides/setup.md"),
+            ("Other Setup", "reference/setup.md"),
+        ];
+        (
+            pages
+                .iter()
+                .map(|(title, file)| (String::from(*title), String::from(*file)))
+                .collect(),
+            pages
+                .iter()
+                .map(|(title, file)| (String::from(*file), String::from(*title)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn it_resolves_by_title_then_by_file() {
+        let (title_to_file, file_to_title) = files();
+        let resolve = |url| resolve_wiki_link(url, &title_to_file, &file_to_title);
+
+        assert_eq!(
+            resolve("Getting Started#Install").as_deref(),
+            Some("guides/getting-started.md")
+        );
+        assert_eq!(resolve("guides/setup").as_deref(), Some("guides/setup.md"));
+        assert_eq!(
+            resolve("getting-started.md").as_deref(),
+            Some("guides/getting-started.md")
+        );
+        // there are two setup.md files, so it's ambiguous
+        assert_eq!(resolve("setup"), None);
+        assert_eq!(resolve("Nowhere"), None);
+    }
+
+    #[test]
+    fn it_renders_wiki_links_to_pages() -> TestResult {
+        let arena = Arena::<AstNode>::new();
+        let mut link_generator = LinkGenerator::default_test();
+        register_mark_and_conf_page(
+            "1",
+            &mut link_generator,
+            markdown_page_from_str("guides/setup.md", "# Setup\n", &arena)?,
+        )?;
+        let page = register_mark_and_conf_page(
+            "2",
+            &mut link_generator,
+            markdown_page_from_str(
+                "index.md",
+                "# Home\nSee [[Setup]], [[guides/setup#Install|installing]] and [[Home]].\n",
+                &arena,
+            )?,
+        )?;
+
+        let rendered = page.render(&link_generator)?;
+
+        assert!(
+            rendered.content.contains(
+                r#"See <a href="https://example.atlassian.net/wiki/spaces/TEST/pages/1">Setup</a>, <a href="https://example.atlassian.net/wiki/spaces/TEST/pages/1#Install">installing</a> and <a href="https://example.atlassian.net/wiki/spaces/TEST/pages/999">Home</a>."#
+            ),
+            "{}",
+            rendered.content
+        );
+        assert_eq!(
+            page.wiki_links,
+            vec!["Setup", "guides/setup#Install", "Home"]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,408 @@
+//! Keeps the storage format valid XHTML, which Confluence rejects the whole page for otherwise.
+//!
+//! Text is always escaped. Confluence's own markup (ie, `<ac:structured-macro>`) isn't HTML to
+//! comrak, so the tags that templates and builtins write are marked as they're rendered, kept out
+//! of the markdown while it's parsed, and put back as raw nodes. HTML is trusted, but the usual
+//! slips - `<br>` and a bare `&` - are fixed. Then the page is checked before it's uploaded.
+
+use std::io::{self, Write};
+
+use comrak::{
+    nodes::{AstNode, NodeValue},
+    Arena,
+};
+use once_cell::sync::Lazy;
+use quick_xml::{events::Event, Reader};
+use regex::{Captures, Regex};
+
+/// Brackets the storage markup from templates, which are private use characters so they can't
+/// mean anything to markdown.
+const STORAGE_START: char = '\u{E000}';
+const STORAGE_END: char = '\u{E001}';
+
+/// The markers in the page's own text, marked as storage that can't be a tag.
+const ESCAPED_START: &str = "\u{E000}&#xE000;\u{E001}";
+const ESCAPED_END: &str = "\u{E000}&#xE001;\u{E001}";
+
+/// A tag of Confluence's, marked or not.
+static STORAGE_TAG: Lazy<Regex> =
+    Lazy::new(|| Regex::new("\u{E000}?(</?(?:ac|ri|at):[^<>]*>)\u{E001}?").unwrap());
+
+static MARKED_STORAGE: Lazy<Regex> =
+    Lazy::new(|| Regex::new("\u{E000}([^\u{E000}\u{E001}]*)\u{E001}").unwrap());
+
+static VOID_ELEMENT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)<(area|base|br|col|embed|hr|img|input|link|meta|param|source|track|wbr)(\s[^<>]*?)?\s*/?>",
+    )
+    .unwrap()
+});
+
+static ENTITY: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^&(#[0-9]+|#[xX][0-9a-fA-F]+|[A-Za-z][A-Za-z0-9]*);").unwrap());
+
+/// Marks Confluence's tags in a template, or the output of a builtin, as storage markup to be
+/// written as it is. Only what comes from templates is marked: the page's own text is escaped.
+pub(crate) fn mark_storage(template: &str) -> String {
+    STORAGE_TAG
+        .replace_all(template, |captures: &Captures| {
+            format!("{}{}{}", STORAGE_START, &captures[1], STORAGE_END)
+        })
+        .into_owned()
+}
+
+/// Marks the characters used as markers in the page's own text, so that they're kept as they are
+/// rather than being taken for storage markup.
+pub(crate) fn escape_markers(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            STORAGE_START => escaped.push_str(ESCAPED_START),
+            STORAGE_END => escaped.push_str(ESCAPED_END),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Takes the marked storage markup out of the rendered markdown, leaving a numbered placeholder
+/// for each tag so that the markdown parser can't change them.
+pub(crate) fn take_storage(markdown: &str) -> (String, Vec<String>) {
+    let mut storage = Vec::default();
+    let markdown = MARKED_STORAGE.replace_all(markdown, |captures: &Captures| {
+        storage.push(match captures.get(0).map(|m| m.as_str()) {
+            Some(ESCAPED_START) => String::from(STORAGE_START),
+            Some(ESCAPED_END) => String::from(STORAGE_END),
+            _ => String::from(&captures[1]),
+        });
+        format!("{}{}{}", STORAGE_START, storage.len() - 1, STORAGE_END)
+    });
+    (markdown.into_owned(), storage)
+}
+
+fn put_back(text: &str, storage: &[String]) -> String {
+    MARKED_STORAGE
+        .replace_all(text, |captures: &Captures| {
+            captures[1]
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| storage.get(i))
+                .cloned()
+                .unwrap_or_default()
+        })
+        .into_owned()
+}
+
+/// Puts the storage markup taken out by take_storage back into the parsed markdown, as raw nodes
+/// in text and as it was in code and HTML.
+pub(crate) fn restore_storage<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    root: &'a AstNode<'a>,
+    storage: &[String],
+) {
+    if storage.is_empty() {
+        return;
+    }
+    let mut texts = Vec::<(&'a AstNode<'a>, String)>::default();
+    for node in root.descendants() {
+        match node.data.borrow_mut().value {
+            NodeValue::Text(ref text) if text.contains(STORAGE_START) => {
+                texts.push((node, text.clone()))
+            }
+            NodeValue::Code(ref mut code) => code.literal = put_back(&code.literal, storage),
+            NodeValue::CodeBlock(ref mut code_block) => {
+                code_block.literal = put_back(&code_block.literal, storage)
+            }
+            NodeValue::HtmlBlock(ref mut html_block) => {
+                html_block.literal = put_back(&html_block.literal, storage)
+            }
+            NodeValue::HtmlInline(ref mut html) => *html = put_back(html, storage),
+            _ => (),
+        }
+    }
+    for (node, text) in texts {
+        let mut last = 0;
+        for placeholder in MARKED_STORAGE.find_iter(&text) {
+            if placeholder.start() > last {
+                node.insert_before(
+                    arena.alloc(
+                        NodeValue::Text(String::from(&text[last..placeholder.start()])).into(),
+                    ),
+                );
+            }
+            node.insert_before(
+                arena.alloc(NodeValue::Raw(put_back(placeholder.as_str(), storage)).into()),
+            );
+            last = placeholder.end();
+        }
+        if last == text.len() {
+            node.detach();
+        } else {
+            node.data.borrow_mut().value = NodeValue::Text(String::from(&text[last..]));
+        }
+    }
+}
+
+/// Whether text is inside one of Confluence's elements from a template, ie a macro's parameter,
+/// going by the tags before it in its paragraph.
+pub(crate) fn inside_storage<'a>(node: &'a AstNode<'a>) -> bool {
+    let mut open = 0;
+    for inline in node
+        .ancestors()
+        .take_while(|ancestor| !ancestor.data.borrow().value.block())
+    {
+        for sibling in inline.preceding_siblings().skip(1) {
+            if let NodeValue::Raw(ref raw) = sibling.data.borrow().value {
+                for captures in STORAGE_TAG.captures_iter(raw) {
+                    let tag = &captures[1];
+                    if tag.starts_with("</") {
+                        open -= 1;
+                    } else if !tag.ends_with("/>") {
+                        open += 1;
+                    }
+                }
+            }
+        }
+    }
+    open > 0
+}
+
+/// Writes text from the markdown, escaped.
+pub(crate) fn escape_text(output: &mut dyn Write, text: &str) -> io::Result<()> {
+    let mut last = 0;
+    for (i, c) in text.char_indices() {
+        let escaped = match c {
+            '&' => "&amp;",
+            '<' => "&lt;",
+            // `]]>` can only end a CDATA section
+            '>' if text[..i].ends_with("]]") => "&gt;",
+            _ => continue,
+        };
+        output.write_all(&text.as_bytes()[last..i])?;
+        output.write_all(escaped.as_bytes())?;
+        last = i + 1;
+    }
+    output.write_all(&text.as_bytes()[last..])
+}
+
+/// Text for a CDATA section, with any `]]>` in it split across two sections.
+pub(crate) fn cdata(text: &str) -> String {
+    text.replace("]]>", "]]]]><![CDATA[>")
+}
+
+/// Closes void elements, ie `<br>` to `<br />`, and escapes ampersands that don't start an entity.
+pub(crate) fn fix_html(html: &str) -> String {
+    let closed = VOID_ELEMENT.replace_all(html, |captures: &regex::Captures| {
+        format!(
+            "<{}{} />",
+            &captures[1],
+            captures.get(2).map_or("", |m| m.as_str())
+        )
+    });
+    let mut fixed = String::with_capacity(closed.len());
+    for (i, c) in closed.char_indices() {
+        if c == '&' && !ENTITY.is_match(&closed[i..]) {
+            fixed.push_str("&amp;");
+        } else {
+            fixed.push(c);
+        }
+    }
+    fixed
+}
+
+/// Where the storage format isn't well formed, and why.
+#[derive(Debug, PartialEq)]
+pub(crate) struct StorageError {
+    pub position: usize,
+    pub message: String,
+}
+
+/// Checks that the storage format is well formed, which is what Confluence needs.
+pub(crate) fn check_storage(storage: &str) -> Result<(), StorageError> {
+    let mut reader = Reader::from_str(storage);
+    let mut open = Vec::<(String, usize)>::default();
+    loop {
+        let position = reader.buffer_position() as usize;
+        match reader.read_event() {
+            Ok(Event::Start(start)) => open.push((
+                String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+                position,
+            )),
+            Ok(Event::End(_)) => {
+                open.pop();
+            }
+            Ok(Event::Text(text)) => {
+                if let Some(i) = text.windows(3).position(|w| w == b"]]>") {
+                    return Err(StorageError {
+                        position: position + i,
+                        message: String::from("]]> is outside of a CDATA section"),
+                    });
+                }
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => (),
+            Err(err) => {
+                return Err(StorageError {
+                    position: reader.error_position() as usize,
+                    message: err.to_string(),
+                })
+            }
+        }
+    }
+    match open.pop() {
+        Some((name, position)) => Err(StorageError {
+            position,
+            message: format!("<{}> is never closed", name),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The line of the markdown that the storage at the position came from, from the nearest
+/// `data-sourcepos` before it.
+pub(crate) fn source_line(storage: &str, position: usize) -> Option<usize> {
+    const SOURCEPOS: &str = "data-sourcepos=\"";
+    let before = &storage[..position.min(storage.len())];
+    let start = before.rfind(SOURCEPOS)? + SOURCEPOS.len();
+    let line = &storage[start..];
+    line[..line.find(':')?].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::TestResult, test_helpers::test_render};
+
+    use super::*;
+
+    #[test]
+    fn it_escapes_text_but_not_storage_markup_from_templates() -> TestResult {
+        let rendered = test_render(
+            "# Page\nFish & chips < 5 quid, use the <ac:emoticon ac:name=\"smile\" /> element and \\<br>\n\n{{ children() }} and `<ac:link>`\n",
+        )?;
+
+        assert_eq!(
+            rendered.content.trim(),
+            "<p>Fish &amp; chips &lt; 5 quid, use the &lt;ac:emoticon ac:name=\"smile\" /> element and &lt;br></p>\n<p><ac:structured-macro ac:name=\"children\" ac:schema-version=\"2\" data-layout=\"default\" ac:macro-id=\"4172775450124db364aa2f7e7faf4cb3\" /> and <code>&lt;ac:link&gt;</code></p>"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_marker_characters_in_the_page() -> TestResult {
+        let rendered =
+            test_render("# Page\n`<ac:x>` \u{E000} and \u{E001}\n\n```\n\u{E000}x\u{E001}\n```\n")?;
+
+        assert!(rendered
+            .content
+            .contains("<p><code>&lt;ac:x&gt;</code> \u{E000} and \u{E001}</p>"));
+        assert!(rendered.content.contains("<![CDATA[\u{E000}x\u{E001}]]>"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_storage_markup_from_templates_as_it_is() -> TestResult {
+        let (markdown, storage) = take_storage(&mark_storage(
+            "<ac:parameter ac:name=\"x_y*\">*a* & b</ac:parameter>",
+        ));
+        assert_eq!(storage.len(), 2);
+
+        let arena = Arena::new();
+        let root = comrak::parse_document(&arena, &markdown, &comrak::Options::default());
+        restore_storage(&arena, root, &storage);
+        let paragraph = root.first_child().unwrap();
+        let values: Vec<NodeValue> = paragraph
+            .children()
+            .map(|node| node.data.borrow().value.clone())
+            .collect();
+
+        assert_eq!(
+            values,
+            vec![
+                NodeValue::Raw(String::from("<ac:parameter ac:name=\"x_y*\">")),
+                NodeValue::Emph,
+                NodeValue::Text(String::from(" & b")),
+                NodeValue::Raw(String::from("</ac:parameter>")),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_fixes_html() -> TestResult {
+        assert_eq!(
+            fix_html("<p>A<br>B<BR/>C<img src=\"a.png\">&nbsp;&#8212;&#x2014; R&D</p>"),
+            "<p>A<br />B<BR />C<img src=\"a.png\" />&nbsp;&#8212;&#x2014; R&amp;D</p>"
+        );
+
+        let rendered = test_render("# Page\n<div>\nTom & Jerry<br>\n</div>\n\nLine<br>break\n")?;
+
+        assert!(
+            check_storage(&rendered.content).is_ok(),
+            "{}",
+            rendered.content
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_finds_badly_formed_storage() {
+        assert!(
+            check_storage("<p>fine <ac:link><ri:page ri:content-title=\"A\"/></ac:link></p>")
+                .is_ok()
+        );
+        assert_eq!(
+            check_storage("<p>one</p>\n<div><p>two</div>")
+                .unwrap_err()
+                .message,
+            "ill-formed document: expected `</p>`, but `</div>` was found"
+        );
+        assert_eq!(
+            check_storage("<p>one</p>\n<div>two"),
+            Err(StorageError {
+                position: 11,
+                message: String::from("<div> is never closed")
+            })
+        );
+        assert_eq!(
+            check_storage("<p>a]]>b</p>"),
+            Err(StorageError {
+                position: 4,
+                message: String::from("]]> is outside of a CDATA section")
+            })
+        );
+        assert!(check_storage("<p><![CDATA[a]]]]><![CDATA[>b]]></p>").is_ok());
+    }
+
+    #[test]
+    fn it_splits_cdata_in_code_and_escapes_it_in_text() -> TestResult {
+        let rendered = test_render(
+            "# Page
+x[y]]>z
+
+```
+<![CDATA[a]]>
+```
+",
+        )?;
+
+        assert!(rendered.content.contains("<p>x[y]]&gt;z</p>"));
+        assert!(rendered
+            .content
+            .contains("<![CDATA[<![CDATA[a]]]]><![CDATA[>]]></ac:plain-text-body>"));
+        assert!(check_storage(&rendered.content).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_the_line_of_bad_html() -> TestResult {
+        let err = test_render("# Page\n\nSome text.\n\n<div>\nnever closed\n")
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            format!("{:#}", err),
+            "page.md:5: Invalid storage format: <div> is never closed"
+        );
+        Ok(())
+    }
+}